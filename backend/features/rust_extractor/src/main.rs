@@ -1,535 +1,6695 @@
-use pcap::Capture;
-use etherparse::PacketHeaders;
-use serde::Serialize;
-use std::collections::{HashSet, HashMap};
+use pcap::{Activated, Capture, Device, Linktype};
+use etherparse::{ether_type, Ethernet2Header, PacketHeaders, ReadError};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs::File;
-use std::net::{Ipv4Addr, Ipv6Addr};
+use std::io::{BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use maxminddb::geoip2;
 
 // --------------------------
 // Helper Functions
 // --------------------------
 
-/// Build packet size distribution histogram
-fn build_packet_size_histogram(packet_sizes: &[usize]) -> HashMap<String, usize> {
-    let mut histogram = HashMap::new();
-    histogram.insert("64".to_string(), 0);
-    histogram.insert("128".to_string(), 0);
-    histogram.insert("256".to_string(), 0);
-    histogram.insert("512".to_string(), 0);
-    histogram.insert("1024".to_string(), 0);
-    histogram.insert("1500".to_string(), 0);
-    
-    for &size in packet_sizes {
-        if size <= 64 {
-            *histogram.get_mut("64").unwrap() += 1;
-        } else if size <= 128 {
-            *histogram.get_mut("128").unwrap() += 1;
-        } else if size <= 256 {
-            *histogram.get_mut("256").unwrap() += 1;
-        } else if size <= 512 {
-            *histogram.get_mut("512").unwrap() += 1;
-        } else if size <= 1024 {
-            *histogram.get_mut("1024").unwrap() += 1;
+/// Fixed-bucket histogram. Serializes as a JSON object like a map would,
+/// but with the buckets in boundary order so output is byte-stable.
+#[derive(Clone)]
+struct Histogram {
+    buckets: Vec<(Cow<'static, str>, usize)>,
+}
+
+impl Histogram {
+    fn new(labels: &[&'static str]) -> Self {
+        Histogram {
+            buckets: labels.iter().map(|&label| (Cow::Borrowed(label), 0)).collect(),
+        }
+    }
+
+    /// A histogram whose bucket labels are built at run time
+    fn with_labels(labels: impl IntoIterator<Item = String>) -> Self {
+        Histogram {
+            buckets: labels.into_iter().map(|label| (Cow::Owned(label), 0)).collect(),
+        }
+    }
+
+    fn add(&mut self, bucket: usize) {
+        self.buckets[bucket].1 += 1;
+    }
+
+    fn counts_mut(&mut self) -> impl Iterator<Item = &mut usize> {
+        self.buckets.iter_mut().map(|(_, count)| count)
+    }
+
+    fn total(&self) -> usize {
+        self.buckets.iter().map(|(_, count)| count).sum()
+    }
+
+    /// Add another histogram's counts; both must use the same buckets
+    fn merge(&mut self, other: &Histogram) {
+        for ((_, count), (_, other_count)) in self.buckets.iter_mut().zip(&other.buckets) {
+            *count += other_count;
+        }
+    }
+}
+
+impl Serialize for Histogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.buckets.len()))?;
+        for (label, count) in &self.buckets {
+            map.serialize_entry(label, count)?;
+        }
+        map.end()
+    }
+}
+
+const PACKET_SIZE_BUCKETS: [&str; 6] = ["64", "128", "256", "512", "1024", "1500"];
+
+/// TCP payload sizes by bucket upper bound, with empty segments (pure ACKs
+/// and the like) in a bucket of their own
+const TCP_PAYLOAD_SIZE_BUCKETS: [&str; 7] = ["0", "64", "128", "256", "512", "1024", "1460"];
+
+/// Bucket of `TCP_PAYLOAD_SIZE_BUCKETS` for a segment carrying `len` payload
+/// bytes; anything over 1024 goes in the last
+fn tcp_payload_size_bucket(len: usize) -> usize {
+    [0, 64, 128, 256, 512, 1024].iter().position(|&upper| len <= upper).unwrap_or(6)
+}
+
+/// Bucket of `PACKET_SIZE_BUCKETS` a frame of `size` bytes falls into
+fn packet_size_bucket(size: usize) -> usize {
+    if size <= 64 {
+        0
+    } else if size <= 128 {
+        1
+    } else if size <= 256 {
+        2
+    } else if size <= 512 {
+        3
+    } else if size <= 1024 {
+        4
+    } else {
+        5
+    }
+}
+
+/// Unit-length vector of the packet-size bucket fractions followed by the
+/// TCP/UDP/ICMP/other ratios, so windows with similar traffic composition
+/// point the same way. All zeros for an empty window.
+fn shape_fingerprint(packet_sizes: &Histogram, protocol_ratios: [f64; 4]) -> Vec<f64> {
+    let total = packet_sizes.total().max(1) as f64;
+    let mut fingerprint: Vec<f64> =
+        packet_sizes.buckets.iter().map(|&(_, count)| count as f64 / total).chain(protocol_ratios).collect();
+    let norm = fingerprint.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        for x in &mut fingerprint {
+            *x /= norm;
+        }
+    }
+    fingerprint
+}
+
+/// Streaming estimate of one quantile with the P² algorithm (Jain &
+/// Chlamtac, 1985): five markers, adjusted with piecewise-parabolic
+/// interpolation, in constant memory. Exact until five samples are seen.
+#[derive(Clone)]
+struct P2Quantile {
+    p: f64,
+    count: usize,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn add(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Cell holding x, stretching the outer markers if needed
+        let k = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
         } else {
-            *histogram.get_mut("1500").unwrap() += 1;
+            (0..4).find(|&i| x < self.heights[i + 1]).unwrap_or(3)
+        };
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += increment;
+        }
+
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let step = d.signum();
+                let (h, n) = (&self.heights, &self.positions);
+                let parabolic = h[i]
+                    + step / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + step) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - step) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                self.heights[i] = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    let j = if step > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + step * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                self.positions[i] += step;
+            }
+        }
+    }
+
+    fn value(&self) -> f64 {
+        if self.count >= 5 {
+            return self.heights[2];
+        }
+        if self.count == 0 {
+            return 0.0;
+        }
+        // Nearest rank over the few samples held so far
+        let mut seen = self.heights[..self.count].to_vec();
+        seen.sort_by(f64::total_cmp);
+        seen[((self.count - 1) as f64 * self.p).round() as usize]
+    }
+}
+
+/// Per-window packet size statistics gathered in one pass, so nothing grows
+/// with the packet count. Central moments use Welford's update, extended to
+/// the third and fourth moment (Terriberry).
+#[derive(Clone)]
+struct SizeStats {
+    count: usize,
+    min: usize,
+    max: usize,
+    mean: f64,
+    m2: f64,
+    m3: f64,
+    m4: f64,
+    small: usize,
+    large: usize,
+    histogram: Histogram,
+    median: P2Quantile,
+    p95: P2Quantile,
+}
+
+impl SizeStats {
+    fn new() -> Self {
+        SizeStats {
+            count: 0,
+            min: usize::MAX,
+            max: 0,
+            mean: 0.0,
+            m2: 0.0,
+            m3: 0.0,
+            m4: 0.0,
+            small: 0,
+            large: 0,
+            histogram: Histogram::new(&PACKET_SIZE_BUCKETS),
+            median: P2Quantile::new(0.5),
+            p95: P2Quantile::new(0.95),
+        }
+    }
+
+    fn add(&mut self, size: usize, small_threshold: usize, large_threshold: usize) {
+        self.count += 1;
+        self.min = self.min.min(size);
+        self.max = self.max.max(size);
+        let (x, n) = (size as f64, self.count as f64);
+        let delta = x - self.mean;
+        let delta_n = delta / n;
+        let term = delta * delta_n * (n - 1.0);
+        self.mean += delta_n;
+        self.m4 += term * delta_n * delta_n * (n * n - 3.0 * n + 3.0) + 6.0 * delta_n * delta_n * self.m2
+            - 4.0 * delta_n * self.m3;
+        self.m3 += term * delta_n * (n - 2.0) - 3.0 * delta_n * self.m2;
+        self.m2 += term;
+        if size < small_threshold {
+            self.small += 1;
+        }
+        if size > large_threshold {
+            self.large += 1;
+        }
+        self.histogram.add(packet_size_bucket(size));
+        self.median.add(x);
+        self.p95.add(x);
+    }
+
+    fn min(&self) -> usize {
+        if self.count > 0 { self.min } else { 0 }
+    }
+
+    /// Population standard deviation
+    fn std(&self) -> f64 {
+        if self.count > 0 { (self.m2 / self.count as f64).sqrt() } else { 0.0 }
+    }
+
+    /// Sarle's bimodality coefficient with the finite-sample correction.
+    /// Values above 5/9 (a uniform distribution) suggest two modes; 0 with
+    /// fewer than 4 packets or a single size.
+    fn bimodality(&self) -> f64 {
+        if self.count < 4 || self.m2 == 0.0 {
+            return 0.0;
+        }
+        let n = self.count as f64;
+        let skewness = n.sqrt() * self.m3 / self.m2.powf(1.5);
+        let excess_kurtosis = n * self.m4 / (self.m2 * self.m2) - 3.0;
+        (skewness * skewness + 1.0) / (excess_kurtosis + 3.0 * (n - 1.0).powi(2) / ((n - 2.0) * (n - 3.0)))
+    }
+}
+
+const MPLS_UNICAST_ETHER_TYPE: u16 = 0x8847;
+const MPLS_MULTICAST_ETHER_TYPE: u16 = 0x8848;
+
+/// Walk an MPLS label stack, returning the labels and the payload that
+/// follows the bottom-of-stack entry. Returns None on a truncated stack.
+fn strip_mpls_labels(data: &[u8]) -> Option<(Vec<u32>, &[u8])> {
+    let mut labels = Vec::new();
+    let mut rest = data;
+    loop {
+        if rest.len() < 4 {
+            return None;
+        }
+        let entry = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        labels.push(entry >> 12);
+        rest = &rest[4..];
+        if entry & 0x100 != 0 {
+            return Some((labels, rest));
+        }
+    }
+}
+
+/// MPLS carries no next-protocol field, so guess it from the IP version nibble
+fn mpls_inner_ether_type(payload: &[u8]) -> Option<u16> {
+    match payload.first().map(|b| b >> 4) {
+        Some(4) => Some(ether_type::IPV4),
+        Some(6) => Some(ether_type::IPV6),
+        _ => None,
+    }
+}
+
+/// Link-layer framing of a capture, from its pcap datalink type
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum LinkLayer {
+    Ethernet,
+    /// Linux cooked capture v1: a 16-byte header with the protocol at offset 14
+    LinuxSll,
+    /// Linux cooked capture v2 (`tcpdump -i any` on recent systems): a
+    /// 20-byte header with the protocol first
+    LinuxSll2,
+}
+
+impl LinkLayer {
+    /// Anything other than a Linux cooked capture is decoded as Ethernet
+    fn from_linktype(link_type: Linktype) -> Self {
+        match link_type {
+            Linktype::LINUX_SLL => LinkLayer::LinuxSll,
+            Linktype::LINUX_SLL2 => LinkLayer::LinuxSll2,
+            _ => LinkLayer::Ethernet,
+        }
+    }
+}
+
+/// Parse a frame's headers for the capture's link layer. A cooked header is
+/// replaced by an Ethernet header carrying its protocol, so VLAN and MPLS
+/// handling after this doesn't depend on the link type.
+fn parse_frame(link: LinkLayer, data: &[u8]) -> Result<PacketHeaders<'_>, ReadError> {
+    let (header_len, protocol_offset) = match link {
+        LinkLayer::Ethernet => return PacketHeaders::from_ethernet_slice(data),
+        LinkLayer::LinuxSll => (16, 14),
+        LinkLayer::LinuxSll2 => (20, 0),
+    };
+    if data.len() < header_len {
+        return Err(ReadError::UnexpectedEndOfSlice(header_len));
+    }
+    let ether_type = u16::from_be_bytes([data[protocol_offset], data[protocol_offset + 1]]);
+    let mut headers = PacketHeaders::from_ether_type(ether_type, &data[header_len..])?;
+    headers.link = Some(Ethernet2Header { source: [0; 6], destination: [0; 6], ether_type });
+    Ok(headers)
+}
+
+/// Decode a frame's headers, looking through MPLS, for the pre-filters
+fn decode_frame(link: LinkLayer, data: &[u8]) -> Option<PacketHeaders<'_>> {
+    let headers = parse_frame(link, data).ok()?;
+    if matches!(
+        headers.payload_ether_type(),
+        Some(MPLS_UNICAST_ETHER_TYPE) | Some(MPLS_MULTICAST_ETHER_TYPE)
+    ) {
+        let (_, inner) = strip_mpls_labels(headers.payload)?;
+        return PacketHeaders::from_ether_type(mpls_inner_ether_type(inner)?, inner).ok();
+    }
+    Some(headers)
+}
+
+/// TCP/UDP source and destination ports
+fn header_ports(headers: &PacketHeaders) -> Option<(u16, u16)> {
+    match headers.transport.as_ref() {
+        Some(etherparse::TransportHeader::Tcp(tcp)) => Some((tcp.source_port, tcp.destination_port)),
+        Some(etherparse::TransportHeader::Udp(udp)) => Some((udp.source_port, udp.destination_port)),
+        Some(_) => None,
+        None => sctp_packet(headers).map(|sctp| (sctp.source_port, sctp.destination_port)),
+    }
+}
+
+/// Upper-layer protocol number, after any IPv6 extension headers
+fn upper_protocol(ip: &etherparse::IpHeader) -> u8 {
+    ip.next_header().unwrap_or(match ip {
+        etherparse::IpHeader::Version4(header, _) => header.protocol,
+        etherparse::IpHeader::Version6(header, _) => header.next_header,
+    })
+}
+
+/// IP protocol number of SCTP, which etherparse leaves undecoded
+const IP_PROTOCOL_SCTP: u8 = 132;
+
+/// SCTP common header (RFC 9260 section 3.1) plus the types of the chunks
+/// that follow it
+struct SctpPacket {
+    source_port: u16,
+    destination_port: u16,
+    chunk_types: Vec<u8>,
+}
+
+/// Parse the SCTP packet carried by an IP packet etherparse found no
+/// transport header in
+fn sctp_packet(headers: &PacketHeaders) -> Option<SctpPacket> {
+    if headers.transport.is_some() || upper_protocol(headers.ip.as_ref()?) != IP_PROTOCOL_SCTP {
+        return None;
+    }
+    parse_sctp(headers.payload)
+}
+
+/// Ports and chunk types of an SCTP packet. Chunk walking stops at the first
+/// malformed length; a packet shorter than the 12-byte common header is not
+/// SCTP.
+fn parse_sctp(payload: &[u8]) -> Option<SctpPacket> {
+    let header = payload.get(..12)?;
+    let mut chunk_types = Vec::new();
+    let mut chunks = &payload[12..];
+    while chunks.len() >= 4 {
+        let length = u16::from_be_bytes([chunks[2], chunks[3]]) as usize;
+        if length < 4 {
+            break;
+        }
+        chunk_types.push(chunks[0]);
+        // Chunks are padded to a multiple of 4 bytes
+        chunks = chunks.get((length + 3) & !3..).unwrap_or(&[]);
+    }
+    Some(SctpPacket {
+        source_port: u16::from_be_bytes([header[0], header[1]]),
+        destination_port: u16::from_be_bytes([header[2], header[3]]),
+        chunk_types,
+    })
+}
+
+/// DHCP message types a client sends to get an address (RFC 2132 section 9.6)
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_REQUEST: u8 = 3;
+
+/// The BOOTP header fields and DHCP options of a UDP payload on ports 67/68
+/// (RFC 2131 section 2)
+struct DhcpMessage {
+    // op 1 (BOOTREQUEST) comes from a client, op 2 from a server or relay
+    from_client: bool,
+    client_mac: String,
+    // Option 53; absent for plain BOOTP
+    message_type: Option<u8>,
+    // Option 50, or ciaddr when a bound client renews
+    requested_ip: Option<Ipv4Addr>,
+}
+
+/// Parse a DHCP message: the fixed 236-byte BOOTP header, the magic cookie,
+/// then options. Option walking stops at the end option or the first option
+/// that runs past the payload; a payload shorter than the header, or with an
+/// unknown op, is not DHCP.
+fn parse_dhcp(payload: &[u8]) -> Option<DhcpMessage> {
+    let header = payload.get(..236)?;
+    if header[0] != 1 && header[0] != 2 {
+        return None;
+    }
+    // chaddr is 16 bytes, of which hlen are the hardware address
+    let hlen = (header[2] as usize).min(16);
+    let client_mac = header[28..28 + hlen].iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":");
+    let ciaddr = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+    let mut message = DhcpMessage {
+        from_client: header[0] == 1,
+        client_mac,
+        message_type: None,
+        requested_ip: (!ciaddr.is_unspecified()).then_some(ciaddr),
+    };
+    if payload.get(236..240) != Some(&[99, 130, 83, 99][..]) {
+        return Some(message);
+    }
+    let mut options = &payload[240..];
+    while let [code, rest @ ..] = options {
+        match *code {
+            // Pad is a single byte, end stops the list
+            0 => {
+                options = rest;
+                continue;
+            }
+            255 => break,
+            _ => {}
+        }
+        let Some((&length, rest)) = rest.split_first() else { break };
+        let Some(value) = rest.get(..length as usize) else { break };
+        match (*code, value) {
+            (53, &[message_type]) => message.message_type = Some(message_type),
+            (50, &[a, b, c, d]) => message.requested_ip = Some(Ipv4Addr::new(a, b, c, d)),
+            _ => {}
+        }
+        options = &rest[length as usize..];
+    }
+    Some(message)
+}
+
+/// IP source and destination addresses
+fn header_addresses(headers: &PacketHeaders) -> Option<(IpAddr, IpAddr)> {
+    match headers.ip.as_ref()? {
+        etherparse::IpHeader::Version4(header, _) => {
+            Some((Ipv4Addr::from(header.source).into(), Ipv4Addr::from(header.destination).into()))
+        }
+        etherparse::IpHeader::Version6(header, _) => {
+            Some((Ipv6Addr::from(header.source).into(), Ipv6Addr::from(header.destination).into()))
+        }
+    }
+}
+
+/// Hops a packet has travelled, assuming it started at the nearest common
+/// initial TTL (64, 128 or 255) at or above the observed TTL or hop limit
+fn estimated_hops(ttl: u8) -> usize {
+    let initial = match ttl {
+        0..=64 => 64,
+        65..=128 => 128,
+        _ => 255,
+    };
+    (initial - ttl) as usize
+}
+
+/// Prefix lengths used to collapse addresses into subnets (`--aggregate-prefix`)
+#[derive(Clone, Copy)]
+struct AggregatePrefix {
+    v4: u8,
+    v6: u8,
+}
+
+fn mask_ipv4(addr: Ipv4Addr, prefix_len: u8) -> Ipv4Addr {
+    let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+    Ipv4Addr::from(u32::from(addr) & mask)
+}
+
+fn mask_ipv6(addr: Ipv6Addr, prefix_len: u8) -> Ipv6Addr {
+    let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+    Ipv6Addr::from(u128::from(addr) & mask)
+}
+
+/// Reduce an address to its subnet when aggregating
+fn mask_ip(addr: IpAddr, aggregate: Option<AggregatePrefix>) -> IpAddr {
+    match (addr, aggregate) {
+        (IpAddr::V4(v4), Some(prefix)) => IpAddr::V4(mask_ipv4(v4, prefix.v4)),
+        (IpAddr::V6(v6), Some(prefix)) => IpAddr::V6(mask_ipv6(v6, prefix.v6)),
+        (addr, None) => addr,
+    }
+}
+
+/// The address flows are keyed and reported under. With `--unmap-ipv4`, an
+/// IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) becomes the IPv4 address it
+/// carries, so it correlates with native IPv4 traffic of the same host.
+fn canonical_ip(addr: IpAddr, unmap_ipv4: bool) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) if unmap_ipv4 => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        _ => addr,
+    }
+}
+
+/// Render a (possibly masked) address for output, in CIDR form when
+/// aggregating. IPv6 uses the compressed RFC 5952 form (`2001:db8::1`).
+fn format_ip(addr: IpAddr, aggregate: Option<AggregatePrefix>) -> String {
+    match (addr, aggregate) {
+        (IpAddr::V4(_), Some(prefix)) => format!("{}/{}", addr, prefix.v4),
+        (IpAddr::V6(_), Some(prefix)) => format!("{}/{}", addr, prefix.v6),
+        (addr, None) => addr.to_string(),
+    }
+}
+
+/// Transport protocol used in flow/port keys. Serializes to the same
+/// upper-case strings the output has always used.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Protocol {
+    Tcp,
+    Udp,
+    Sctp,
+    Icmp,
+    Other,
+}
+
+impl Protocol {
+    fn as_str(self) -> &'static str {
+        match self {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+            Protocol::Sctp => "SCTP",
+            Protocol::Icmp => "ICMP",
+            Protocol::Other => "OTHER",
+        }
+    }
+}
+
+impl Serialize for Protocol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Flow 5-tuple. Addresses stay binary in the hot path and are only turned
+/// into strings when a flow makes it into the output. ICMP flows (with
+/// --icmp-flows) have ports 0 and are told apart by their ICMP type.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    src_ip: IpAddr,
+    src_port: u16,
+    dst_ip: IpAddr,
+    dst_port: u16,
+    protocol: Protocol,
+    icmp_type: Option<u8>,
+}
+
+impl FlowKey {
+    fn reversed(&self) -> FlowKey {
+        FlowKey {
+            src_ip: self.dst_ip,
+            src_port: self.dst_port,
+            dst_ip: self.src_ip,
+            dst_port: self.src_port,
+            protocol: self.protocol,
+            icmp_type: self.icmp_type.map(icmp_reply_type),
+        }
+    }
+}
+
+/// ICMP type of a packet's transport header
+fn icmp_type(transport: &Option<etherparse::TransportHeader>) -> Option<u8> {
+    match transport {
+        Some(etherparse::TransportHeader::Icmpv4(icmp)) => icmp.to_bytes().first().copied(),
+        Some(etherparse::TransportHeader::Icmpv6(icmp)) => Some(icmp.icmp_type.type_u8()),
+        _ => None,
+    }
+}
+
+/// The ICMP type answering `icmp_type`, so echo and timestamp exchanges pair
+/// up as the two directions of one flow. Other types map to themselves.
+fn icmp_reply_type(icmp_type: u8) -> u8 {
+    match icmp_type {
+        // ICMPv4 echo and timestamp
+        8 => 0,
+        0 => 8,
+        13 => 14,
+        14 => 13,
+        // ICMPv6 echo
+        128 => 129,
+        129 => 128,
+        other => other,
+    }
+}
+
+type PortKey = (u16, Protocol);
+
+/// Transport payload length derived from the IP length fields, so Ethernet
+/// padding on short frames isn't mistaken for payload. Clamped to the bytes
+/// actually captured.
+fn transport_payload_len(
+    ip: &etherparse::IpHeader,
+    transport: &etherparse::TransportHeader,
+    captured_payload: usize,
+) -> usize {
+    let ip_payload = match ip {
+        etherparse::IpHeader::Version4(header, ext) => (header.payload_len as usize).saturating_sub(ext.header_len()),
+        etherparse::IpHeader::Version6(header, ext) => (header.payload_length as usize).saturating_sub(ext.header_len()),
+    };
+    ip_payload.saturating_sub(transport.header_len()).min(captured_payload)
+}
+
+/// How a frame is addressed, judged on its destination
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Cast {
+    Unicast,
+    Broadcast,
+    Multicast,
+}
+
+/// Classify a frame by its destination MAC (only known on Ethernet links)
+/// and IP address. Directed subnet broadcasts look like unicast without the
+/// netmask, so only the limited broadcast address counts.
+fn destination_cast(dst_mac: Option<&[u8]>, dst_ip: Option<IpAddr>) -> Cast {
+    let broadcast_ip = dst_ip == Some(IpAddr::V4(Ipv4Addr::BROADCAST));
+    if dst_mac.is_some_and(|mac| mac == [0xff; 6]) || broadcast_ip {
+        Cast::Broadcast
+    } else if dst_mac.is_some_and(|mac| mac[0] & 0x01 != 0) || dst_ip.is_some_and(|ip| ip.is_multicast()) {
+        Cast::Multicast
+    } else {
+        Cast::Unicast
+    }
+}
+
+/// Destination address of an IP header
+fn ip_destination(ip: &etherparse::IpHeader) -> IpAddr {
+    match ip {
+        etherparse::IpHeader::Version4(header, _) => IpAddr::V4(Ipv4Addr::from(header.destination)),
+        etherparse::IpHeader::Version6(header, _) => IpAddr::V6(Ipv6Addr::from(header.destination)),
+    }
+}
+
+/// IP packet length (header plus payload) from the length fields, the size an
+/// MTU applies to
+fn ip_packet_len(ip: &etherparse::IpHeader) -> usize {
+    match ip {
+        etherparse::IpHeader::Version4(header, _) => header.total_len() as usize,
+        etherparse::IpHeader::Version6(header, _) => header.header_len() + header.payload_length as usize,
+    }
+}
+
+#[derive(Clone)]
+struct FlowAgg {
+    packet_count: usize,
+    total_bytes: u64,
+    first_ts: f64,
+    last_ts: f64,
+    // Handshake timestamps used for RTT estimation
+    syn_ts: Option<f64>,
+    syn_ack_ts: Option<f64>,
+    // First data-bearing packet, for time-to-first-byte
+    first_payload_ts: Option<f64>,
+    // Segments with PSH set, a rough count of application messages
+    push_count: usize,
+    // Frame bytes of segments detected as retransmissions (TCP only)
+    retransmitted_bytes: u64,
+    // Largest packet so far, taken as the flow's path MTU, and the packet
+    // counts at each size within MTU_SIZE_SLACK of it (index = bytes below)
+    max_packet_size: usize,
+    near_max_sizes: [usize; MTU_SIZE_SLACK + 1],
+    // IPv4 IDs seen on a UDP flow, with --track-ip-ids
+    ip_ids: HashSet<u16>,
+    // First packet seen for this key was a connection-opening SYN
+    started_with_syn: bool,
+    // Sequence/ACK state for retransmission detection (TCP only)
+    highest_seq_end: Option<u32>,
+    last_ack: Option<u32>,
+    dup_ack_run: usize,
+}
+
+impl FlowAgg {
+    fn new(timestamp: f64) -> Self {
+        FlowAgg {
+            packet_count: 0,
+            total_bytes: 0,
+            first_ts: timestamp,
+            last_ts: timestamp,
+            syn_ts: None,
+            syn_ack_ts: None,
+            first_payload_ts: None,
+            push_count: 0,
+            retransmitted_bytes: 0,
+            max_packet_size: 0,
+            near_max_sizes: [0; MTU_SIZE_SLACK + 1],
+            ip_ids: HashSet::new(),
+            started_with_syn: false,
+            highest_seq_end: None,
+            last_ack: None,
+            dup_ack_run: 0,
+        }
+    }
+
+    fn record_size(&mut self, size: usize) {
+        // A new maximum slides the window; packets that fall out of it were
+        // already too small to count against any later maximum
+        if size > self.max_packet_size {
+            let shift = (size - self.max_packet_size).min(MTU_SIZE_SLACK + 1);
+            self.near_max_sizes.rotate_right(shift);
+            self.near_max_sizes[..shift].fill(0);
+            self.max_packet_size = size;
+        }
+        if let Some(count) = self.near_max_sizes.get_mut(self.max_packet_size - size) {
+            *count += 1;
+        }
+    }
+
+    fn mtu_sized_packet_count(&self) -> usize {
+        self.near_max_sizes.iter().sum()
+    }
+}
+
+/// Packets this many bytes or fewer below a flow's largest packet count as
+/// MTU-sized, allowing for option and padding differences between segments
+const MTU_SIZE_SLACK: usize = 8;
+
+/// Duplicate ACKs from the receiver that trigger a fast retransmit (RFC 5681)
+const FAST_RETRANSMIT_DUP_ACKS: usize = 3;
+
+/// Minimum quiet period before a retransmission is attributed to an RTO
+/// expiry; matches the common 200ms minimum RTO
+const RTO_MIN_SECONDS: f64 = 0.2;
+
+/// True if sequence number `a` is after `b`, accounting for wraparound
+fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[derive(Clone, Default)]
+struct PortAgg {
+    packet_count: usize,
+    total_bytes: u64,
+    // Flows and (aggregated) sources using the port
+    flows: HashSet<FlowKey>,
+    src_ips: HashSet<IpAddr>,
+}
+
+#[derive(Clone, Default, Serialize)]
+struct HostAgg {
+    packet_count: usize,
+    total_bytes: u64,
+}
+
+/// Gaps between packet bursts toward one destination. Packets less than
+/// `BEACON_BURST_GAP` apart belong to the same burst, so a beacon that opens a
+/// handshake each time is timed by its burst starts, not every segment.
+struct BurstIntervals {
+    burst_start: f64,
+    last_ts: f64,
+    // Welford running mean and sum of squared deviations of the gaps
+    count: usize,
+    mean: f64,
+    m2: f64,
+}
+
+impl BurstIntervals {
+    fn new(timestamp: f64) -> Self {
+        BurstIntervals { burst_start: timestamp, last_ts: timestamp, count: 0, mean: 0.0, m2: 0.0 }
+    }
+
+    fn add(&mut self, timestamp: f64) {
+        if timestamp - self.last_ts >= BEACON_BURST_GAP {
+            let gap = timestamp - self.burst_start;
+            self.count += 1;
+            let delta = gap - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (gap - self.mean);
+            self.burst_start = timestamp;
+        }
+        self.last_ts = timestamp;
+    }
+
+    /// Coefficient of variation of the gaps, once there are enough of them
+    fn coefficient_of_variation(&self) -> Option<f64> {
+        if self.count < BEACON_MIN_INTERVALS || self.mean <= 0.0 {
+            return None;
+        }
+        Some((self.m2 / self.count as f64).sqrt() / self.mean)
+    }
+}
+
+/// Traffic per IPv4 /8 and /16 block, keyed by the block in CIDR form
+/// (e.g. "10.0.0.0/8", "10.1.0.0/16"). IPv6 addresses are not bucketed.
+#[derive(Clone, Default, Serialize)]
+struct OctetHeatmap {
+    slash8: BTreeMap<String, HostAgg>,
+    slash16: BTreeMap<String, HostAgg>,
+}
+
+impl OctetHeatmap {
+    fn add(&mut self, addr: IpAddr, bytes: u64) {
+        if let IpAddr::V4(v4) = addr {
+            let [a, b, _, _] = v4.octets();
+            for cell in [
+                self.slash8.entry(format!("{}.0.0.0/8", a)).or_default(),
+                self.slash16.entry(format!("{}.{}.0.0/16", a, b)).or_default(),
+            ] {
+                cell.packet_count += 1;
+                cell.total_bytes += bytes;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.slash8.clear();
+        self.slash16.clear();
+    }
+
+    fn merge(&mut self, other: &OctetHeatmap) {
+        for (mine, theirs) in [(&mut self.slash8, &other.slash8), (&mut self.slash16, &other.slash16)] {
+            for (block, agg) in theirs {
+                let cell = mine.entry(block.clone()).or_default();
+                cell.packet_count += agg.packet_count;
+                cell.total_bytes += agg.total_bytes;
+            }
+        }
+    }
+}
+
+/// One line of `--flows-out`: a flow of window `window_index`, counted from 0
+/// before any `--block-size` merging
+#[derive(Serialize)]
+struct FlowRecord {
+    window_index: usize,
+    window_start: f64,
+    #[serde(flatten)]
+    flow: FlowStat,
+}
+
+#[derive(Serialize, Clone)]
+struct FlowStat {
+    src_ip: String,
+    dst_ip: String,
+    src_port: u16,
+    dst_port: u16,
+    protocol: Protocol,
+    packet_count: usize,
+    total_bytes: u64,
+    // Share of the window's total_bytes, in percent
+    bytes_pct: f64,
+    duration_seconds: f64,
+    // "elephant" or "mice", by --elephant-bytes and --elephant-duration
+    flow_class: &'static str,
+    start_timestamp: f64,
+    end_timestamp: f64,
+    estimated_rtt_ms: f64,
+    time_to_first_byte_ms: f64,
+    push_count: usize,
+    retransmitted_bytes: u64,
+    // total_bytes less retransmitted_bytes, only with --effective-bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    effective_bytes: Option<u64>,
+    max_packet_size: usize,
+    mtu_sized_packet_count: usize,
+    // Only on ICMP flows, with --icmp-flows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icmp_type: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dst_label: Option<String>,
+}
+
+impl FlowStat {
+    /// Estimate the unsampled counters from a sample at 1 in `rate`, for
+    /// `top_flows` and `--flows-out` alike
+    fn scale_sampled(&mut self, rate: f64) {
+        self.packet_count = scale_count(self.packet_count, rate);
+        self.total_bytes = scale_bytes(self.total_bytes, rate);
+        self.push_count = scale_count(self.push_count, rate);
+        self.retransmitted_bytes = scale_bytes(self.retransmitted_bytes, rate);
+        self.effective_bytes = self.effective_bytes.map(|bytes| scale_bytes(bytes, rate));
+        self.mtu_sized_packet_count = scale_count(self.mtu_sized_packet_count, rate);
+    }
+}
+
+/// The top flows between one pair of addresses, in either direction
+#[derive(Serialize, Clone)]
+struct Conversation {
+    // The endpoints of the conversation's first-ranked flow
+    src_ip: String,
+    dst_ip: String,
+    total_bytes: u64,
+    flows: Vec<FlowStat>,
+}
+
+/// Groups `flows` by unordered address pair. Flows keep their ranking order
+/// within a conversation, and conversations are sorted by total bytes, ties
+/// in order of their first-ranked flow.
+fn build_conversations(flows: &[FlowStat]) -> Vec<Conversation> {
+    let mut conversations: Vec<Conversation> = Vec::new();
+    let mut by_pair: HashMap<(&str, &str), usize> = HashMap::new();
+    for flow in flows {
+        let (src, dst) = (flow.src_ip.as_str(), flow.dst_ip.as_str());
+        let pair = if src <= dst { (src, dst) } else { (dst, src) };
+        let index = *by_pair.entry(pair).or_insert_with(|| {
+            conversations.push(Conversation {
+                src_ip: flow.src_ip.clone(),
+                dst_ip: flow.dst_ip.clone(),
+                total_bytes: 0,
+                flows: Vec::new(),
+            });
+            conversations.len() - 1
+        });
+        conversations[index].total_bytes += flow.total_bytes;
+        conversations[index].flows.push(flow.clone());
+    }
+    conversations.sort_by_key(|c| std::cmp::Reverse(c.total_bytes));
+    conversations
+}
+
+#[derive(Serialize, Clone)]
+struct PortStat {
+    port: u16,
+    // "TCP", "UDP" or "SCTP", or "TCP+UDP" when merged across protocols
+    protocol: &'static str,
+    service_name: String,
+    packet_count: usize,
+    total_bytes: u64,
+    bytes_pct: f64,
+    distinct_flows: usize,
+    distinct_src_ips: usize,
+}
+
+#[derive(Serialize, Clone)]
+struct TalkerStat {
+    ip: String,
+    packet_count: usize,
+    total_bytes: u64,
+    // Only present when a --geoip database is loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    asn: Option<u32>,
+}
+
+#[derive(Serialize, Clone)]
+struct BeaconCandidate {
+    dst_ip: String,
+    // 1 - coefficient of variation of the burst intervals; 1.0 is perfectly periodic
+    interval_regularity: f64,
+}
+
+#[derive(Serialize, Clone)]
+struct SlowScanCandidate {
+    src_ip: String,
+    // Distinct destination ports over the --slow-scan-horizon, and in this
+    // window alone
+    distinct_dst_ports: usize,
+    window_dst_ports: usize,
+}
+
+#[derive(Serialize, Clone)]
+struct FanStat {
+    ip: String,
+    // Distinct destinations in top_fanout, distinct sources in top_fanin
+    distinct_peers: usize,
+}
+
+#[derive(Serialize, Clone)]
+struct MplsLabelStat {
+    label: u32,
+    packet_count: usize,
+}
+
+#[derive(Serialize, Clone)]
+struct DhcpClientStat {
+    client_mac: String,
+    discover_count: usize,
+    request_count: usize,
+    // The address last asked for, when the client named one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requested_ip: Option<String>,
+}
+
+fn service_name_for_port(port: u16) -> &'static str {
+    match port {
+        80 => "HTTP",
+        443 => "HTTPS",
+        53 => "DNS",
+        22 => "SSH",
+        25 => "SMTP",
+        110 => "POP3",
+        143 => "IMAP",
+        3389 => "RDP",
+        3306 => "MySQL",
+        5432 => "Postgres",
+        2905 => "M3UA",
+        3868 => "Diameter",
+        36412 => "S1AP",
+        38412 => "NGAP",
+        _ => "Unknown",
+    }
+}
+
+/// Flows that sent a SYN, split by whether the reverse flow answered with a
+/// SYN-ACK: (answered, unanswered)
+fn syn_answers(flow_stats: &HashMap<FlowKey, FlowAgg>) -> (usize, usize) {
+    flow_stats
+        .iter()
+        .filter(|(_, agg)| agg.syn_ts.is_some())
+        .fold((0, 0), |(answered, unanswered), (key, _)| {
+            match flow_stats.get(&key.reversed()).and_then(|reverse| reverse.syn_ack_ts) {
+                Some(_) => (answered + 1, unanswered),
+                None => (answered, unanswered + 1),
+            }
+        })
+}
+
+/// Share of connection attempts that went unanswered
+fn syn_ratio(answered: usize, unanswered: usize) -> f64 {
+    let attempts = answered + unanswered;
+    if attempts > 0 { unanswered as f64 / attempts as f64 } else { 0.0 }
+}
+
+/// Peak number of flows active at the same instant, treating each flow as
+/// active from its first to its last packet
+fn max_concurrent_flows(flow_stats: &HashMap<FlowKey, FlowAgg>) -> usize {
+    let mut events: Vec<(f64, i32)> = Vec::with_capacity(flow_stats.len() * 2);
+    for agg in flow_stats.values() {
+        events.push((agg.first_ts, 1));
+        events.push((agg.last_ts, -1));
+    }
+    // Starts sort before ends at the same timestamp, so flows that touch
+    // (or single-packet flows) still overlap
+    events.sort_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)));
+
+    let mut active = 0;
+    let mut peak = 0;
+    for (_, delta) in events {
+        active += delta;
+        peak = peak.max(active);
+    }
+    peak as usize
+}
+
+/// New flows per sub-interval: the window is cut into `buckets` equal slices
+/// and each flow is counted in the slice holding its first packet
+fn flow_birth_series(flow_stats: &HashMap<FlowKey, FlowAgg>, window_start: f64, window_size: f64, buckets: usize) -> Vec<usize> {
+    let mut series = vec![0; buckets];
+    for agg in flow_stats.values() {
+        let offset = ((agg.first_ts - window_start) / window_size).clamp(0.0, 1.0);
+        let bucket = ((offset * buckets as f64) as usize).min(buckets - 1);
+        series[bucket] += 1;
+    }
+    series
+}
+
+/// Gini coefficient of per-flow byte totals: 0 when every flow carries the
+/// same volume, approaching 1 when a single flow carries nearly everything
+fn flow_size_gini(flow_stats: &HashMap<FlowKey, FlowAgg>) -> f64 {
+    let mut sizes: Vec<u64> = flow_stats.values().map(|agg| agg.total_bytes).collect();
+    let total: u64 = sizes.iter().sum();
+    if sizes.len() < 2 || total == 0 {
+        return 0.0;
+    }
+    sizes.sort_unstable();
+    let n = sizes.len() as f64;
+    let weighted: f64 = sizes.iter().enumerate().map(|(i, &size)| (i + 1) as f64 * size as f64).sum();
+    2.0 * weighted / (n * total as f64) - (n + 1.0) / n
+}
+
+/// Shannon entropy in bits of a distribution given by its counts: 0 when
+/// everything falls in one bucket, log2(n) when n buckets are equally full
+fn shannon_entropy(counts: impl Iterator<Item = usize> + Clone) -> f64 {
+    let total: usize = counts.clone().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    counts
+        .filter(|&count| count > 0)
+        .map(|count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// QUIC version of a UDP payload in the long-header form (RFC 9000 §17.2):
+/// header-form and fixed bits set, then a 32-bit version. Short-header
+/// packets carry no version and aren't recognised.
+fn quic_long_header_version(payload: &[u8]) -> Option<u32> {
+    match payload {
+        [first, a, b, c, d, ..] if first & 0xc0 == 0xc0 => Some(u32::from_be_bytes([*a, *b, *c, *d])),
+        _ => None,
+    }
+}
+
+fn quic_version_name(version: u32) -> String {
+    match version {
+        0 => "negotiation".to_string(),
+        0x0000_0001 => "v1".to_string(),
+        0x6b33_43cf => "v2".to_string(),
+        0xff00_0000..=0xff00_00ff => format!("draft-{}", version & 0xff),
+        other => format!("0x{:08x}", other),
+    }
+}
+
+/// Distinct kinds of TCP option a segment carries. Parsing stops at the first
+/// unknown or malformed option; an unknown kind is still reported by number.
+fn tcp_option_kinds(tcp: &etherparse::TcpHeader) -> BTreeSet<String> {
+    use etherparse::{TcpOptionElement, TcpOptionReadError};
+    tcp.options_iterator()
+        .filter_map(|option| {
+            let name = match option {
+                Ok(TcpOptionElement::Noop) => "nop",
+                Ok(TcpOptionElement::MaximumSegmentSize(_)) => "mss",
+                Ok(TcpOptionElement::WindowScale(_)) => "window_scale",
+                Ok(TcpOptionElement::SelectiveAcknowledgementPermitted) => "sack_permitted",
+                Ok(TcpOptionElement::SelectiveAcknowledgement(..)) => "sack",
+                Ok(TcpOptionElement::Timestamp(..)) => "timestamp",
+                Err(TcpOptionReadError::UnknownId(kind)) => return Some(format!("kind_{}", kind)),
+                Err(_) => return None,
+            };
+            Some(name.to_string())
+        })
+        .collect()
+}
+
+/// Name of a DHCP message type (option 53), or its number when not one of
+/// RFC 2132's; BOOTP messages without the option are "bootp"
+fn dhcp_message_type_name(message_type: Option<u8>) -> String {
+    let name = match message_type {
+        None => "bootp",
+        Some(1) => "discover",
+        Some(2) => "offer",
+        Some(3) => "request",
+        Some(4) => "decline",
+        Some(5) => "ack",
+        Some(6) => "nak",
+        Some(7) => "release",
+        Some(8) => "inform",
+        Some(other) => return format!("type_{}", other),
+    };
+    name.to_string()
+}
+
+/// Name of an SCTP chunk type, or its number when not one of RFC 9260's
+fn sctp_chunk_name(chunk_type: u8) -> String {
+    let name = match chunk_type {
+        0 => "data",
+        1 => "init",
+        2 => "init_ack",
+        3 => "sack",
+        4 => "heartbeat",
+        5 => "heartbeat_ack",
+        6 => "abort",
+        7 => "shutdown",
+        8 => "shutdown_ack",
+        9 => "error",
+        10 => "cookie_echo",
+        11 => "cookie_ack",
+        14 => "shutdown_complete",
+        other => return format!("type_{}", other),
+    };
+    name.to_string()
+}
+
+/// Label for an IP protocol number: the common name where known, otherwise
+/// the number itself
+fn ip_protocol_name(protocol: u8) -> String {
+    let name = match protocol {
+        1 => "ICMP",
+        2 => "IGMP",
+        4 => "IPIP",
+        6 => "TCP",
+        17 => "UDP",
+        41 => "IPv6",
+        47 => "GRE",
+        50 => "ESP",
+        51 => "AH",
+        58 => "ICMPv6",
+        88 => "EIGRP",
+        89 => "OSPF",
+        103 => "PIM",
+        112 => "VRRP",
+        132 => "SCTP",
+        other => return other.to_string(),
+    };
+    name.to_string()
+}
+
+/// Labels for flow duration buckets with the given upper bounds in seconds,
+/// e.g. `0-5`, `5-10`, `10-20`, `20-30` and `30+` for the defaults
+fn duration_bucket_labels(boundaries: &[f64]) -> Vec<String> {
+    let lower_bounds = std::iter::once(0.0).chain(boundaries.iter().copied());
+    let mut labels: Vec<String> =
+        lower_bounds.zip(boundaries).map(|(lower, upper)| format!("{}-{}", lower, upper)).collect();
+    labels.push(format!("{}+", boundaries.last().copied().unwrap_or(0.0)));
+    labels
+}
+
+/// Build flow duration distribution histogram. A flow falls in the first
+/// bucket whose upper bound it doesn't exceed, or the open-ended last one.
+fn build_flow_duration_histogram(flow_stats: &HashMap<FlowKey, FlowAgg>, boundaries: &[f64]) -> Histogram {
+    let mut histogram = Histogram::with_labels(duration_bucket_labels(boundaries));
+    for agg in flow_stats.values() {
+        let duration = (agg.last_ts - agg.first_ts).max(0.0);
+        let bucket = boundaries.iter().position(|&upper| duration <= upper).unwrap_or(boundaries.len());
+        histogram.add(bucket);
+    }
+    histogram
+}
+
+/// Reported when a flow's handshake wasn't observed within the window
+const RTT_UNAVAILABLE: f64 = -1.0;
+
+/// Estimate a TCP flow's RTT from the SYN in one direction and the SYN-ACK in
+/// the reverse flow. This is the RTT between the capture point and the
+/// responder, so it only approximates the end-to-end RTT when capturing near
+/// the client.
+fn estimate_rtt_ms(key: &FlowKey, agg: &FlowAgg, flow_stats: &HashMap<FlowKey, FlowAgg>) -> f64 {
+    if agg.syn_ts.is_none() && agg.syn_ack_ts.is_none() {
+        return RTT_UNAVAILABLE;
+    }
+    let reverse = match flow_stats.get(&key.reversed()) {
+        Some(reverse) => reverse,
+        None => return RTT_UNAVAILABLE,
+    };
+
+    let handshake = match (agg.syn_ts, reverse.syn_ack_ts, agg.syn_ack_ts, reverse.syn_ts) {
+        (Some(syn), Some(syn_ack), _, _) | (_, _, Some(syn_ack), Some(syn)) => Some((syn, syn_ack)),
+        _ => None,
+    };
+    match handshake {
+        Some((syn, syn_ack)) if syn_ack >= syn => (syn_ack - syn) * 1000.0,
+        _ => RTT_UNAVAILABLE,
+    }
+}
+
+/// Sentinel `time_to_first_byte_ms` for flows without an observed SYN or
+/// without any payload
+const TTFB_UNAVAILABLE: f64 = -1.0;
+
+/// Delay from the connection's SYN to its first data-bearing packet in either
+/// direction, so both flow keys of a TCP connection report the same value
+fn time_to_first_byte_ms(key: &FlowKey, agg: &FlowAgg, flow_stats: &HashMap<FlowKey, FlowAgg>) -> f64 {
+    let reverse = flow_stats.get(&key.reversed());
+    let syn = agg.syn_ts.or_else(|| reverse.and_then(|reverse| reverse.syn_ts));
+    let first_payload = match (agg.first_payload_ts, reverse.and_then(|reverse| reverse.first_payload_ts)) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    match (syn, first_payload) {
+        (Some(syn), Some(payload)) if payload >= syn => (payload - syn) * 1000.0,
+        _ => TTFB_UNAVAILABLE,
+    }
+}
+
+/// Largest flows first by the `--top-flows-by` key, then by bytes. Ties fall
+/// back to the flow tuple so the order doesn't depend on hashing.
+fn flow_order(a: &FlowStat, b: &FlowStat, ranking: FlowRanking) -> Ordering {
+    let primary = match ranking {
+        FlowRanking::Bytes => Ordering::Equal,
+        FlowRanking::Packets => b.packet_count.cmp(&a.packet_count),
+        FlowRanking::Duration => b.duration_seconds.partial_cmp(&a.duration_seconds).unwrap_or(Ordering::Equal),
+    };
+    primary
+        .then_with(|| b.total_bytes.cmp(&a.total_bytes))
+        .then_with(|| (&a.src_ip, a.src_port, &a.dst_ip, a.dst_port).cmp(&(&b.src_ip, b.src_port, &b.dst_ip, b.dst_port)))
+        .then_with(|| a.protocol.as_str().cmp(b.protocol.as_str()))
+        .then_with(|| a.icmp_type.cmp(&b.icmp_type))
+}
+
+fn port_order(a: &PortStat, b: &PortStat) -> Ordering {
+    b.total_bytes
+        .cmp(&a.total_bytes)
+        .then(a.port.cmp(&b.port))
+        .then(a.protocol.cmp(b.protocol))
+}
+
+fn beacon_order(a: &BeaconCandidate, b: &BeaconCandidate) -> Ordering {
+    b.interval_regularity
+        .partial_cmp(&a.interval_regularity)
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| a.dst_ip.cmp(&b.dst_ip))
+}
+
+/// An entry's share of the window's bytes, as a percentage
+fn bytes_pct(bytes: u64, window_bytes: u64) -> f64 {
+    if window_bytes > 0 { bytes as f64 * 100.0 / window_bytes as f64 } else { 0.0 }
+}
+
+fn slow_scan_order(a: &SlowScanCandidate, b: &SlowScanCandidate) -> Ordering {
+    b.distinct_dst_ports.cmp(&a.distinct_dst_ports).then_with(|| a.src_ip.cmp(&b.src_ip))
+}
+
+fn fan_order(a: &FanStat, b: &FanStat) -> Ordering {
+    b.distinct_peers.cmp(&a.distinct_peers).then_with(|| a.ip.cmp(&b.ip))
+}
+
+fn mpls_label_order(a: &MplsLabelStat, b: &MplsLabelStat) -> Ordering {
+    b.packet_count.cmp(&a.packet_count).then(a.label.cmp(&b.label))
+}
+
+fn dhcp_client_order(a: &DhcpClientStat, b: &DhcpClientStat) -> Ordering {
+    (b.discover_count + b.request_count)
+        .cmp(&(a.discover_count + a.request_count))
+        .then_with(|| a.client_mac.cmp(&b.client_mac))
+}
+
+fn build_top_flows(
+    flow_stats: &HashMap<FlowKey, FlowAgg>,
+    limit: usize,
+    options: &Options,
+    labels: Option<&LabelMap>,
+    window_bytes: u64,
+) -> Vec<FlowStat> {
+    let aggregate = options.aggregate_prefix;
+    let mut flows: Vec<FlowStat> = flow_stats
+        .iter()
+        .map(|(key, agg)| {
+            let duration_seconds = (agg.last_ts - agg.first_ts).max(0.0);
+            FlowStat {
+                src_ip: format_ip(key.src_ip, aggregate),
+                src_port: key.src_port,
+                dst_ip: format_ip(key.dst_ip, aggregate),
+                dst_port: key.dst_port,
+                protocol: key.protocol,
+                packet_count: agg.packet_count,
+                total_bytes: agg.total_bytes,
+                bytes_pct: bytes_pct(agg.total_bytes, window_bytes),
+                duration_seconds,
+                flow_class: options.elephants.classify(scale_bytes(agg.total_bytes, options.sample_scale), duration_seconds),
+                start_timestamp: agg.first_ts,
+                end_timestamp: agg.last_ts,
+                estimated_rtt_ms: estimate_rtt_ms(key, agg, flow_stats),
+                time_to_first_byte_ms: time_to_first_byte_ms(key, agg, flow_stats),
+                push_count: agg.push_count,
+                retransmitted_bytes: agg.retransmitted_bytes,
+                effective_bytes: options.effective_bytes.then(|| agg.total_bytes - agg.retransmitted_bytes),
+                max_packet_size: agg.max_packet_size,
+                mtu_sized_packet_count: agg.mtu_sized_packet_count(),
+                icmp_type: key.icmp_type,
+                src_label: labels.and_then(|labels| labels.lookup(key.src_ip)).map(str::to_string),
+                dst_label: labels.and_then(|labels| labels.lookup(key.dst_ip)).map(str::to_string),
+            }
+        })
+        .collect();
+
+    flows.sort_by(|a, b| flow_order(a, b, options.top_flows_by));
+    flows.truncate(limit);
+    flows
+}
+
+/// Per-port rows, keyed on (port, protocol) or, with `merge_protocols`,
+/// on the port number alone with TCP and UDP traffic summed. SCTP rows are
+/// never merged.
+fn build_top_ports(
+    port_stats: &HashMap<PortKey, PortAgg>,
+    limit: usize,
+    merge_protocols: bool,
+    window_bytes: u64,
+) -> Vec<PortStat> {
+    let mut ports: Vec<PortStat> = if merge_protocols {
+        let mut merged: HashMap<(u16, bool), (PortStat, HashSet<IpAddr>)> = HashMap::new();
+        for (key, agg) in port_stats {
+            let (port, src_ips) = merged.entry((key.0, key.1 == Protocol::Sctp)).or_insert_with(|| {
+                let port = PortStat {
+                    port: key.0,
+                    protocol: key.1.as_str(),
+                    service_name: service_name_for_port(key.0).to_string(),
+                    packet_count: 0,
+                    total_bytes: 0,
+                    bytes_pct: 0.0,
+                    distinct_flows: 0,
+                    distinct_src_ips: 0,
+                };
+                (port, HashSet::new())
+            });
+            if port.protocol != key.1.as_str() {
+                port.protocol = "TCP+UDP";
+            }
+            port.packet_count += agg.packet_count;
+            port.total_bytes += agg.total_bytes;
+            port.bytes_pct = bytes_pct(port.total_bytes, window_bytes);
+            // Flow keys carry the protocol, so only sources can overlap
+            port.distinct_flows += agg.flows.len();
+            src_ips.extend(&agg.src_ips);
+            port.distinct_src_ips = src_ips.len();
+        }
+        merged.into_values().map(|(port, _)| port).collect()
+    } else {
+        port_stats
+            .iter()
+            .map(|(key, agg)| PortStat {
+                port: key.0,
+                protocol: key.1.as_str(),
+                service_name: service_name_for_port(key.0).to_string(),
+                packet_count: agg.packet_count,
+                total_bytes: agg.total_bytes,
+                bytes_pct: bytes_pct(agg.total_bytes, window_bytes),
+                distinct_flows: agg.flows.len(),
+                distinct_src_ips: agg.src_ips.len(),
+            })
+            .collect()
+    };
+
+    ports.sort_by(port_order);
+    ports.truncate(limit);
+    ports
+}
+
+fn build_top_talkers(
+    host_stats: &HashMap<IpAddr, HostAgg>,
+    limit: usize,
+    aggregate: Option<AggregatePrefix>,
+    geoip: Option<&GeoIp>,
+) -> Vec<TalkerStat> {
+    let mut hosts: Vec<(&IpAddr, &HostAgg)> = host_stats.iter().collect();
+    hosts.sort_by(|a, b| b.1.total_bytes.cmp(&a.1.total_bytes).then(a.0.cmp(b.0)));
+    hosts.truncate(limit);
+
+    // Lookups only run for the entries that made the cut; aggregated
+    // addresses are looked up by their network address
+    hosts
+        .into_iter()
+        .map(|(&addr, agg)| {
+            let (country, asn) = geoip.map_or((None, None), |geoip| geoip.lookup(addr));
+            TalkerStat {
+                ip: format_ip(addr, aggregate),
+                packet_count: agg.packet_count,
+                total_bytes: agg.total_bytes,
+                country,
+                asn,
+            }
+        })
+        .collect()
+}
+
+/// Hosts with the most distinct peers: sources by destinations contacted
+/// (fan-out) or destinations by sources seen (fan-in)
+fn build_top_fan(peers: &HashMap<IpAddr, HashSet<IpAddr>>, limit: usize, aggregate: Option<AggregatePrefix>) -> Vec<FanStat> {
+    let mut hosts: Vec<(&IpAddr, usize)> = peers.iter().map(|(addr, peers)| (addr, peers.len())).collect();
+    hosts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    hosts.truncate(limit);
+    hosts
+        .into_iter()
+        .map(|(&addr, distinct_peers)| FanStat { ip: format_ip(addr, aggregate), distinct_peers })
+        .collect()
+}
+
+/// Destinations whose burst intervals vary by at most `BEACON_MAX_CV`,
+/// most regular first
+fn build_beaconing_candidates(
+    intervals: &HashMap<IpAddr, BurstIntervals>,
+    limit: usize,
+    aggregate: Option<AggregatePrefix>,
+) -> Vec<BeaconCandidate> {
+    let mut candidates: Vec<BeaconCandidate> = intervals
+        .iter()
+        .filter_map(|(&addr, gaps)| {
+            let cv = gaps.coefficient_of_variation().filter(|&cv| cv <= BEACON_MAX_CV)?;
+            Some(BeaconCandidate { dst_ip: format_ip(addr, aggregate), interval_regularity: 1.0 - cv })
+        })
+        .collect();
+    candidates.sort_by(beacon_order);
+    candidates.truncate(limit);
+    candidates
+}
+
+fn build_top_mpls_labels(label_counts: &HashMap<u32, usize>, limit: usize) -> Vec<MplsLabelStat> {
+    let mut labels: Vec<MplsLabelStat> = label_counts
+        .iter()
+        .map(|(&label, &packet_count)| MplsLabelStat { label, packet_count })
+        .collect();
+
+    labels.sort_by(mpls_label_order);
+    labels.truncate(limit);
+    labels
+}
+
+fn build_dhcp_clients(clients: &HashMap<String, DhcpClientStat>, limit: usize) -> Vec<DhcpClientStat> {
+    let mut clients: Vec<DhcpClientStat> = clients.values().cloned().collect();
+    clients.sort_by(dhcp_client_order);
+    clients.truncate(limit);
+    clients
+}
+
+// --------------------------
+// GeoIP Enrichment
+// --------------------------
+
+/// Label for addresses that have no meaningful public geolocation
+fn non_public_scope(addr: IpAddr) -> Option<&'static str> {
+    match addr {
+        IpAddr::V4(v4) => {
+            if v4.is_private() || v4.is_link_local() || (v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64) {
+                Some("private")
+            } else if v4.is_loopback() || v4.is_multicast() || v4.is_broadcast()
+                || v4.is_unspecified() || v4.is_documentation() || v4.octets()[0] >= 240
+            {
+                Some("reserved")
+            } else {
+                None
+            }
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            if (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80 {
+                Some("private")
+            } else if v6.is_loopback() || v6.is_multicast() || v6.is_unspecified() || (first == 0x2001 && v6.segments()[1] == 0x0db8) {
+                Some("reserved")
+            } else if let Some(v4) = v6.to_ipv4_mapped() {
+                non_public_scope(IpAddr::V4(v4))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// MaxMind databases loaded via `--geoip`. Country and ASN data usually ship
+/// as separate files, so each lookup takes the first database that has the field.
+struct GeoIp {
+    readers: Vec<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    fn open(paths: &[String]) -> Result<Self, maxminddb::MaxMindDBError> {
+        let readers = paths
+            .iter()
+            .map(maxminddb::Reader::open_readfile)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(GeoIp { readers })
+    }
+
+    /// Country ISO code and ASN for an address. Private and reserved ranges
+    /// are labeled in the country field instead of being looked up.
+    fn lookup(&self, addr: IpAddr) -> (Option<String>, Option<u32>) {
+        if let Some(scope) = non_public_scope(addr) {
+            return (Some(scope.to_string()), None);
+        }
+
+        let mut country = None;
+        let mut asn = None;
+        for reader in &self.readers {
+            if country.is_none() {
+                if let Ok(record) = reader.lookup::<geoip2::Country>(addr) {
+                    country = record.country.and_then(|c| c.iso_code).map(str::to_string);
+                }
+            }
+            if asn.is_none() {
+                if let Ok(record) = reader.lookup::<geoip2::Asn>(addr) {
+                    asn = record.autonomous_system_number;
+                }
+            }
+        }
+        (country, asn)
+    }
+}
+
+// --------------------------
+// Flow Labels
+// --------------------------
+
+/// CIDR ranges mapped to role labels, loaded via `--labels`. Ranges are kept
+/// most-specific first, so the first match is the longest prefix.
+struct LabelMap {
+    ranges: Vec<(IpAddr, u8, String)>,
+}
+
+/// Parse `addr/len` (or a bare address, as a host route) into its network
+/// address and prefix length
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8), String> {
+    let (addr, len) = match cidr.split_once('/') {
+        Some((addr, len)) => (addr, Some(len)),
+        None => (cidr, None),
+    };
+    let addr: IpAddr = addr.trim().parse().map_err(|_| format!("invalid address in range '{}'", cidr))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let len = match len {
+        Some(len) => len
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&len: &u8| len <= max)
+            .ok_or_else(|| format!("invalid prefix length in range '{}'", cidr))?,
+        None => max,
+    };
+    let network = match addr {
+        IpAddr::V4(v4) => IpAddr::V4(mask_ipv4(v4, len)),
+        IpAddr::V6(v6) => IpAddr::V6(mask_ipv6(v6, len)),
+    };
+    Ok((network, len))
+}
+
+fn cidr_contains(network: IpAddr, prefix_len: u8, addr: IpAddr) -> bool {
+    match (addr, network) {
+        (IpAddr::V4(v4), IpAddr::V4(net)) => mask_ipv4(v4, prefix_len) == net,
+        (IpAddr::V6(v6), IpAddr::V6(net)) => mask_ipv6(v6, prefix_len) == net,
+        _ => false,
+    }
+}
+
+impl LabelMap {
+    /// Read a JSON object of `"cidr": "label"` entries
+    fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("cannot open labels '{}': {}", path, e))?;
+        let entries: BTreeMap<String, String> = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("invalid labels '{}': {}", path, e))?;
+        LabelMap::from_entries(entries)
+    }
+
+    fn from_entries(entries: impl IntoIterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut ranges = entries
+            .into_iter()
+            .map(|(cidr, label)| parse_cidr(&cidr).map(|(network, len)| (network, len, label)))
+            .collect::<Result<Vec<_>, _>>()?;
+        ranges.sort_by_key(|r| std::cmp::Reverse(r.1));
+        Ok(LabelMap { ranges })
+    }
+
+    fn lookup(&self, addr: IpAddr) -> Option<&str> {
+        self.ranges
+            .iter()
+            .find(|(network, len, _)| cidr_contains(*network, *len, addr))
+            .map(|(_, _, label)| label.as_str())
+    }
+}
+
+// --------------------------
+// Window Feature Structure
+// --------------------------
+#[derive(Serialize)]
+struct WindowFeature {
+    // Position in the stream, numbered per --window-index
+    window_index: usize,
+    window_start: f64,
+    window_end: f64,
+    clock_adjustment_events: usize,
+    // Longest silence between packets, up to the window end; long ones
+    // suggest the capture paused or dropped packets
+    max_gap_seconds: f64,
+    has_capture_gap: bool,
+    packet_count: usize,
+    total_bytes: u64,
+    // Captured bytes plus per-frame overhead, only with --wire-bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wire_bytes: Option<u64>,
+    ip_packet_count: usize,
+    ip_total_bytes: u64,
+    jumbo_frame_count: usize,
+    avg_packet_size: f64,
+    avg_payload_packet_size: f64,
+    min_packet_size: usize,
+    max_packet_size: usize,
+    packet_size_std: f64,
+    median_packet_size: f64,
+    p95_packet_size: f64,
+    packet_size_bimodality: f64,
+    small_packet_ratio: f64,
+    large_packet_ratio: f64,
+    tcp_count: usize,
+    udp_count: usize,
+    sctp_count: usize,
+    quic_count: usize,
+    icmp_count: usize,
+    other_count: usize,
+    tcp_ratio: f64,
+    udp_ratio: f64,
+    icmp_ratio: f64,
+    other_ratio: f64,
+    unique_src_ips: usize,
+    unique_dst_ips: usize,
+    unique_src_ratio: f64,
+    unique_dst_ratio: f64,
+    // Shannon entropy (bits) of the packet distribution over source/destination IPs
+    src_ip_entropy: f64,
+    dst_ip_entropy: f64,
+    top_src_ips: Vec<TalkerStat>,
+    top_dst_ips: Vec<TalkerStat>,
+    // Sources by distinct destinations, and destinations by distinct sources
+    top_fanout: Vec<FanStat>,
+    top_fanin: Vec<FanStat>,
+    flow_count: usize,
+    tcp_connection_count: usize,
+    // Connection attempts (flows with a SYN) and whether the reverse flow
+    // carried a SYN-ACK within the window
+    answered_syn_count: usize,
+    unanswered_syn_count: usize,
+    unanswered_syn_ratio: f64,
+    max_concurrent_flows: usize,
+    unidirectional_flow_count: usize,
+    unidirectional_flow_ratio: f64,
+    // Flows of each flow_class, over every flow of the window
+    elephant_flow_count: usize,
+    mice_flow_count: usize,
+    flow_ratio: f64,
+    avg_flow_packets: f64,
+    avg_flow_bytes: f64,
+    flow_size_gini: f64,
+    flow_birth_series: Vec<usize>,
+    packets_per_sec: f64,
+    bytes_per_sec: f64,
+    port_diversity: f64,
+    unique_dst_ports: usize,
+    tcp_unique_dst_ports: usize,
+    udp_unique_dst_ports: usize,
+    unique_dst_ports_per_src: usize,
+    // Phase 2: TCP Health Metrics
+    tcp_syn_count: usize,
+    // tcp_syn_count per second of window length
+    syn_rate: f64,
+    // syn_rate is above --syn-rate-threshold, only with that option
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_storm: Option<bool>,
+    tcp_ack_count: usize,
+    tcp_rst_count: usize,
+    tcp_fin_count: usize,
+    tcp_retransmissions: usize,
+    fast_retransmit_count: usize,
+    timeout_retransmit_count: usize,
+    // UDP packets reusing an IPv4 ID already seen in their flow, only with
+    // --track-ip-ids
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeated_ip_id_count: Option<usize>,
+    // Congestion signalling: TCP ECE/CWR flags and IP-layer CE marks
+    tcp_ece_count: usize,
+    tcp_cwr_count: usize,
+    tcp_zero_window_count: usize,
+    // SYN and SYN-ACK segments carrying each TCP option kind
+    tcp_option_counts: BTreeMap<String, usize>,
+    // Chunks of each type across SCTP packets
+    sctp_chunk_counts: BTreeMap<String, usize>,
+    ecn_marked_count: usize,
+    avg_estimated_hops: f64,
+    port_zero_count: usize,
+    evicted_flow_count: usize,
+    // Direction relative to --local-net (IP packets only)
+    inbound_bytes: u64,
+    outbound_bytes: u64,
+    internal_bytes: u64,
+    external_bytes: u64,
+    // Frames to the all-ones MAC or 255.255.255.255, and frames to a
+    // multicast MAC or group address; unicast is the rest
+    broadcast_packet_count: usize,
+    broadcast_bytes: u64,
+    multicast_packet_count: usize,
+    multicast_bytes: u64,
+    // MPLS-labeled frames (classified by their inner IP header)
+    mpls_labeled_count: usize,
+    top_mpls_labels: Vec<MplsLabelStat>,
+    // DHCP messages by type, and the clients sending DISCOVER or REQUEST
+    dhcp_message_counts: BTreeMap<String, usize>,
+    dhcp_clients: Vec<DhcpClientStat>,
+    // Phase 2: Distribution Histograms
+    packet_size_distribution: Histogram,
+    // TCP segments by payload length, headers excluded
+    tcp_payload_size_distribution: Histogram,
+    flow_duration_distribution: Histogram,
+    ip_protocol_distribution: BTreeMap<String, usize>,
+    // QUIC long-header packets per version, only with --quic-versions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quic_versions: Option<BTreeMap<String, usize>>,
+    top_flows: Vec<FlowStat>,
+    // top_flows grouped by address pair, only with --conversations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conversations: Option<Vec<Conversation>>,
+    port_stats: Vec<PortStat>,
+    beaconing_candidates: Vec<BeaconCandidate>,
+    // Sources whose destination ports add up across windows, only with
+    // --slow-scan-horizon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slow_scan_candidates: Option<Vec<SlowScanCandidate>>,
+    // Address-space heatmaps, only with --heatmap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src_octet_heatmap: Option<OctetHeatmap>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dst_octet_heatmap: Option<OctetHeatmap>,
+    // Packet-size fractions then protocol ratios, L2-normalized, only with
+    // --shape-fingerprint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shape_fingerprint: Option<Vec<f64>>,
+    // Smoothed copies of key metrics (e.g. `packets_per_sec_ema`), present
+    // only with --ema-alpha or --ma-window
+    #[serde(flatten)]
+    smoothed: BTreeMap<String, f64>,
+}
+
+/// A count observed under sampling, scaled up by `rate` to the nearest whole number
+fn scale_count(count: usize, rate: f64) -> usize {
+    (count as f64 * rate).round() as usize
+}
+
+fn scale_bytes(bytes: u64, rate: f64) -> u64 {
+    (bytes as f64 * rate).round() as u64
+}
+
+impl WindowFeature {
+    /// Scale counts and byte totals observed under sampling back up to
+    /// estimates of the full traffic. Ratios, averages, min/max and distinct
+    /// counts (IPs, flows, ports) are left as observed in the sample.
+    fn scale_sampled(&mut self, rate: f64) {
+        let scale = |count: &mut usize| *count = scale_count(*count, rate);
+        let scale_u64 = |bytes: &mut u64| *bytes = scale_bytes(*bytes, rate);
+        scale(&mut self.packet_count);
+        scale_u64(&mut self.total_bytes);
+        if let Some(wire_bytes) = &mut self.wire_bytes {
+            scale_u64(wire_bytes);
+        }
+        if let Some(count) = &mut self.repeated_ip_id_count {
+            scale(count);
+        }
+        scale(&mut self.ip_packet_count);
+        scale_u64(&mut self.ip_total_bytes);
+        scale(&mut self.jumbo_frame_count);
+        scale(&mut self.tcp_count);
+        scale(&mut self.udp_count);
+        scale(&mut self.sctp_count);
+        scale(&mut self.quic_count);
+        scale(&mut self.icmp_count);
+        scale(&mut self.other_count);
+        self.packets_per_sec *= rate;
+        self.bytes_per_sec *= rate;
+        scale(&mut self.tcp_syn_count);
+        self.syn_rate *= rate;
+        scale(&mut self.tcp_ack_count);
+        scale(&mut self.tcp_rst_count);
+        scale(&mut self.tcp_fin_count);
+        scale(&mut self.tcp_retransmissions);
+        scale(&mut self.fast_retransmit_count);
+        scale(&mut self.timeout_retransmit_count);
+        scale(&mut self.tcp_ece_count);
+        scale(&mut self.tcp_cwr_count);
+        scale(&mut self.tcp_zero_window_count);
+        scale(&mut self.ecn_marked_count);
+        scale(&mut self.port_zero_count);
+        scale_u64(&mut self.inbound_bytes);
+        scale_u64(&mut self.outbound_bytes);
+        scale_u64(&mut self.internal_bytes);
+        scale_u64(&mut self.external_bytes);
+        scale(&mut self.broadcast_packet_count);
+        scale_u64(&mut self.broadcast_bytes);
+        scale(&mut self.multicast_packet_count);
+        scale_u64(&mut self.multicast_bytes);
+        scale(&mut self.mpls_labeled_count);
+        for label in &mut self.top_mpls_labels {
+            scale(&mut label.packet_count);
+        }
+        for count in self.dhcp_message_counts.values_mut() {
+            scale(count);
+        }
+        for client in &mut self.dhcp_clients {
+            scale(&mut client.discover_count);
+            scale(&mut client.request_count);
+        }
+        for talker in self.top_src_ips.iter_mut().chain(self.top_dst_ips.iter_mut()) {
+            scale(&mut talker.packet_count);
+            scale_u64(&mut talker.total_bytes);
+        }
+        for count in self.packet_size_distribution.counts_mut().chain(self.tcp_payload_size_distribution.counts_mut()) {
+            scale(count);
+        }
+        for count in self.ip_protocol_distribution.values_mut() {
+            scale(count);
+        }
+        for count in self.tcp_option_counts.values_mut().chain(self.sctp_chunk_counts.values_mut()) {
+            scale(count);
+        }
+        for count in self.quic_versions.iter_mut().flat_map(|versions| versions.values_mut()) {
+            scale(count);
+        }
+        for heatmap in self.src_octet_heatmap.iter_mut().chain(self.dst_octet_heatmap.iter_mut()) {
+            for cell in heatmap.slash8.values_mut().chain(heatmap.slash16.values_mut()) {
+                scale(&mut cell.packet_count);
+                scale_u64(&mut cell.total_bytes);
+            }
+        }
+        for flow in &mut self.top_flows {
+            flow.scale_sampled(rate);
+        }
+        for port in &mut self.port_stats {
+            scale(&mut port.packet_count);
+            scale_u64(&mut port.total_bytes);
+        }
+    }
+}
+
+// --------------------------
+// Window Blocks
+// --------------------------
+
+fn merge_talkers<'w>(lists: impl Iterator<Item = &'w Vec<TalkerStat>>, limit: usize) -> Vec<TalkerStat> {
+    let mut merged: BTreeMap<String, TalkerStat> = BTreeMap::new();
+    for talker in lists.flatten() {
+        merged
+            .entry(talker.ip.clone())
+            .and_modify(|entry| {
+                entry.packet_count += talker.packet_count;
+                entry.total_bytes += talker.total_bytes;
+            })
+            .or_insert_with(|| talker.clone());
+    }
+    let mut talkers: Vec<TalkerStat> = merged.into_values().collect();
+    // Stable sort over the BTreeMap order keeps byte ties sorted by address
+    talkers.sort_by_key(|t| std::cmp::Reverse(t.total_bytes));
+    talkers.truncate(limit);
+    talkers
+}
+
+fn merge_flows<'w>(
+    lists: impl Iterator<Item = &'w Vec<FlowStat>>,
+    limit: usize,
+    ranking: FlowRanking,
+    elephants: ElephantThresholds,
+    window_bytes: u64,
+) -> Vec<FlowStat> {
+    type MergeKey = (String, u16, String, u16, Protocol, Option<u8>);
+    let mut merged: HashMap<MergeKey, FlowStat> = HashMap::new();
+    for flow in lists.flatten() {
+        let key = (flow.src_ip.clone(), flow.src_port, flow.dst_ip.clone(), flow.dst_port, flow.protocol, flow.icmp_type);
+        match merged.get_mut(&key) {
+            Some(entry) => {
+                entry.packet_count += flow.packet_count;
+                entry.total_bytes += flow.total_bytes;
+                entry.push_count += flow.push_count;
+                entry.retransmitted_bytes += flow.retransmitted_bytes;
+                if let (Some(merged), Some(other)) = (&mut entry.effective_bytes, flow.effective_bytes) {
+                    *merged += other;
+                }
+                // Only windows that reached the block's largest packet saw
+                // MTU-sized packets by the merged measure
+                if flow.max_packet_size > entry.max_packet_size + MTU_SIZE_SLACK {
+                    entry.mtu_sized_packet_count = 0;
+                }
+                if flow.max_packet_size + MTU_SIZE_SLACK >= entry.max_packet_size {
+                    entry.mtu_sized_packet_count += flow.mtu_sized_packet_count;
+                }
+                entry.max_packet_size = entry.max_packet_size.max(flow.max_packet_size);
+                entry.start_timestamp = entry.start_timestamp.min(flow.start_timestamp);
+                entry.end_timestamp = entry.end_timestamp.max(flow.end_timestamp);
+                entry.duration_seconds = (entry.end_timestamp - entry.start_timestamp).max(0.0);
+                entry.flow_class = elephants.classify(entry.total_bytes, entry.duration_seconds);
+                if entry.estimated_rtt_ms == RTT_UNAVAILABLE {
+                    entry.estimated_rtt_ms = flow.estimated_rtt_ms;
+                }
+                if entry.time_to_first_byte_ms == TTFB_UNAVAILABLE {
+                    entry.time_to_first_byte_ms = flow.time_to_first_byte_ms;
+                }
+            }
+            None => {
+                merged.insert(key, flow.clone());
+            }
+        }
+    }
+    let mut flows: Vec<FlowStat> = merged.into_values().collect();
+    for flow in &mut flows {
+        flow.bytes_pct = bytes_pct(flow.total_bytes, window_bytes);
+    }
+    flows.sort_by(|a, b| flow_order(a, b, ranking));
+    flows.truncate(limit);
+    flows
+}
+
+fn merge_ports<'w>(lists: impl Iterator<Item = &'w Vec<PortStat>>, limit: usize, window_bytes: u64) -> Vec<PortStat> {
+    let mut merged: HashMap<(u16, &'static str), PortStat> = HashMap::new();
+    for port in lists.flatten() {
+        merged
+            .entry((port.port, port.protocol))
+            .and_modify(|entry| {
+                entry.packet_count += port.packet_count;
+                entry.total_bytes += port.total_bytes;
+                // Windows may share flows and sources, so keep the largest
+                entry.distinct_flows = entry.distinct_flows.max(port.distinct_flows);
+                entry.distinct_src_ips = entry.distinct_src_ips.max(port.distinct_src_ips);
+            })
+            .or_insert_with(|| port.clone());
+    }
+    let mut ports: Vec<PortStat> = merged.into_values().collect();
+    for port in &mut ports {
+        port.bytes_pct = bytes_pct(port.total_bytes, window_bytes);
+    }
+    ports.sort_by(port_order);
+    ports.truncate(limit);
+    ports
+}
+
+/// A destination flagged in several windows keeps its most regular score
+fn merge_beaconing_candidates<'w>(
+    lists: impl Iterator<Item = &'w Vec<BeaconCandidate>>,
+    limit: usize,
+) -> Vec<BeaconCandidate> {
+    let mut merged: HashMap<&str, f64> = HashMap::new();
+    for candidate in lists.flatten() {
+        let entry = merged.entry(candidate.dst_ip.as_str()).or_insert(candidate.interval_regularity);
+        *entry = entry.max(candidate.interval_regularity);
+    }
+    let mut candidates: Vec<BeaconCandidate> = merged
+        .into_iter()
+        .map(|(dst_ip, interval_regularity)| BeaconCandidate { dst_ip: dst_ip.to_string(), interval_regularity })
+        .collect();
+    candidates.sort_by(beacon_order);
+    candidates.truncate(limit);
+    candidates
+}
+
+/// A source flagged in several windows keeps its largest counts
+fn merge_slow_scan_candidates<'w>(
+    lists: impl Iterator<Item = &'w Vec<SlowScanCandidate>>,
+    limit: usize,
+) -> Vec<SlowScanCandidate> {
+    let mut merged: HashMap<&str, SlowScanCandidate> = HashMap::new();
+    for candidate in lists.flatten() {
+        merged
+            .entry(candidate.src_ip.as_str())
+            .and_modify(|entry| {
+                entry.distinct_dst_ports = entry.distinct_dst_ports.max(candidate.distinct_dst_ports);
+                entry.window_dst_ports = entry.window_dst_ports.max(candidate.window_dst_ports);
+            })
+            .or_insert_with(|| candidate.clone());
+    }
+    let mut candidates: Vec<SlowScanCandidate> = merged.into_values().collect();
+    candidates.sort_by(slow_scan_order);
+    candidates.truncate(limit);
+    candidates
+}
+
+/// Peer sets aren't kept past a window, so a host's merged count is its
+/// largest single-window count: a lower bound on its distinct peers
+fn merge_fan<'w>(lists: impl Iterator<Item = &'w Vec<FanStat>>, limit: usize) -> Vec<FanStat> {
+    let mut merged: HashMap<&str, FanStat> = HashMap::new();
+    for host in lists.flatten() {
+        merged
+            .entry(host.ip.as_str())
+            .and_modify(|entry| entry.distinct_peers = entry.distinct_peers.max(host.distinct_peers))
+            .or_insert_with(|| host.clone());
+    }
+    let mut hosts: Vec<FanStat> = merged.into_values().collect();
+    hosts.sort_by(fan_order);
+    hosts.truncate(limit);
+    hosts
+}
+
+fn merge_mpls_labels<'w>(lists: impl Iterator<Item = &'w Vec<MplsLabelStat>>, limit: usize) -> Vec<MplsLabelStat> {
+    let mut merged: HashMap<u32, usize> = HashMap::new();
+    for label in lists.flatten() {
+        *merged.entry(label.label).or_insert(0) += label.packet_count;
+    }
+    let mut labels: Vec<MplsLabelStat> = merged
+        .into_iter()
+        .map(|(label, packet_count)| MplsLabelStat { label, packet_count })
+        .collect();
+    labels.sort_by(mpls_label_order);
+    labels.truncate(limit);
+    labels
+}
+
+/// Sum each client's messages across windows, keeping the latest requested address
+fn merge_dhcp_clients<'w>(lists: impl Iterator<Item = &'w Vec<DhcpClientStat>>, limit: usize) -> Vec<DhcpClientStat> {
+    let mut merged: HashMap<String, DhcpClientStat> = HashMap::new();
+    for client in lists.flatten() {
+        let entry = merged.entry(client.client_mac.clone()).or_insert_with(|| DhcpClientStat {
+            discover_count: 0,
+            request_count: 0,
+            ..client.clone()
+        });
+        entry.discover_count += client.discover_count;
+        entry.request_count += client.request_count;
+        if client.requested_ip.is_some() {
+            entry.requested_ip = client.requested_ip.clone();
+        }
+    }
+    build_dhcp_clients(&merged, limit)
+}
+
+/// Combine consecutive windows into one record with the same schema.
+/// - Counts and byte totals are summed, histograms and top-N lists merged
+///   (the lists were already cut to top-N, so merged lists are approximate)
+/// - Ratios, rates and per-flow averages are averaged across the windows
+/// - Packet size mean/std and small/large ratios are pooled over all sized
+///   packets; min/max span the block; median/p95 are packet-weighted means
+///   of the window estimates
+/// - Distinct counts (IPs, flows, ports) take the block maximum: windows
+///   don't keep their key sets, so the true block-wide count is unknown but
+///   at least this large
+fn merge_windows(
+    windows: &[WindowFeature],
+    top_n: usize,
+    top_flows_by: FlowRanking,
+    elephants: ElephantThresholds,
+) -> WindowFeature {
+    let first = &windows[0];
+    let last = &windows[windows.len() - 1];
+    let n = windows.len() as f64;
+    let sum = |field: fn(&WindowFeature) -> usize| windows.iter().map(field).sum::<usize>();
+    let sum_bytes = |field: fn(&WindowFeature) -> u64| windows.iter().map(field).sum::<u64>();
+    let max = |field: fn(&WindowFeature) -> usize| windows.iter().map(field).max().unwrap_or(0);
+    let mean = |field: fn(&WindowFeature) -> f64| windows.iter().map(field).sum::<f64>() / n;
+
+    // Pooled size statistics, weighting each window by its sized packets
+    let sized: Vec<f64> = windows.iter().map(|w| w.packet_size_distribution.total() as f64).collect();
+    let sized_total: f64 = sized.iter().sum();
+    let (avg_packet_size, packet_size_std) = if sized_total > 0.0 {
+        let mean_size = windows.iter().zip(&sized).map(|(w, k)| w.avg_packet_size * k).sum::<f64>() / sized_total;
+        let variance = windows
+            .iter()
+            .zip(&sized)
+            .map(|(w, k)| k * (w.packet_size_std.powi(2) + (w.avg_packet_size - mean_size).powi(2)))
+            .sum::<f64>()
+            / sized_total;
+        (mean_size, variance.sqrt())
+    } else {
+        (0.0, 0.0)
+    };
+    let sized_mean = |field: fn(&WindowFeature) -> f64| {
+        if sized_total > 0.0 {
+            windows.iter().zip(&sized).map(|(w, k)| field(w) * k).sum::<f64>() / sized_total
+        } else {
+            0.0
+        }
+    };
+    let packet_count = sum(|w| w.packet_count);
+    let avg_payload_packet_size = if packet_count > 0 {
+        windows.iter().map(|w| w.avg_payload_packet_size * w.packet_count as f64).sum::<f64>() / packet_count as f64
+    } else {
+        0.0
+    };
+    let min_packet_size = windows
+        .iter()
+        .zip(&sized)
+        .filter(|(_, &k)| k > 0.0)
+        .map(|(w, _)| w.min_packet_size)
+        .min()
+        .unwrap_or(0);
+
+    let answered_syn_count = sum(|w| w.answered_syn_count);
+    // flow_count is the busiest window's, so the elephants are capped by it
+    // and mice make up the rest, as within a window
+    let flow_count = max(|w| w.flow_count);
+    let elephant_flow_count = sum(|w| w.elephant_flow_count).min(flow_count);
+    let unanswered_syn_count = sum(|w| w.unanswered_syn_count);
+    let ip_packets = sum(|w| w.ip_packet_count);
+    let avg_estimated_hops = if ip_packets > 0 {
+        windows.iter().map(|w| w.avg_estimated_hops * w.ip_packet_count as f64).sum::<f64>() / ip_packets as f64
+    } else {
+        0.0
+    };
+
+    let mut packet_size_distribution = first.packet_size_distribution.clone();
+    let mut tcp_payload_size_distribution = first.tcp_payload_size_distribution.clone();
+    let mut flow_duration_distribution = first.flow_duration_distribution.clone();
+    let mut ip_protocol_distribution = first.ip_protocol_distribution.clone();
+    let mut quic_versions = first.quic_versions.clone();
+    let mut tcp_option_counts = first.tcp_option_counts.clone();
+    let mut sctp_chunk_counts = first.sctp_chunk_counts.clone();
+    let mut dhcp_message_counts = first.dhcp_message_counts.clone();
+    let mut src_octet_heatmap = first.src_octet_heatmap.clone();
+    let mut dst_octet_heatmap = first.dst_octet_heatmap.clone();
+    for window in &windows[1..] {
+        packet_size_distribution.merge(&window.packet_size_distribution);
+        tcp_payload_size_distribution.merge(&window.tcp_payload_size_distribution);
+        flow_duration_distribution.merge(&window.flow_duration_distribution);
+        for (protocol, count) in &window.ip_protocol_distribution {
+            *ip_protocol_distribution.entry(protocol.clone()).or_insert(0) += count;
+        }
+        for (kind, count) in &window.tcp_option_counts {
+            *tcp_option_counts.entry(kind.clone()).or_insert(0) += count;
+        }
+        for (kind, count) in &window.sctp_chunk_counts {
+            *sctp_chunk_counts.entry(kind.clone()).or_insert(0) += count;
+        }
+        for (kind, count) in &window.dhcp_message_counts {
+            *dhcp_message_counts.entry(kind.clone()).or_insert(0) += count;
+        }
+        if let (Some(merged), Some(other)) = (quic_versions.as_mut(), &window.quic_versions) {
+            for (version, count) in other {
+                *merged.entry(version.clone()).or_insert(0) += count;
+            }
+        }
+        for (merged, other) in [
+            (&mut src_octet_heatmap, &window.src_octet_heatmap),
+            (&mut dst_octet_heatmap, &window.dst_octet_heatmap),
+        ] {
+            if let (Some(merged), Some(other)) = (merged.as_mut(), other) {
+                merged.merge(other);
+            }
+        }
+    }
+    let top_flows = merge_flows(
+        windows.iter().map(|w| &w.top_flows),
+        top_n,
+        top_flows_by,
+        elephants,
+        sum_bytes(|w| w.total_bytes),
+    );
+    let shape_fingerprint = first.shape_fingerprint.as_ref().map(|_| {
+        let ratios = [mean(|w| w.tcp_ratio), mean(|w| w.udp_ratio), mean(|w| w.icmp_ratio), mean(|w| w.other_ratio)];
+        shape_fingerprint(&packet_size_distribution, ratios)
+    });
+
+    let mut smoothed: BTreeMap<String, f64> = BTreeMap::new();
+    for name in first.smoothed.keys() {
+        let values: Vec<f64> = windows.iter().filter_map(|w| w.smoothed.get(name).copied()).collect();
+        smoothed.insert(name.clone(), values.iter().sum::<f64>() / values.len() as f64);
+    }
+
+    WindowFeature {
+        window_index: first.window_index,
+        window_start: first.window_start,
+        window_end: last.window_end,
+        clock_adjustment_events: sum(|w| w.clock_adjustment_events),
+        max_gap_seconds: windows.iter().map(|w| w.max_gap_seconds).fold(0.0, f64::max),
+        has_capture_gap: windows.iter().any(|w| w.has_capture_gap),
+        packet_count,
+        total_bytes: sum_bytes(|w| w.total_bytes),
+        wire_bytes: windows.iter().map(|w| w.wire_bytes).sum(),
+        ip_packet_count: sum(|w| w.ip_packet_count),
+        ip_total_bytes: sum_bytes(|w| w.ip_total_bytes),
+        jumbo_frame_count: sum(|w| w.jumbo_frame_count),
+        avg_packet_size,
+        avg_payload_packet_size,
+        min_packet_size,
+        max_packet_size: max(|w| w.max_packet_size),
+        packet_size_std,
+        median_packet_size: sized_mean(|w| w.median_packet_size),
+        p95_packet_size: sized_mean(|w| w.p95_packet_size),
+        packet_size_bimodality: sized_mean(|w| w.packet_size_bimodality),
+        small_packet_ratio: sized_mean(|w| w.small_packet_ratio),
+        large_packet_ratio: sized_mean(|w| w.large_packet_ratio),
+        tcp_count: sum(|w| w.tcp_count),
+        udp_count: sum(|w| w.udp_count),
+        sctp_count: sum(|w| w.sctp_count),
+        quic_count: sum(|w| w.quic_count),
+        icmp_count: sum(|w| w.icmp_count),
+        other_count: sum(|w| w.other_count),
+        tcp_ratio: mean(|w| w.tcp_ratio),
+        udp_ratio: mean(|w| w.udp_ratio),
+        icmp_ratio: mean(|w| w.icmp_ratio),
+        other_ratio: mean(|w| w.other_ratio),
+        unique_src_ips: max(|w| w.unique_src_ips),
+        unique_dst_ips: max(|w| w.unique_dst_ips),
+        unique_src_ratio: mean(|w| w.unique_src_ratio),
+        unique_dst_ratio: mean(|w| w.unique_dst_ratio),
+        src_ip_entropy: mean(|w| w.src_ip_entropy),
+        dst_ip_entropy: mean(|w| w.dst_ip_entropy),
+        top_src_ips: merge_talkers(windows.iter().map(|w| &w.top_src_ips), top_n),
+        top_dst_ips: merge_talkers(windows.iter().map(|w| &w.top_dst_ips), top_n),
+        top_fanout: merge_fan(windows.iter().map(|w| &w.top_fanout), top_n),
+        top_fanin: merge_fan(windows.iter().map(|w| &w.top_fanin), top_n),
+        flow_count,
+        tcp_connection_count: sum(|w| w.tcp_connection_count),
+        answered_syn_count,
+        unanswered_syn_count,
+        unanswered_syn_ratio: syn_ratio(answered_syn_count, unanswered_syn_count),
+        max_concurrent_flows: max(|w| w.max_concurrent_flows),
+        unidirectional_flow_count: max(|w| w.unidirectional_flow_count),
+        unidirectional_flow_ratio: mean(|w| w.unidirectional_flow_ratio),
+        elephant_flow_count,
+        mice_flow_count: flow_count - elephant_flow_count,
+        flow_ratio: mean(|w| w.flow_ratio),
+        avg_flow_packets: mean(|w| w.avg_flow_packets),
+        avg_flow_bytes: mean(|w| w.avg_flow_bytes),
+        flow_size_gini: mean(|w| w.flow_size_gini),
+        flow_birth_series: windows.iter().flat_map(|w| w.flow_birth_series.iter().copied()).collect(),
+        packets_per_sec: mean(|w| w.packets_per_sec),
+        bytes_per_sec: mean(|w| w.bytes_per_sec),
+        port_diversity: windows.iter().map(|w| w.port_diversity).fold(0.0, f64::max),
+        unique_dst_ports: max(|w| w.unique_dst_ports),
+        tcp_unique_dst_ports: max(|w| w.tcp_unique_dst_ports),
+        udp_unique_dst_ports: max(|w| w.udp_unique_dst_ports),
+        unique_dst_ports_per_src: max(|w| w.unique_dst_ports_per_src),
+        tcp_syn_count: sum(|w| w.tcp_syn_count),
+        syn_rate: mean(|w| w.syn_rate),
+        connection_storm: first.connection_storm.map(|_| windows.iter().any(|w| w.connection_storm == Some(true))),
+        tcp_ack_count: sum(|w| w.tcp_ack_count),
+        tcp_rst_count: sum(|w| w.tcp_rst_count),
+        tcp_fin_count: sum(|w| w.tcp_fin_count),
+        tcp_retransmissions: sum(|w| w.tcp_retransmissions),
+        fast_retransmit_count: sum(|w| w.fast_retransmit_count),
+        timeout_retransmit_count: sum(|w| w.timeout_retransmit_count),
+        repeated_ip_id_count: windows.iter().map(|w| w.repeated_ip_id_count).sum(),
+        tcp_ece_count: sum(|w| w.tcp_ece_count),
+        tcp_cwr_count: sum(|w| w.tcp_cwr_count),
+        tcp_zero_window_count: sum(|w| w.tcp_zero_window_count),
+        tcp_option_counts,
+        sctp_chunk_counts,
+        ecn_marked_count: sum(|w| w.ecn_marked_count),
+        avg_estimated_hops,
+        port_zero_count: sum(|w| w.port_zero_count),
+        evicted_flow_count: sum(|w| w.evicted_flow_count),
+        inbound_bytes: sum_bytes(|w| w.inbound_bytes),
+        outbound_bytes: sum_bytes(|w| w.outbound_bytes),
+        internal_bytes: sum_bytes(|w| w.internal_bytes),
+        external_bytes: sum_bytes(|w| w.external_bytes),
+        broadcast_packet_count: sum(|w| w.broadcast_packet_count),
+        broadcast_bytes: sum_bytes(|w| w.broadcast_bytes),
+        multicast_packet_count: sum(|w| w.multicast_packet_count),
+        multicast_bytes: sum_bytes(|w| w.multicast_bytes),
+        mpls_labeled_count: sum(|w| w.mpls_labeled_count),
+        top_mpls_labels: merge_mpls_labels(windows.iter().map(|w| &w.top_mpls_labels), top_n),
+        dhcp_message_counts,
+        dhcp_clients: merge_dhcp_clients(windows.iter().map(|w| &w.dhcp_clients), top_n),
+        packet_size_distribution,
+        tcp_payload_size_distribution,
+        flow_duration_distribution,
+        ip_protocol_distribution,
+        quic_versions,
+        conversations: first.conversations.as_ref().map(|_| build_conversations(&top_flows)),
+        top_flows,
+        port_stats: merge_ports(windows.iter().map(|w| &w.port_stats), top_n, sum_bytes(|w| w.total_bytes)),
+        beaconing_candidates: merge_beaconing_candidates(windows.iter().map(|w| &w.beaconing_candidates), top_n),
+        slow_scan_candidates: first.slow_scan_candidates.as_ref().map(|_| {
+            merge_slow_scan_candidates(windows.iter().filter_map(|w| w.slow_scan_candidates.as_ref()), top_n)
+        }),
+        src_octet_heatmap,
+        dst_octet_heatmap,
+        shape_fingerprint,
+        smoothed,
+    }
+}
+
+// --------------------------
+// Feature Smoothing
+// --------------------------
+#[derive(Clone, Copy)]
+enum Smoothing {
+    /// Exponentially-weighted moving average with the given alpha in (0, 1]
+    Ema(f64),
+    /// Trailing simple moving average over the last N windows
+    MovingAverage(usize),
+}
+
+/// Metrics that get a smoothed companion field
+const SMOOTHED_METRICS: [&str; 6] = [
+    "packets_per_sec",
+    "bytes_per_sec",
+    "flow_count",
+    "unique_dst_ports",
+    "tcp_syn_count",
+    "tcp_rst_count",
+];
+
+fn smoothed_metric_values(window: &WindowFeature) -> [f64; SMOOTHED_METRICS.len()] {
+    [
+        window.packets_per_sec,
+        window.bytes_per_sec,
+        window.flow_count as f64,
+        window.unique_dst_ports as f64,
+        window.tcp_syn_count as f64,
+        window.tcp_rst_count as f64,
+    ]
+}
+
+/// Carries smoothing state across the window sequence. Only past windows
+/// contribute, so it can run as windows are finalized and works the same in
+/// JSON and JSONL mode.
+struct Smoother {
+    smoothing: Smoothing,
+    ema: Option<[f64; SMOOTHED_METRICS.len()]>,
+    history: VecDeque<[f64; SMOOTHED_METRICS.len()]>,
+}
+
+impl Smoother {
+    fn new(smoothing: Smoothing) -> Self {
+        Smoother {
+            smoothing,
+            ema: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    fn apply(&mut self, window: &mut WindowFeature) {
+        let values = smoothed_metric_values(window);
+        let (smoothed, suffix) = match self.smoothing {
+            Smoothing::Ema(alpha) => {
+                // Seeded with the first window rather than zero
+                let next = match self.ema {
+                    Some(prev) => {
+                        let mut next = prev;
+                        for (avg, value) in next.iter_mut().zip(values) {
+                            *avg = alpha * value + (1.0 - alpha) * *avg;
+                        }
+                        next
+                    }
+                    None => values,
+                };
+                self.ema = Some(next);
+                (next, "ema")
+            }
+            Smoothing::MovingAverage(span) => {
+                self.history.push_back(values);
+                if self.history.len() > span {
+                    self.history.pop_front();
+                }
+                let mut sums = [0.0; SMOOTHED_METRICS.len()];
+                for past in &self.history {
+                    for (sum, value) in sums.iter_mut().zip(past) {
+                        *sum += value;
+                    }
+                }
+                let count = self.history.len() as f64;
+                (sums.map(|sum| sum / count), "ma")
+            }
+        };
+        for (name, value) in SMOOTHED_METRICS.iter().zip(smoothed) {
+            window.smoothed.insert(format!("{}_{}", name, suffix), value);
+        }
+    }
+}
+
+// --------------------------
+// Slow Scan Detection
+// --------------------------
+
+/// Remembers when each source last sent to each destination port, so a
+/// scan spread thinly over many windows still adds up. Ports a source hasn't
+/// touched within the horizon age out.
+struct SlowScanTracker {
+    horizon: f64,
+    threshold: usize,
+    last_seen: HashMap<IpAddr, HashMap<u16, f64>>,
+}
+
+impl SlowScanTracker {
+    fn new(horizon: f64, threshold: usize) -> Self {
+        SlowScanTracker { horizon, threshold, last_seen: HashMap::new() }
+    }
+
+    /// Fold in a finished window's ports per source, ending at `window_end`,
+    /// and list the sources now at or over the threshold
+    fn update(
+        &mut self,
+        window_ports: &HashMap<IpAddr, HashSet<u16>>,
+        window_end: f64,
+        limit: usize,
+        aggregate: Option<AggregatePrefix>,
+    ) -> Vec<SlowScanCandidate> {
+        for (&src, ports) in window_ports {
+            let seen = self.last_seen.entry(src).or_default();
+            for &port in ports {
+                seen.insert(port, window_end);
+            }
+        }
+        let cutoff = window_end - self.horizon;
+        self.last_seen.retain(|_, seen| {
+            seen.retain(|_, last| *last > cutoff);
+            !seen.is_empty()
+        });
+        let mut candidates: Vec<SlowScanCandidate> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| seen.len() >= self.threshold)
+            .map(|(src, seen)| SlowScanCandidate {
+                src_ip: format_ip(*src, aggregate),
+                distinct_dst_ports: seen.len(),
+                window_dst_ports: window_ports.get(src).map_or(0, HashSet::len),
+            })
+            .collect();
+        candidates.sort_by(slow_scan_order);
+        candidates.truncate(limit);
+        candidates
+    }
+}
+
+// --------------------------
+// Command-Line Options
+// --------------------------
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// Pretty-printed JSON array, written once processing finishes
+    Json,
+    /// One compact JSON object per line, written as each window finalizes
+    Jsonl,
+    /// A schema header line, then one `FEATURE_VECTOR_COLUMNS` array per line
+    Vector,
+}
+
+/// Sort key for `top_flows`, from `--top-flows-by`
+#[derive(Clone, Copy, PartialEq)]
+enum FlowRanking {
+    Bytes,
+    Packets,
+    Duration,
+}
+
+/// Cut-offs from `--elephant-bytes` and `--elephant-duration`: a flow at or
+/// over both is an elephant, anything else is a mouse
+#[derive(Clone, Copy)]
+struct ElephantThresholds {
+    bytes: u64,
+    duration_seconds: f64,
+}
+
+impl ElephantThresholds {
+    fn classify(self, bytes: u64, duration_seconds: f64) -> &'static str {
+        if bytes >= self.bytes && duration_seconds >= self.duration_seconds {
+            "elephant"
+        } else {
+            "mice"
+        }
+    }
+}
+
+/// How `window_index` is assigned, from `--window-index`
+#[derive(Clone, Copy, PartialEq)]
+enum WindowNumbering {
+    /// Count every finalized window, so a block takes its first window's index
+    Finalized,
+    /// Number output records 0, 1, 2, ...
+    Emitted,
+}
+
+/// What closes a window, from `--window-mode`
+#[derive(Clone, Copy, PartialEq)]
+enum WindowMode {
+    /// A fixed span of `--window-size` seconds
+    Time,
+    /// A fixed number of `--window-count` packets
+    Count,
+}
+
+/// Windows written between flushes in JSONL mode when `--flush-interval` is
+/// unset; live captures flush every window instead
+const DEFAULT_FLUSH_INTERVAL: usize = 100;
+
+/// IPv6 prefix used by `--aggregate-prefix` when only an IPv4 length is given
+const DEFAULT_AGGREGATE_PREFIX_V6: u8 = 48;
+
+/// Window length in seconds when `--window-size` is unset
+const DEFAULT_WINDOW_SIZE: f64 = 10.0; // REDUCED from 60s to get more training windows
+
+/// Packets per window in `--window-mode count` when `--window-count` is unset
+const DEFAULT_WINDOW_COUNT: usize = 10_000;
+
+/// Share of the window size a silence must exceed to set `has_capture_gap`
+/// when `--gap-fraction` is unset
+const DEFAULT_GAP_FRACTION: f64 = 0.5;
+
+/// Entries kept in each per-window top list when `--top-n` is unset
+const DEFAULT_TOP_N: usize = 10;
+
+/// Sub-intervals in `flow_birth_series` when `--flow-birth-buckets` is unset
+const DEFAULT_FLOW_BIRTH_BUCKETS: usize = 10;
+
+/// Trailing Ethernet frame check sequence that `--has-fcs` strips
+const ETHERNET_FCS_LEN: usize = 4;
+
+/// Per-frame bytes that `--wire-bytes` adds for what pcap never sees on
+/// Ethernet: 7-byte preamble, 1-byte SFD, 4-byte FCS and 12-byte inter-frame gap
+const DEFAULT_WIRE_OVERHEAD: usize = 24;
+
+/// Bytes at which a flow can be an elephant when `--elephant-bytes` is unset
+const DEFAULT_ELEPHANT_BYTES: u64 = 1_000_000;
+
+/// Seconds a flow must last to be an elephant when `--elephant-duration` is unset
+const DEFAULT_ELEPHANT_DURATION: f64 = 1.0;
+
+/// Upper bounds of the flow duration buckets, in seconds, when
+/// `--duration-buckets` is unset
+const DEFAULT_DURATION_BUCKETS: [f64; 4] = [5.0, 10.0, 20.0, 30.0];
+
+/// Distinct destination ports within the horizon that flag a slow scan when
+/// `--slow-scan-ports` is unset
+const DEFAULT_SLOW_SCAN_PORTS: usize = 50;
+
+/// How long a live capture waits for a packet before checking whether the
+/// open window has run out on the wall clock, in milliseconds
+const LIVE_POLL_MILLIS: i32 = 250;
+
+/// IP packet size above which a frame counts as jumbo (the standard Ethernet MTU)
+const DEFAULT_JUMBO_THRESHOLD: usize = 1500;
+
+/// Packets below this size count toward `small_packet_ratio` when `--small-threshold` is unset
+const DEFAULT_SMALL_THRESHOLD: usize = 100;
+
+/// Packets above this size count toward `large_packet_ratio` when `--large-threshold` is unset
+const DEFAULT_LARGE_THRESHOLD: usize = 1000;
+
+const USAGE: &str = "\
+Usage: rust_extractor <input.pcap> <output.json> [options]
+       rust_extractor <input.pcap> --benchmark [options]
+       rust_extractor <interface> <output.jsonl> --live --format jsonl [options]
+       rust_extractor --manifest <file> [<output-dir>] [options]
+       rust_extractor --use-defaults [options]
+       rust_extractor --list-interfaces
+       rust_extractor --describe-schema
+       rust_extractor --help | --version
+
+Options:
+  --config <file.json>             Read option defaults from a JSON file
+  --                               Treat every later argument as a path
+  --manifest <file>                Process every capture listed in a file
+  --jobs <n>                       Manifest entries processed in parallel (default 1)
+  --window-size <seconds>          Length of each feature window (default 10)
+  --window-mode <time|count>       Close windows by elapsed time (default) or packet count
+  --window-count <n>               Packets per window in count mode (default 10000)
+  --gap-fraction <f>               Flag windows with a silence over f of the window (default 0.5)
+  --top-n <n>                      Entries kept in each top list (default 10)
+  --top-flows-by <key>             Rank top flows by bytes (default), packets or duration
+  --window-index <mode>            Number windows as finalized (default) or as emitted
+  --flow-birth-buckets <k>         Sub-intervals for flow_birth_series (default 10)
+  --format <json|jsonl|vector>     Output a JSON array (default), one window per line,
+                                   or one numeric feature vector per line
+  --flush-interval <windows>       Flush JSONL output every N windows (default 100, or 1 with --live)
+  --flows-out <path>               Also write every flow of every window as JSON lines
+  --sqlite-out <path>              Also append windows, top flows and ports to a SQLite database
+  --split-windows                  Write each window to its own file; <output> is a directory
+  --summary-only                   Write one whole-run summary instead of the windows
+  --output-template <name>         File name for --split-windows, with {index} and {start}
+                                   (default window_{index}_{start}.json)
+  --aggregate-prefix <v4>[,<v6>]   Collapse addresses into subnets, e.g. 24 or 24,48
+  --fail-on-empty                  Exit non-zero when the capture has no packets
+  --geoip <mmdb>                   Add country/ASN to top talkers (repeatable)
+  --tcp-only                       Track only TCP; other traffic is just counted
+  --icmp-flows                     Track ICMP as flows keyed on addresses and ICMP type
+  --merge-tcp-udp-ports            Combine TCP and UDP rows for the same port
+  --unmap-ipv4                     Report IPv4-mapped IPv6 addresses as IPv4
+  --exclude-port-zero              Keep port-0 TCP/UDP packets out of flow and port stats
+  --only-ports <port,...>          Process only TCP/UDP packets to or from these ports
+  --filter-ip <ip-or-cidr>         Process only packets to or from these hosts (repeatable)
+  --local-net <cidr>               Mark a prefix as local for direction stats (repeatable)
+  --labels <file.json>             Tag flows with labels from a CIDR-to-label map
+  --heatmap                        Add per-window IPv4 /8 and /16 traffic heatmaps
+  --per-host                       Output one record per source IP per window instead
+  --max-hosts <n>                  Hosts tracked at once by --per-host (default 10000)
+  --max-flows <n>                  Evict the smallest flows once a window holds this many
+  --slow-scan-horizon <seconds>    Flag sources whose ports add up over this span of windows
+  --slow-scan-ports <n>            Distinct ports that flag a slow scan (default 50)
+  --elephant-bytes <bytes>         Bytes at which a flow can be an elephant (default 1000000)
+  --elephant-duration <seconds>    Seconds a flow must last to be an elephant (default 1)
+  --duration-buckets <s,s,...>     Upper bounds of the flow duration buckets (default 5,10,20,30)
+  --syn-rate-threshold <per-sec>   Flag windows whose syn_rate exceeds this as connection_storm
+  --quic-versions                  Break quic_count down by QUIC version
+  --track-ip-ids                   Count UDP packets that repeat an IPv4 ID in their flow
+  --shape-fingerprint              Add a unit-length size/protocol vector for clustering
+  --conversations                  Also group top_flows by address pair
+  --effective-bytes                Add flow byte totals without TCP retransmissions
+  --ip-size-stats                  Compute packet size stats over IP packets only
+  --jumbo-threshold <bytes>        IP packet size counted as jumbo above (default 1500)
+  --small-threshold <bytes>        Packets below this are small (default 100)
+  --large-threshold <bytes>        Packets above this are large (default 1000)
+  --wire-bytes                     Add per-frame Ethernet overhead to bytes_per_sec
+  --wire-overhead <bytes>          Overhead per frame for --wire-bytes (default 24)
+  --sample <n>                     Process every Nth packet, scaling counts by N
+  --sample-prob <p>                Keep each packet with probability p, scaling counts by 1/p
+  --seed <n>                       Seed for --sample-prob (default 0)
+  --block-size <windows>           Merge every N windows into one output record
+  --ema-alpha <alpha>              Add EMA-smoothed metrics, alpha in (0, 1]
+  --ma-window <windows>            Add trailing moving averages over N windows
+  --checkpoint <path>              Save resumable progress every --flush-interval windows (default 100)
+  --resume                         Continue from the --checkpoint file if there is one
+  --benchmark                      Skip output and report throughput to stderr
+  --quiet                          Print only warnings and errors
+  --verbose                        Print a summary line per window to stderr
+  --color                          Print a terminal summary to stderr even when not a TTY
+  --pcapng-direction               Take inbound/outbound from pcapng packet flags over --local-net
+  --has-fcs                        Strip the 4-byte FCS that ends each captured Ethernet frame
+  --list-interfaces                List capture interfaces and exit
+  --live                           Capture from the interface given as input, closing windows on the clock
+  --describe-schema                Print a JSON description of every output field and exit
+  --use-defaults                   Read data/raw/2023_test.pcap and write
+                                   data/processed/2023_test_features.json
+";
+
+#[derive(Clone)]
+struct Options {
+    /// Capture file to read, or with `live` the interface to capture from
+    pcap_file: String,
+    output_path: String,
+    /// Capture list to process instead of `pcap_file`; `output_path` is then
+    /// the directory for entries without their own output
+    manifest: Option<String>,
+    jobs: usize,
+    window_size: f64,
+    window_mode: WindowMode,
+    window_count: usize,
+    /// Silence, as a fraction of the window size, that sets has_capture_gap
+    gap_fraction: f64,
+    top_n: usize,
+    top_flows_by: FlowRanking,
+    window_index: WindowNumbering,
+    flow_birth_buckets: usize,
+    format: OutputFormat,
+    flush_interval: usize,
+    /// Where --checkpoint saves progress, and whether to pick it up again
+    checkpoint_path: Option<String>,
+    resume: bool,
+    /// Side file for the full per-window flow table
+    flows_out: Option<String>,
+    /// Database that --sqlite-out appends windows, flows and ports to
+    sqlite_out: Option<String>,
+    /// Per-window file names when --split-windows is on
+    split_template: Option<OutputTemplate>,
+    /// Write a RunSummary in place of the windows
+    summary_only: bool,
+    aggregate_prefix: Option<AggregatePrefix>,
+    fail_on_empty: bool,
+    /// Time extraction only: no output file is created or written
+    benchmark: bool,
+    /// Suppress progress and summary lines on stdout
+    quiet: bool,
+    /// Print the terminal summary even when stderr isn't a terminal
+    color: bool,
+    pcapng_direction: bool,
+    has_fcs: bool,
+    live: bool,
+    verbose: bool,
+    geoip_paths: Vec<String>,
+    labels_path: Option<String>,
+    local_nets: Vec<(IpAddr, u8)>,
+    ip_size_stats: bool,
+    jumbo_threshold: usize,
+    small_threshold: usize,
+    large_threshold: usize,
+    merge_tcp_udp_ports: bool,
+    unmap_ipv4: bool,
+    exclude_port_zero: bool,
+    /// Allow-list from --only-ports; empty keeps every packet
+    only_ports: HashSet<u16>,
+    /// Networks from --filter-ip; empty keeps every packet
+    filter_ips: Vec<(IpAddr, u8)>,
+    tcp_only: bool,
+    icmp_flows: bool,
+    heatmap: bool,
+    per_host: bool,
+    max_hosts: usize,
+    /// Flow table cap per window; the smallest flows are evicted beyond it
+    max_flows: Option<usize>,
+    /// Span over which --slow-scan-horizon accumulates ports per source
+    slow_scan_horizon: Option<f64>,
+    slow_scan_ports: usize,
+    elephants: ElephantThresholds,
+    /// Increasing upper bounds, in seconds, of flow_duration_distribution
+    duration_buckets: Vec<f64>,
+    /// SYNs per second above which a window is a connection storm
+    syn_rate_threshold: Option<f64>,
+    quic_versions: bool,
+    track_ip_ids: bool,
+    shape_fingerprint: bool,
+    conversations: bool,
+    effective_bytes: bool,
+    sample: usize,
+    /// Chance of keeping each packet, drawn from a generator seeded with `seed`
+    sample_prob: Option<f64>,
+    seed: u64,
+    /// Factor sampled counts are scaled up by: N for --sample, 1/p for --sample-prob
+    sample_scale: f64,
+    /// Bytes added per frame for wire-level byte counts, set by --wire-bytes
+    wire_overhead: Option<usize>,
+    smoothing: Option<Smoothing>,
+    block_size: Option<usize>,
+}
+
+/// Option defaults loaded with `--config`. Keys mirror the long flag names
+/// (with underscores); flags given on the command line take precedence.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    input: Option<String>,
+    output: Option<String>,
+    window_size: Option<f64>,
+    window_mode: Option<String>,
+    window_count: Option<usize>,
+    gap_fraction: Option<f64>,
+    top_n: Option<usize>,
+    top_flows_by: Option<String>,
+    window_index: Option<String>,
+    flow_birth_buckets: Option<usize>,
+    format: Option<String>,
+    flush_interval: Option<usize>,
+    checkpoint: Option<String>,
+    resume: Option<bool>,
+    flows_out: Option<String>,
+    sqlite_out: Option<String>,
+    split_windows: Option<bool>,
+    summary_only: Option<bool>,
+    output_template: Option<String>,
+    aggregate_prefix: Option<String>,
+    fail_on_empty: Option<bool>,
+    quiet: Option<bool>,
+    color: Option<bool>,
+    pcapng_direction: Option<bool>,
+    has_fcs: Option<bool>,
+    live: Option<bool>,
+    verbose: Option<bool>,
+    geoip: Vec<String>,
+    labels: Option<String>,
+    local_net: Vec<String>,
+    ip_size_stats: Option<bool>,
+    jumbo_threshold: Option<usize>,
+    small_threshold: Option<usize>,
+    large_threshold: Option<usize>,
+    merge_tcp_udp_ports: Option<bool>,
+    unmap_ipv4: Option<bool>,
+    exclude_port_zero: Option<bool>,
+    only_ports: Vec<u16>,
+    filter_ip: Vec<String>,
+    tcp_only: Option<bool>,
+    icmp_flows: Option<bool>,
+    heatmap: Option<bool>,
+    per_host: Option<bool>,
+    max_hosts: Option<usize>,
+    max_flows: Option<usize>,
+    slow_scan_horizon: Option<f64>,
+    slow_scan_ports: Option<usize>,
+    elephant_bytes: Option<u64>,
+    elephant_duration: Option<f64>,
+    duration_buckets: Option<Vec<f64>>,
+    syn_rate_threshold: Option<f64>,
+    quic_versions: Option<bool>,
+    track_ip_ids: Option<bool>,
+    shape_fingerprint: Option<bool>,
+    conversations: Option<bool>,
+    effective_bytes: Option<bool>,
+    sample: Option<usize>,
+    sample_prob: Option<f64>,
+    seed: Option<u64>,
+    wire_bytes: Option<bool>,
+    wire_overhead: Option<usize>,
+    ema_alpha: Option<f64>,
+    ma_window: Option<usize>,
+    block_size: Option<usize>,
+    manifest: Option<String>,
+    jobs: Option<usize>,
+}
+
+impl ConfigFile {
+    fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("cannot open config '{}': {}", path, e))?;
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| format!("invalid config '{}': {}", path, e))
+    }
+}
+
+fn flag_value<'a>(iter: &mut impl Iterator<Item = &'a String>, flag: &str) -> Result<&'a str, String> {
+    iter.next()
+        .map(|value| value.as_str())
+        .ok_or_else(|| format!("{} requires a value", flag))
+}
+
+fn parse_format(value: &str) -> Result<OutputFormat, String> {
+    match value {
+        "json" => Ok(OutputFormat::Json),
+        "jsonl" => Ok(OutputFormat::Jsonl),
+        "vector" => Ok(OutputFormat::Vector),
+        other => Err(format!("unknown --format '{}' (expected json, jsonl or vector)", other)),
+    }
+}
+
+fn parse_flow_ranking(value: &str) -> Result<FlowRanking, String> {
+    match value {
+        "bytes" => Ok(FlowRanking::Bytes),
+        "packets" => Ok(FlowRanking::Packets),
+        "duration" => Ok(FlowRanking::Duration),
+        other => Err(format!("unknown --top-flows-by '{}' (expected bytes, packets or duration)", other)),
+    }
+}
+
+fn parse_window_mode(value: &str) -> Result<WindowMode, String> {
+    match value {
+        "time" => Ok(WindowMode::Time),
+        "count" => Ok(WindowMode::Count),
+        other => Err(format!("unknown --window-mode '{}' (expected time or count)", other)),
+    }
+}
+
+fn parse_window_numbering(value: &str) -> Result<WindowNumbering, String> {
+    match value {
+        "finalized" => Ok(WindowNumbering::Finalized),
+        "emitted" => Ok(WindowNumbering::Emitted),
+        other => Err(format!("unknown --window-index '{}' (expected finalized or emitted)", other)),
+    }
+}
+
+fn parse_positive(flag: &str, value: &str, what: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&n: &usize| n > 0)
+        .ok_or_else(|| format!("{} expects a positive {}, got '{}'", flag, what, value))
+}
+
+fn config_positive(key: &str, value: Option<usize>, default: usize) -> Result<usize, String> {
+    match value {
+        Some(0) => Err(format!("config {} must be positive", key)),
+        Some(n) => Ok(n),
+        None => Ok(default),
+    }
+}
+
+/// Values a non-integer flag accepts
+#[derive(Clone, Copy)]
+enum NumberRange {
+    Positive,
+    NonNegative,
+    UnitInterval,
+}
+
+impl NumberRange {
+    fn contains(self, value: f64) -> bool {
+        match self {
+            NumberRange::Positive => value.is_finite() && value > 0.0,
+            NumberRange::NonNegative => value.is_finite() && value >= 0.0,
+            // NaN fails both comparisons
+            NumberRange::UnitInterval => value > 0.0 && value <= 1.0,
+        }
+    }
+
+    /// The range as error messages show it
+    fn describe(self) -> &'static str {
+        match self {
+            NumberRange::Positive => "> 0",
+            NumberRange::NonNegative => ">= 0",
+            NumberRange::UnitInterval => "in (0, 1]",
+        }
+    }
+}
+
+/// Parse the value of a flag like `--gap-fraction`, where `what` names the
+/// quantity ("seconds", "a fraction") for the error message
+fn parse_number(flag: &str, value: &str, what: &str, range: NumberRange) -> Result<f64, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&n| range.contains(n))
+        .ok_or_else(|| format!("{} expects {} {}, got '{}'", flag, what, range.describe(), value))
+}
+
+/// Check the config file's value for `key`, the counterpart of `parse_number`
+fn config_number(key: &str, value: Option<f64>, what: &str, range: NumberRange) -> Result<Option<f64>, String> {
+    match value {
+        Some(n) if !range.contains(n) => Err(format!("config {} must be {} {}, got {}", key, what, range.describe(), n)),
+        value => Ok(value),
+    }
+}
+
+/// Check flow duration bucket bounds: positive, finite and increasing
+fn check_duration_buckets(boundaries: Vec<f64>) -> Result<Vec<f64>, String> {
+    let positive = boundaries.iter().all(|&upper| upper.is_finite() && upper > 0.0);
+    if !boundaries.is_empty() && positive && boundaries.windows(2).all(|pair| pair[0] < pair[1]) {
+        Ok(boundaries)
+    } else {
+        let listed: Vec<String> = boundaries.iter().map(f64::to_string).collect();
+        Err(format!("--duration-buckets expects increasing positive seconds, got '{}'", listed.join(",")))
+    }
+}
+
+/// Parse a comma-separated list of bucket bounds, e.g. `0.1,0.5,1,5`
+fn parse_duration_buckets(value: &str) -> Result<Vec<f64>, String> {
+    let boundaries = value
+        .split(',')
+        .map(|upper| {
+            upper
+                .trim()
+                .parse()
+                .map_err(|_| format!("--duration-buckets expects comma-separated seconds, got '{}'", upper))
+        })
+        .collect::<Result<_, _>>()?;
+    check_duration_buckets(boundaries)
+}
+
+fn parse_prefix_len(value: &str, max: u8) -> Result<u8, String> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&len: &u8| len <= max)
+        .ok_or_else(|| format!("--aggregate-prefix expects a prefix length of 0-{}, got '{}'", max, value))
+}
+
+/// Parse `<v4-len>[,<v6-len>]`, e.g. `24` or `24,48`
+fn parse_aggregate_prefix(value: &str) -> Result<AggregatePrefix, String> {
+    let (v4, v6) = match value.split_once(',') {
+        Some((v4, v6)) => (parse_prefix_len(v4, 32)?, parse_prefix_len(v6, 128)?),
+        None => (parse_prefix_len(value, 32)?, DEFAULT_AGGREGATE_PREFIX_V6),
+    };
+    Ok(AggregatePrefix { v4, v6 })
+}
+
+/// Parse a comma-separated port list, e.g. `53,80,443`
+fn parse_port_list(value: &str) -> Result<HashSet<u16>, String> {
+    value
+        .split(',')
+        .map(|port| {
+            port.trim()
+                .parse()
+                .map_err(|_| format!("--only-ports expects comma-separated ports (0-65535), got '{}'", port))
+        })
+        .collect()
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    // The config file seeds the defaults, so find it before anything else
+    let mut config_path = None;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => config_path = Some(flag_value(&mut iter, arg)?),
+            "--" => break,
+            _ => {}
+        }
+    }
+    let config = match config_path {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+
+    let mut positional: Vec<String> = Vec::new();
+    let mut manifest = config.manifest.clone();
+    let mut jobs = config_positive("jobs", config.jobs, 1)?;
+    let mut window_size =
+        config_number("window_size", config.window_size, "seconds", NumberRange::Positive)?.unwrap_or(DEFAULT_WINDOW_SIZE);
+    let mut window_mode = config.window_mode.as_deref().map_or(Ok(WindowMode::Time), parse_window_mode)?;
+    let mut window_count = match config.window_count {
+        Some(0) => return Err("config window_count must be positive".to_string()),
+        count => count,
+    };
+    let mut gap_fraction = config_number("gap_fraction", config.gap_fraction, "a fraction", NumberRange::UnitInterval)?
+        .unwrap_or(DEFAULT_GAP_FRACTION);
+    let mut top_n = config_positive("top_n", config.top_n, DEFAULT_TOP_N)?;
+    let mut top_flows_by = config.top_flows_by.as_deref().map_or(Ok(FlowRanking::Bytes), parse_flow_ranking)?;
+    let mut window_index =
+        config.window_index.as_deref().map_or(Ok(WindowNumbering::Finalized), parse_window_numbering)?;
+    let mut flow_birth_buckets =
+        config_positive("flow_birth_buckets", config.flow_birth_buckets, DEFAULT_FLOW_BIRTH_BUCKETS)?;
+    let mut format = config.format.as_deref().map_or(Ok(OutputFormat::Json), parse_format)?;
+    if config.flush_interval == Some(0) {
+        return Err("config flush_interval must be positive".to_string());
+    }
+    let mut flush_interval = config.flush_interval;
+    let mut checkpoint_path = config.checkpoint.clone();
+    let mut resume = config.resume.unwrap_or(false);
+    let mut flows_out = config.flows_out.clone();
+    let mut sqlite_out = config.sqlite_out.clone();
+    let mut split_windows = config.split_windows.unwrap_or(false);
+    let mut summary_only = config.summary_only.unwrap_or(false);
+    let mut output_template = OutputTemplate::parse(config.output_template.as_deref().unwrap_or(DEFAULT_OUTPUT_TEMPLATE))?;
+    let mut aggregate_prefix = config.aggregate_prefix.as_deref().map(parse_aggregate_prefix).transpose()?;
+    let mut fail_on_empty = config.fail_on_empty.unwrap_or(false);
+    let mut geoip_paths = Vec::new();
+    let mut labels_path = config.labels.clone();
+    let mut local_nets = Vec::new();
+    let mut filter_ips = Vec::new();
+    let mut use_defaults = false;
+    let mut benchmark = false;
+    let mut quiet = config.quiet.unwrap_or(false);
+    let mut color = config.color.unwrap_or(false);
+    let mut pcapng_direction = config.pcapng_direction.unwrap_or(false);
+    let mut has_fcs = config.has_fcs.unwrap_or(false);
+    let mut live = config.live.unwrap_or(false);
+    let mut verbose = config.verbose.unwrap_or(false);
+    let mut ip_size_stats = config.ip_size_stats.unwrap_or(false);
+    let mut jumbo_threshold = config_positive("jumbo_threshold", config.jumbo_threshold, DEFAULT_JUMBO_THRESHOLD)?;
+    let mut small_threshold = config_positive("small_threshold", config.small_threshold, DEFAULT_SMALL_THRESHOLD)?;
+    let mut large_threshold = config_positive("large_threshold", config.large_threshold, DEFAULT_LARGE_THRESHOLD)?;
+    let mut merge_tcp_udp_ports = config.merge_tcp_udp_ports.unwrap_or(false);
+    let mut unmap_ipv4 = config.unmap_ipv4.unwrap_or(false);
+    let mut exclude_port_zero = config.exclude_port_zero.unwrap_or(false);
+    let mut only_ports: HashSet<u16> = config.only_ports.iter().copied().collect();
+    let mut tcp_only = config.tcp_only.unwrap_or(false);
+    let mut icmp_flows = config.icmp_flows.unwrap_or(false);
+    let mut heatmap = config.heatmap.unwrap_or(false);
+    let mut per_host = config.per_host.unwrap_or(false);
+    let mut max_hosts = config_positive("max_hosts", config.max_hosts, DEFAULT_MAX_HOSTS)?;
+    if config.max_flows == Some(0) {
+        return Err("config max_flows must be positive".to_string());
+    }
+    let mut max_flows = config.max_flows;
+    let mut slow_scan_horizon =
+        config_number("slow_scan_horizon", config.slow_scan_horizon, "seconds", NumberRange::Positive)?;
+    let mut slow_scan_ports = config.slow_scan_ports;
+    if slow_scan_ports == Some(0) {
+        return Err("config slow_scan_ports must be positive".to_string());
+    }
+    let mut elephant_bytes = match config.elephant_bytes {
+        Some(0) => return Err("config elephant_bytes must be positive".to_string()),
+        bytes => bytes.unwrap_or(DEFAULT_ELEPHANT_BYTES),
+    };
+    let mut elephant_duration =
+        config_number("elephant_duration", config.elephant_duration, "seconds", NumberRange::NonNegative)?
+            .unwrap_or(DEFAULT_ELEPHANT_DURATION);
+    let mut duration_buckets =
+        config.duration_buckets.map_or(Ok(DEFAULT_DURATION_BUCKETS.to_vec()), check_duration_buckets)?;
+    let mut syn_rate_threshold =
+        config_number("syn_rate_threshold", config.syn_rate_threshold, "SYNs per second", NumberRange::Positive)?;
+    let mut quic_versions = config.quic_versions.unwrap_or(false);
+    let mut track_ip_ids = config.track_ip_ids.unwrap_or(false);
+    let mut shape_fingerprint = config.shape_fingerprint.unwrap_or(false);
+    let mut conversations = config.conversations.unwrap_or(false);
+    let mut effective_bytes = config.effective_bytes.unwrap_or(false);
+    let mut sample = config_positive("sample", config.sample, 1)?;
+    let mut sample_prob = config_number("sample_prob", config.sample_prob, "a probability", NumberRange::UnitInterval)?;
+    let mut seed = config.seed;
+    let mut wire_bytes = config.wire_bytes.unwrap_or(false);
+    let mut wire_overhead = config_positive("wire_overhead", config.wire_overhead, DEFAULT_WIRE_OVERHEAD)?;
+    let mut ema_alpha = config_number("ema_alpha", config.ema_alpha, "a weight", NumberRange::UnitInterval)?;
+    if config.ma_window == Some(0) {
+        return Err("config ma_window must be positive".to_string());
+    }
+    let mut ma_window = config.ma_window;
+    if config.block_size == Some(0) {
+        return Err("config block_size must be positive".to_string());
+    }
+    let mut block_size = config.block_size;
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--config" => {
+                flag_value(&mut iter, arg)?;
+            }
+            "--manifest" => manifest = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--jobs" => jobs = parse_positive(arg, flag_value(&mut iter, arg)?, "job count")?,
+            "--window-size" => {
+                window_size = parse_number(arg, flag_value(&mut iter, arg)?, "seconds", NumberRange::Positive)?
+            }
+            "--window-mode" => window_mode = parse_window_mode(flag_value(&mut iter, arg)?)?,
+            "--window-count" => {
+                window_count = Some(parse_positive(arg, flag_value(&mut iter, arg)?, "packet count")?)
+            }
+            "--top-n" => top_n = parse_positive(arg, flag_value(&mut iter, arg)?, "entry count")?,
+            "--top-flows-by" => top_flows_by = parse_flow_ranking(flag_value(&mut iter, arg)?)?,
+            "--window-index" => window_index = parse_window_numbering(flag_value(&mut iter, arg)?)?,
+            "--flow-birth-buckets" => {
+                flow_birth_buckets = parse_positive(arg, flag_value(&mut iter, arg)?, "bucket count")?
+            }
+            "--format" => format = parse_format(flag_value(&mut iter, arg)?)?,
+            "--flush-interval" => {
+                flush_interval = Some(parse_positive(arg, flag_value(&mut iter, arg)?, "window count")?)
+            }
+            "--split-windows" => split_windows = true,
+            "--summary-only" => summary_only = true,
+            "--output-template" => {
+                output_template = OutputTemplate::parse(flag_value(&mut iter, arg)?)?;
+                split_windows = true;
+            }
+            "--aggregate-prefix" => {
+                aggregate_prefix = Some(parse_aggregate_prefix(flag_value(&mut iter, arg)?)?);
+            }
+            "--fail-on-empty" => fail_on_empty = true,
+            "--geoip" => geoip_paths.push(flag_value(&mut iter, arg)?.to_string()),
+            "--labels" => labels_path = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--local-net" => {
+                local_nets.push(parse_cidr(flag_value(&mut iter, arg)?).map_err(|e| format!("--local-net: {}", e))?)
+            }
+            "--use-defaults" => use_defaults = true,
+            "--checkpoint" => checkpoint_path = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--resume" => resume = true,
+            "--flows-out" => flows_out = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--sqlite-out" => sqlite_out = Some(flag_value(&mut iter, arg)?.to_string()),
+            "--benchmark" => benchmark = true,
+            "--quiet" => quiet = true,
+            "--verbose" => verbose = true,
+            "--color" => color = true,
+            "--pcapng-direction" => pcapng_direction = true,
+            "--has-fcs" => has_fcs = true,
+            "--live" => live = true,
+            "--ip-size-stats" => ip_size_stats = true,
+            "--jumbo-threshold" => jumbo_threshold = parse_positive(arg, flag_value(&mut iter, arg)?, "byte count")?,
+            "--small-threshold" => small_threshold = parse_positive(arg, flag_value(&mut iter, arg)?, "byte count")?,
+            "--large-threshold" => large_threshold = parse_positive(arg, flag_value(&mut iter, arg)?, "byte count")?,
+            "--merge-tcp-udp-ports" => merge_tcp_udp_ports = true,
+            "--unmap-ipv4" => unmap_ipv4 = true,
+            "--exclude-port-zero" => exclude_port_zero = true,
+            "--only-ports" => only_ports = parse_port_list(flag_value(&mut iter, arg)?)?,
+            "--filter-ip" => {
+                filter_ips.push(parse_cidr(flag_value(&mut iter, arg)?).map_err(|e| format!("--filter-ip: {}", e))?)
+            }
+            "--tcp-only" => tcp_only = true,
+            "--icmp-flows" => icmp_flows = true,
+            "--heatmap" => heatmap = true,
+            "--per-host" => per_host = true,
+            "--max-hosts" => max_hosts = parse_positive(arg, flag_value(&mut iter, arg)?, "host count")?,
+            "--max-flows" => max_flows = Some(parse_positive(arg, flag_value(&mut iter, arg)?, "flow count")?),
+            "--slow-scan-horizon" => {
+                slow_scan_horizon = Some(parse_number(arg, flag_value(&mut iter, arg)?, "seconds", NumberRange::Positive)?)
+            }
+            "--slow-scan-ports" => {
+                slow_scan_ports = Some(parse_positive(arg, flag_value(&mut iter, arg)?, "port count")?)
+            }
+            "--elephant-bytes" => {
+                elephant_bytes = parse_positive(arg, flag_value(&mut iter, arg)?, "byte count")? as u64
+            }
+            "--elephant-duration" => {
+                elephant_duration = parse_number(arg, flag_value(&mut iter, arg)?, "seconds", NumberRange::NonNegative)?
+            }
+            "--duration-buckets" => duration_buckets = parse_duration_buckets(flag_value(&mut iter, arg)?)?,
+            "--syn-rate-threshold" => {
+                let value = flag_value(&mut iter, arg)?;
+                syn_rate_threshold = Some(parse_number(arg, value, "SYNs per second", NumberRange::Positive)?);
+            }
+            "--quic-versions" => quic_versions = true,
+            "--track-ip-ids" => track_ip_ids = true,
+            "--shape-fingerprint" => shape_fingerprint = true,
+            "--conversations" => conversations = true,
+            "--effective-bytes" => effective_bytes = true,
+            "--sample" => sample = parse_positive(arg, flag_value(&mut iter, arg)?, "packet interval")?,
+            "--sample-prob" => {
+                let value = flag_value(&mut iter, arg)?;
+                sample_prob = Some(parse_number(arg, value, "a probability", NumberRange::UnitInterval)?);
+            }
+            "--seed" => {
+                let value = flag_value(&mut iter, arg)?;
+                seed = Some(value.parse().map_err(|_| format!("--seed expects a non-negative integer, got '{}'", value))?);
+            }
+            "--wire-bytes" => wire_bytes = true,
+            "--wire-overhead" => {
+                wire_overhead = parse_positive(arg, flag_value(&mut iter, arg)?, "byte count")?;
+                wire_bytes = true;
+            }
+            "--block-size" => block_size = Some(parse_positive(arg, flag_value(&mut iter, arg)?, "window count")?),
+            "--gap-fraction" => {
+                gap_fraction = parse_number(arg, flag_value(&mut iter, arg)?, "a fraction", NumberRange::UnitInterval)?
+            }
+            "--ema-alpha" => {
+                ema_alpha = Some(parse_number(arg, flag_value(&mut iter, arg)?, "a weight", NumberRange::UnitInterval)?);
+                ma_window = None;
+            }
+            "--ma-window" => {
+                ma_window = Some(parse_positive(arg, flag_value(&mut iter, arg)?, "window count")?);
+                ema_alpha = None;
+            }
+            // Paths that look like options, e.g. a capture named `--live.pcap`
+            "--" => {
+                positional.extend(iter.by_ref().cloned());
+                break;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option '{}'", arg)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    let smoothing = match (ema_alpha, ma_window) {
+        (Some(_), Some(_)) => return Err("config ema_alpha and ma_window are mutually exclusive".to_string()),
+        (Some(alpha), None) => Some(Smoothing::Ema(alpha)),
+        (None, Some(span)) => Some(Smoothing::MovingAverage(span)),
+        (None, None) => None,
+    };
+
+    // Databases and local nets named on the command line replace the config's lists
+    if geoip_paths.is_empty() {
+        geoip_paths = config.geoip;
+    }
+    if local_nets.is_empty() {
+        local_nets = config
+            .local_net
+            .iter()
+            .map(|cidr| parse_cidr(cidr).map_err(|e| format!("config local_net: {}", e)))
+            .collect::<Result<_, _>>()?;
+    }
+    if filter_ips.is_empty() {
+        filter_ips = config
+            .filter_ip
+            .iter()
+            .map(|cidr| parse_cidr(cidr).map_err(|e| format!("config filter_ip: {}", e)))
+            .collect::<Result<_, _>>()?;
+    }
+
+    if quiet && verbose {
+        return Err("--quiet cannot be combined with --verbose".to_string());
+    }
+    if per_host && (block_size.is_some() || split_windows) {
+        return Err("--per-host cannot be combined with --block-size or --split-windows".to_string());
+    }
+    if format == OutputFormat::Vector && (per_host || summary_only) {
+        return Err("--format vector cannot be combined with --per-host or --summary-only".to_string());
+    }
+    if summary_only && (per_host || split_windows || checkpoint_path.is_some()) {
+        return Err("--summary-only cannot be combined with --per-host, --split-windows or --checkpoint".to_string());
+    }
+    if sqlite_out.is_some() && (per_host || summary_only || checkpoint_path.is_some()) {
+        return Err("--sqlite-out cannot be combined with --per-host, --summary-only or --checkpoint".to_string());
+    }
+    if resume && checkpoint_path.is_none() {
+        return Err("--resume requires --checkpoint".to_string());
+    }
+    if sample_prob.is_some() && sample > 1 {
+        return Err("--sample-prob cannot be combined with --sample".to_string());
+    }
+    if seed.is_some() && sample_prob.is_none() {
+        return Err("--seed requires --sample-prob".to_string());
+    }
+    if checkpoint_path.is_some() {
+        // Resuming replays the open window, so the output must already hold
+        // every finished window and nothing may be pending across windows
+        if benchmark || (format == OutputFormat::Json && !split_windows) {
+            return Err("--checkpoint requires --format jsonl or --split-windows".to_string());
+        }
+        if per_host || block_size.is_some() {
+            return Err("--checkpoint cannot be combined with --per-host or --block-size".to_string());
+        }
+    }
+    if small_threshold > large_threshold {
+        return Err(format!(
+            "small threshold ({}) must not exceed large threshold ({})",
+            small_threshold, large_threshold
+        ));
+    }
+
+    if slow_scan_ports.is_some() && slow_scan_horizon.is_none() {
+        return Err("--slow-scan-ports requires --slow-scan-horizon".to_string());
+    }
+    if window_count.is_some() && window_mode != WindowMode::Count {
+        return Err("--window-count requires --window-mode count".to_string());
+    }
+    if jobs > 1 && manifest.is_none() {
+        return Err("--jobs requires --manifest".to_string());
+    }
+    // Every entry would write the same side file
+    if manifest.is_some() && (checkpoint_path.is_some() || flows_out.is_some()) {
+        return Err("--manifest cannot be combined with --checkpoint or --flows-out".to_string());
+    }
+    // Someone is usually watching a live capture, so each window shows up as
+    // it closes
+    let flush_interval = flush_interval.unwrap_or(if live { 1 } else { DEFAULT_FLUSH_INTERVAL });
+    if live {
+        if manifest.is_some() || checkpoint_path.is_some() || summary_only || pcapng_direction {
+            return Err(
+                "--live cannot be combined with --manifest, --checkpoint, --summary-only or --pcapng-direction".to_string()
+            );
+        }
+        // A live run ends when it is interrupted, so windows must be written as they close
+        if benchmark || (format == OutputFormat::Json && !split_windows) {
+            return Err("--live requires --format jsonl or --split-windows".to_string());
+        }
+    }
+
+    let (pcap_file, output_path) = match positional.as_slice() {
+        // Inputs come from the manifest; the one positional is a directory
+        [] if manifest.is_some() => (String::new(), ".".to_string()),
+        [output_dir] if manifest.is_some() => (String::new(), output_dir.clone()),
+        [input, output] => (input.clone(), output.clone()),
+        [input] if benchmark => (input.clone(), String::new()),
+        [] if config.input.is_some() && config.output.is_some() => {
+            (config.input.unwrap_or_default(), config.output.unwrap_or_default())
+        }
+        // The historical hardcoded paths, now only used on request
+        [] if use_defaults => ("data/raw/2023_test.pcap".to_string(), "data/processed/2023_test_features.json".to_string()),
+        [] => return Err("missing <input.pcap> and <output.json>".to_string()),
+        [_] => return Err("missing <output.json>".to_string()),
+        _ => return Err(format!("unexpected argument '{}'", positional[2])),
+    };
+
+    Ok(Options {
+        pcap_file,
+        output_path,
+        manifest,
+        jobs,
+        window_size,
+        window_mode,
+        window_count: window_count.unwrap_or(DEFAULT_WINDOW_COUNT),
+        gap_fraction,
+        top_n,
+        top_flows_by,
+        window_index,
+        flow_birth_buckets,
+        format,
+        flush_interval,
+        checkpoint_path,
+        resume,
+        flows_out,
+        sqlite_out,
+        split_template: split_windows.then_some(output_template),
+        summary_only,
+        aggregate_prefix,
+        fail_on_empty,
+        benchmark,
+        quiet,
+        color,
+        pcapng_direction,
+        has_fcs,
+        live,
+        verbose,
+        geoip_paths,
+        labels_path,
+        local_nets,
+        ip_size_stats,
+        jumbo_threshold,
+        small_threshold,
+        large_threshold,
+        merge_tcp_udp_ports,
+        unmap_ipv4,
+        exclude_port_zero,
+        only_ports,
+        filter_ips,
+        tcp_only,
+        icmp_flows,
+        heatmap,
+        per_host,
+        max_hosts,
+        max_flows,
+        slow_scan_horizon,
+        slow_scan_ports: slow_scan_ports.unwrap_or(DEFAULT_SLOW_SCAN_PORTS),
+        elephants: ElephantThresholds { bytes: elephant_bytes, duration_seconds: elephant_duration },
+        duration_buckets,
+        syn_rate_threshold,
+        quic_versions,
+        track_ip_ids,
+        shape_fingerprint,
+        conversations,
+        effective_bytes,
+        sample,
+        sample_prob,
+        seed: seed.unwrap_or(0),
+        sample_scale: sample_prob.map_or(sample as f64, |probability| 1.0 / probability),
+        wire_overhead: wire_bytes.then_some(wire_overhead),
+        smoothing,
+        block_size,
+    })
+}
+
+/// Print capture devices with their descriptions and addresses
+fn list_interfaces() -> Result<(), pcap::Error> {
+    let devices = Device::list()?;
+    if devices.is_empty() {
+        println!("No capture interfaces found (live capture may require elevated privileges)");
+    }
+    for device in devices {
+        match &device.desc {
+            Some(desc) => println!("{} - {}", device.name, desc),
+            None => println!("{}", device.name),
+        }
+        for address in &device.addresses {
+            match address.netmask {
+                Some(netmask) => println!("    {} netmask {}", address.addr, netmask),
+                None => println!("    {}", address.addr),
+            }
+        }
+    }
+    Ok(())
+}
+
+// --------------------------
+// Field Descriptions
+// --------------------------
+
+/// One output field for `--describe-schema`. `optional` fields are left out
+/// of a record unless the option named in the description is set.
+#[derive(Serialize, Clone)]
+struct FieldDescription {
+    name: Cow<'static, str>,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    optional: bool,
+    description: Cow<'static, str>,
+}
+
+const fn field(name: &'static str, kind: &'static str, description: &'static str) -> FieldDescription {
+    FieldDescription { name: Cow::Borrowed(name), kind, optional: false, description: Cow::Borrowed(description) }
+}
+
+const fn optional_field(name: &'static str, kind: &'static str, description: &'static str) -> FieldDescription {
+    FieldDescription { name: Cow::Borrowed(name), kind, optional: true, description: Cow::Borrowed(description) }
+}
+
+/// Fields of each window record, in output order except for the smoothed
+/// metrics, which `describe_schema` adds from `SMOOTHED_METRICS`
+const WINDOW_FIELDS: &[FieldDescription] = &[
+    field("window_index", "integer", "Position of the record in the stream, numbered per --window-index"),
+    field("window_start", "number", "Window start, seconds since the Unix epoch"),
+    field("window_end", "number", "Window end, seconds since the Unix epoch"),
+    field("clock_adjustment_events", "integer", "Backward clock steps that opened this window"),
+    field("max_gap_seconds", "number", "Longest silence between packets, up to the window end, in seconds"),
+    field("has_capture_gap", "boolean", "max_gap_seconds exceeds --gap-fraction of the window length"),
+    field("packet_count", "integer", "Packets in the window"),
+    field("total_bytes", "integer", "Captured bytes in the window"),
+    optional_field("wire_bytes", "integer", "Captured bytes plus per-frame overhead, with --wire-bytes"),
+    field("ip_packet_count", "integer", "Packets with a decoded IP header"),
+    field("ip_total_bytes", "integer", "Captured bytes of packets with a decoded IP header"),
+    field("jumbo_frame_count", "integer", "IP packets longer than --jumbo-threshold bytes"),
+    field("avg_packet_size", "number", "Mean frame size in bytes"),
+    field("avg_payload_packet_size", "number", "Mean frame size in bytes of packets carrying transport payload"),
+    field("min_packet_size", "integer", "Smallest frame in bytes"),
+    field("max_packet_size", "integer", "Largest frame in bytes"),
+    field("packet_size_std", "number", "Standard deviation of frame size in bytes"),
+    field("median_packet_size", "number", "Estimated median frame size in bytes (P² estimator)"),
+    field("p95_packet_size", "number", "Estimated 95th percentile frame size in bytes (P² estimator)"),
+    field("packet_size_bimodality", "number", "Sarle's bimodality coefficient of frame sizes, 0 to 1"),
+    field("small_packet_ratio", "number", "Share of frames below --small-threshold bytes"),
+    field("large_packet_ratio", "number", "Share of frames above --large-threshold bytes"),
+    field("tcp_count", "integer", "TCP packets"),
+    field("udp_count", "integer", "UDP packets"),
+    field("sctp_count", "integer", "SCTP packets"),
+    field("quic_count", "integer", "UDP/443 packets starting with a QUIC long header"),
+    field("icmp_count", "integer", "ICMP and ICMPv6 packets"),
+    field("other_count", "integer", "Packets of any other protocol, or without a decodable IP header"),
+    field("tcp_ratio", "number", "Share of packets that are TCP"),
+    field("udp_ratio", "number", "Share of packets that are UDP"),
+    field("icmp_ratio", "number", "Share of packets that are ICMP"),
+    field("other_ratio", "number", "Share of packets counted as other"),
+    field("unique_src_ips", "integer", "Distinct source addresses"),
+    field("unique_dst_ips", "integer", "Distinct destination addresses"),
+    field("unique_src_ratio", "number", "Distinct source addresses per packet"),
+    field("unique_dst_ratio", "number", "Distinct destination addresses per packet"),
+    field("src_ip_entropy", "number", "Shannon entropy in bits of packets over source addresses"),
+    field("dst_ip_entropy", "number", "Shannon entropy in bits of packets over destination addresses"),
+    field("top_src_ips", "array", "Busiest source addresses by bytes: ip, packet_count, total_bytes, and country/asn with --geoip"),
+    field("top_dst_ips", "array", "Busiest destination addresses by bytes, as top_src_ips"),
+    field("top_fanout", "array", "Sources contacting the most distinct destinations: ip, distinct_peers"),
+    field("top_fanin", "array", "Destinations reached by the most distinct sources: ip, distinct_peers"),
+    field("flow_count", "integer", "Distinct unidirectional flows"),
+    field("tcp_connection_count", "integer", "TCP flows whose first packet was a SYN"),
+    field("answered_syn_count", "integer", "Flows that sent a SYN whose reverse flow sent a SYN-ACK"),
+    field("unanswered_syn_count", "integer", "Flows that sent a SYN with no SYN-ACK in reply"),
+    field("unanswered_syn_ratio", "number", "Share of SYN-sending flows left unanswered"),
+    field("max_concurrent_flows", "integer", "Most flows active at the same moment"),
+    field("unidirectional_flow_count", "integer", "Flows with no traffic in the reverse direction"),
+    field("unidirectional_flow_ratio", "number", "Share of flows that are unidirectional"),
+    field("elephant_flow_count", "integer", "Flows at or over --elephant-bytes and --elephant-duration"),
+    field("mice_flow_count", "integer", "All other flows"),
+    field("flow_ratio", "number", "Flows per packet"),
+    field("avg_flow_packets", "number", "Mean packets per flow"),
+    field("avg_flow_bytes", "number", "Mean bytes per flow"),
+    field("flow_size_gini", "number", "Gini coefficient of bytes per flow, 0 (even) to 1 (one flow carries all)"),
+    field("flow_birth_series", "array", "New flows in each of --flow-birth-buckets equal sub-intervals"),
+    field("packets_per_sec", "number", "Packets per second of window length"),
+    field("bytes_per_sec", "number", "Bytes (wire bytes with --wire-bytes) per second of window length"),
+    field("port_diversity", "number", "Distinct destination port and protocol pairs"),
+    field("unique_dst_ports", "integer", "Distinct TCP, UDP and SCTP destination ports"),
+    field("tcp_unique_dst_ports", "integer", "Distinct TCP destination ports"),
+    field("udp_unique_dst_ports", "integer", "Distinct UDP destination ports"),
+    field("unique_dst_ports_per_src", "integer", "Most distinct destination ports contacted by one source"),
+    field("tcp_syn_count", "integer", "TCP segments with SYN set"),
+    field("syn_rate", "number", "tcp_syn_count per second of window length"),
+    optional_field("connection_storm", "boolean", "syn_rate exceeds --syn-rate-threshold, with --syn-rate-threshold"),
+    field("tcp_ack_count", "integer", "TCP segments with ACK set"),
+    field("tcp_rst_count", "integer", "TCP segments with RST set"),
+    field("tcp_fin_count", "integer", "TCP segments with FIN set"),
+    field("tcp_retransmissions", "integer", "TCP segments resending already-seen sequence space"),
+    field("fast_retransmit_count", "integer", "Retransmissions preceded by three duplicate ACKs"),
+    field("timeout_retransmit_count", "integer", "Retransmissions after at least 200 ms of quiet"),
+    optional_field("repeated_ip_id_count", "integer", "UDP packets repeating an IPv4 ID within their flow, with --track-ip-ids"),
+    field("tcp_ece_count", "integer", "TCP segments with ECE set"),
+    field("tcp_cwr_count", "integer", "TCP segments with CWR set"),
+    field("tcp_zero_window_count", "integer", "TCP segments advertising a zero receive window"),
+    field("tcp_option_counts", "object", "SYN and SYN-ACK segments carrying each TCP option, by option name"),
+    field("sctp_chunk_counts", "object", "SCTP chunks by chunk type name"),
+    field("ecn_marked_count", "integer", "IP packets marked Congestion Experienced"),
+    field("avg_estimated_hops", "number", "Mean hops from the sender, estimated from TTL or hop limit"),
+    field("port_zero_count", "integer", "TCP and UDP packets with source or destination port 0"),
+    field("evicted_flow_count", "integer", "Flows dropped from the flow table by --max-flows"),
+    field("inbound_bytes", "integer", "IP bytes from external to --local-net addresses, or received with --pcapng-direction"),
+    field("outbound_bytes", "integer", "IP bytes from --local-net to external addresses, or sent with --pcapng-direction"),
+    field("internal_bytes", "integer", "IP bytes between --local-net addresses"),
+    field("external_bytes", "integer", "IP bytes with neither side in --local-net"),
+    field("broadcast_packet_count", "integer", "Frames to the broadcast MAC or 255.255.255.255"),
+    field("broadcast_bytes", "integer", "Captured bytes of broadcast frames"),
+    field("multicast_packet_count", "integer", "Frames to a multicast MAC, 224.0.0.0/4 or ff00::/8"),
+    field("multicast_bytes", "integer", "Captured bytes of multicast frames"),
+    field("mpls_labeled_count", "integer", "Frames carrying an MPLS label stack"),
+    field("top_mpls_labels", "array", "Most common MPLS labels: label, packet_count"),
+    field("dhcp_message_counts", "object", "DHCP messages on UDP ports 67/68 by type (discover, offer, request, ack, nak, ...)"),
+    field("dhcp_clients", "array", "Clients sending DISCOVER or REQUEST, most messages first: client_mac, discover_count, request_count, requested_ip"),
+    field("packet_size_distribution", "object", "Frame counts per size bucket, by bucket upper bound in bytes"),
+    field("tcp_payload_size_distribution", "object", "TCP segments per payload size bucket, by upper bound in bytes; 0 is empty segments"),
+    field("flow_duration_distribution", "object", "Flow counts per duration bucket in seconds, bounded by --duration-buckets"),
+    field("ip_protocol_distribution", "object", "IP packets by upper-layer protocol name"),
+    optional_field("quic_versions", "object", "QUIC long-header packets by version, with --quic-versions"),
+    field("top_flows", "array", "Largest flows by --top-flows-by, see the flow fields"),
+    optional_field("conversations", "array", "top_flows grouped by address pair, most bytes first: src_ip, dst_ip, total_bytes, flows, with --conversations"),
+    field("port_stats", "array", "Busiest destination ports, see the port fields"),
+    field("beaconing_candidates", "array", "Destinations contacted at regular intervals: dst_ip, interval_regularity (0 to 1)"),
+    optional_field("slow_scan_candidates", "array", "Sources over --slow-scan-ports within the horizon: src_ip, distinct_dst_ports, window_dst_ports, with --slow-scan-horizon"),
+    optional_field("src_octet_heatmap", "object", "Packets and bytes per source IPv4 /8 and /16, with --heatmap"),
+    optional_field("dst_octet_heatmap", "object", "Packets and bytes per destination IPv4 /8 and /16, with --heatmap"),
+    optional_field("shape_fingerprint", "array", "Packet size fractions then tcp/udp/icmp/other ratios, L2-normalized, with --shape-fingerprint"),
+];
+
+const FLOW_FIELDS: &[FieldDescription] = &[
+    field("src_ip", "string", "Source address, or subnet with --aggregate-prefix"),
+    field("dst_ip", "string", "Destination address, or subnet with --aggregate-prefix"),
+    field("src_port", "integer", "Source port, 0 for ICMP"),
+    field("dst_port", "integer", "Destination port, 0 for ICMP"),
+    field("protocol", "string", "TCP, UDP, SCTP or ICMP"),
+    field("packet_count", "integer", "Packets in this direction"),
+    field("total_bytes", "integer", "Captured bytes in this direction"),
+    field("bytes_pct", "number", "Share of the window's total_bytes, in percent"),
+    field("duration_seconds", "number", "Seconds from the first to the last packet"),
+    field("flow_class", "string", "elephant at or over --elephant-bytes and --elephant-duration, otherwise mice"),
+    field("start_timestamp", "number", "First packet, seconds since the Unix epoch"),
+    field("end_timestamp", "number", "Last packet, seconds since the Unix epoch"),
+    field("estimated_rtt_ms", "number", "SYN to SYN-ACK delay in milliseconds, -1 when unavailable"),
+    field("time_to_first_byte_ms", "number", "SYN to first payload delay in milliseconds, -1 when unavailable"),
+    field("push_count", "integer", "TCP segments with PSH set"),
+    field("retransmitted_bytes", "integer", "Captured bytes of TCP segments detected as retransmissions"),
+    optional_field("effective_bytes", "integer", "total_bytes less retransmitted_bytes, with --effective-bytes"),
+    field("max_packet_size", "integer", "Largest frame in bytes, taken as the path MTU"),
+    field("mtu_sized_packet_count", "integer", "Frames within 8 bytes of max_packet_size"),
+    optional_field("icmp_type", "integer", "ICMP type, on ICMP flows with --icmp-flows"),
+    optional_field("src_label", "string", "Label of the source address, with --labels"),
+    optional_field("dst_label", "string", "Label of the destination address, with --labels"),
+];
+
+const PORT_FIELDS: &[FieldDescription] = &[
+    field("port", "integer", "Destination port"),
+    field("protocol", "string", "TCP, UDP or SCTP, or TCP+UDP with --merge-tcp-udp-ports"),
+    field("service_name", "string", "Well-known service on the port, or Unknown"),
+    field("packet_count", "integer", "Packets to the port"),
+    field("total_bytes", "integer", "Captured bytes to the port"),
+    field("bytes_pct", "number", "Share of the window's total_bytes, in percent"),
+    field("distinct_flows", "integer", "Distinct flows to the port"),
+    field("distinct_src_ips", "integer", "Distinct source addresses sending to the port"),
+];
+
+/// The `--describe-schema` document: every field of the window, flow and
+/// port records
+#[derive(Serialize)]
+struct SchemaDescription {
+    window: Vec<FieldDescription>,
+    flow: &'static [FieldDescription],
+    port: &'static [FieldDescription],
+}
+
+fn describe_schema() -> SchemaDescription {
+    let mut window = WINDOW_FIELDS.to_vec();
+    for (suffix, flag, what) in [("ema", "--ema-alpha", "Exponential moving average"), ("ma", "--ma-window", "Trailing moving average")] {
+        for metric in SMOOTHED_METRICS {
+            window.push(FieldDescription {
+                name: Cow::Owned(format!("{}_{}", metric, suffix)),
+                kind: "number",
+                optional: true,
+                description: Cow::Owned(format!("{} of {}, with {}", what, metric, flag)),
+            });
+        }
+    }
+    SchemaDescription { window, flow: FLOW_FIELDS, port: PORT_FIELDS }
+}
+
+// --------------------------
+// Output Writers
+// --------------------------
+
+/// Version of the `--format vector` column list; bumped whenever a column
+/// is added, removed or moved
+const FEATURE_VECTOR_SCHEMA_VERSION: u32 = 4;
+
+/// Columns of `--format vector`, in output order: every scalar numeric
+/// window field. Lists, maps and optional fields are left out so the length
+/// never varies; booleans are 0 or 1.
+const FEATURE_VECTOR_COLUMNS: [&str; 82] = [
+    "window_index",
+    "window_start",
+    "window_end",
+    "clock_adjustment_events",
+    "max_gap_seconds",
+    "has_capture_gap",
+    "packet_count",
+    "total_bytes",
+    "ip_packet_count",
+    "ip_total_bytes",
+    "jumbo_frame_count",
+    "avg_packet_size",
+    "avg_payload_packet_size",
+    "min_packet_size",
+    "max_packet_size",
+    "packet_size_std",
+    "median_packet_size",
+    "p95_packet_size",
+    "packet_size_bimodality",
+    "small_packet_ratio",
+    "large_packet_ratio",
+    "tcp_count",
+    "udp_count",
+    "sctp_count",
+    "quic_count",
+    "icmp_count",
+    "other_count",
+    "tcp_ratio",
+    "udp_ratio",
+    "icmp_ratio",
+    "other_ratio",
+    "unique_src_ips",
+    "unique_dst_ips",
+    "unique_src_ratio",
+    "unique_dst_ratio",
+    "src_ip_entropy",
+    "dst_ip_entropy",
+    "flow_count",
+    "tcp_connection_count",
+    "answered_syn_count",
+    "unanswered_syn_count",
+    "unanswered_syn_ratio",
+    "max_concurrent_flows",
+    "unidirectional_flow_count",
+    "unidirectional_flow_ratio",
+    "elephant_flow_count",
+    "mice_flow_count",
+    "flow_ratio",
+    "avg_flow_packets",
+    "avg_flow_bytes",
+    "flow_size_gini",
+    "packets_per_sec",
+    "bytes_per_sec",
+    "port_diversity",
+    "unique_dst_ports",
+    "tcp_unique_dst_ports",
+    "udp_unique_dst_ports",
+    "unique_dst_ports_per_src",
+    "tcp_syn_count",
+    "syn_rate",
+    "tcp_ack_count",
+    "tcp_rst_count",
+    "tcp_fin_count",
+    "tcp_retransmissions",
+    "fast_retransmit_count",
+    "timeout_retransmit_count",
+    "tcp_ece_count",
+    "tcp_cwr_count",
+    "tcp_zero_window_count",
+    "ecn_marked_count",
+    "avg_estimated_hops",
+    "port_zero_count",
+    "evicted_flow_count",
+    "inbound_bytes",
+    "outbound_bytes",
+    "internal_bytes",
+    "external_bytes",
+    "broadcast_packet_count",
+    "broadcast_bytes",
+    "multicast_packet_count",
+    "multicast_bytes",
+    "mpls_labeled_count",
+];
+
+fn feature_vector(window: &WindowFeature) -> [f64; FEATURE_VECTOR_COLUMNS.len()] {
+    [
+        window.window_index as f64,
+        window.window_start,
+        window.window_end,
+        window.clock_adjustment_events as f64,
+        window.max_gap_seconds,
+        f64::from(u8::from(window.has_capture_gap)),
+        window.packet_count as f64,
+        window.total_bytes as f64,
+        window.ip_packet_count as f64,
+        window.ip_total_bytes as f64,
+        window.jumbo_frame_count as f64,
+        window.avg_packet_size,
+        window.avg_payload_packet_size,
+        window.min_packet_size as f64,
+        window.max_packet_size as f64,
+        window.packet_size_std,
+        window.median_packet_size,
+        window.p95_packet_size,
+        window.packet_size_bimodality,
+        window.small_packet_ratio,
+        window.large_packet_ratio,
+        window.tcp_count as f64,
+        window.udp_count as f64,
+        window.sctp_count as f64,
+        window.quic_count as f64,
+        window.icmp_count as f64,
+        window.other_count as f64,
+        window.tcp_ratio,
+        window.udp_ratio,
+        window.icmp_ratio,
+        window.other_ratio,
+        window.unique_src_ips as f64,
+        window.unique_dst_ips as f64,
+        window.unique_src_ratio,
+        window.unique_dst_ratio,
+        window.src_ip_entropy,
+        window.dst_ip_entropy,
+        window.flow_count as f64,
+        window.tcp_connection_count as f64,
+        window.answered_syn_count as f64,
+        window.unanswered_syn_count as f64,
+        window.unanswered_syn_ratio,
+        window.max_concurrent_flows as f64,
+        window.unidirectional_flow_count as f64,
+        window.unidirectional_flow_ratio,
+        window.elephant_flow_count as f64,
+        window.mice_flow_count as f64,
+        window.flow_ratio,
+        window.avg_flow_packets,
+        window.avg_flow_bytes,
+        window.flow_size_gini,
+        window.packets_per_sec,
+        window.bytes_per_sec,
+        window.port_diversity,
+        window.unique_dst_ports as f64,
+        window.tcp_unique_dst_ports as f64,
+        window.udp_unique_dst_ports as f64,
+        window.unique_dst_ports_per_src as f64,
+        window.tcp_syn_count as f64,
+        window.syn_rate,
+        window.tcp_ack_count as f64,
+        window.tcp_rst_count as f64,
+        window.tcp_fin_count as f64,
+        window.tcp_retransmissions as f64,
+        window.fast_retransmit_count as f64,
+        window.timeout_retransmit_count as f64,
+        window.tcp_ece_count as f64,
+        window.tcp_cwr_count as f64,
+        window.tcp_zero_window_count as f64,
+        window.ecn_marked_count as f64,
+        window.avg_estimated_hops,
+        window.port_zero_count as f64,
+        window.evicted_flow_count as f64,
+        window.inbound_bytes as f64,
+        window.outbound_bytes as f64,
+        window.internal_bytes as f64,
+        window.external_bytes as f64,
+        window.broadcast_packet_count as f64,
+        window.broadcast_bytes as f64,
+        window.multicast_packet_count as f64,
+        window.multicast_bytes as f64,
+        window.mpls_labeled_count as f64,
+    ]
+}
+
+/// Header line of `--format vector` output, also written alongside it as a
+/// schema file
+#[derive(Serialize)]
+struct FeatureVectorSchema {
+    schema_version: u32,
+    columns: &'static [&'static str],
+}
+
+impl FeatureVectorSchema {
+    fn current() -> Self {
+        FeatureVectorSchema { schema_version: FEATURE_VECTOR_SCHEMA_VERSION, columns: &FEATURE_VECTOR_COLUMNS }
+    }
+}
+
+/// Where the schema file for `--format vector` goes: inside the directory
+/// with --split-windows, otherwise next to the output as `<output>.schema.json`
+fn feature_vector_schema_path(options: &Options) -> PathBuf {
+    if options.split_template.is_some() {
+        PathBuf::from(&options.output_path).join("schema.json")
+    } else {
+        PathBuf::from(format!("{}.schema.json", options.output_path))
+    }
+}
+
+/// Streams windows as JSON Lines. Flushing after every window keeps a
+/// `tail -f` consumer current but costs throughput on fast captures, so the
+/// buffer is only flushed every `flush_interval` windows.
+struct JsonlWriter {
+    writer: BufWriter<File>,
+    flush_interval: usize,
+    unflushed: usize,
+}
+
+impl JsonlWriter {
+    fn new(file: File, flush_interval: usize) -> Self {
+        JsonlWriter {
+            writer: BufWriter::new(file),
+            flush_interval,
+            unflushed: 0,
+        }
+    }
+
+    fn write_record<T: Serialize + ?Sized>(&mut self, record: &T) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.writer.write_all(b"\n")?;
+        self.unflushed += 1;
+        if self.unflushed >= self.flush_interval {
+            self.writer.flush()?;
+            self.unflushed = 0;
+        }
+        Ok(())
+    }
+
+    /// Flush everything written so far and return the output length, for a checkpoint
+    fn flush_position(&mut self) -> std::io::Result<u64> {
+        self.writer.flush()?;
+        self.unflushed = 0;
+        self.writer.get_mut().stream_position()
+    }
+
+    fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reopen JSONL output for --resume, dropping anything written after the checkpoint
+fn reopen_output(path: &str, len: u64) -> std::io::Result<File> {
+    let mut file = std::fs::OpenOptions::new().write(true).open(path)?;
+    file.set_len(len)?;
+    file.seek(SeekFrom::End(0))?;
+    Ok(file)
+}
+
+/// File name used by `--split-windows` when `--output-template` is unset
+const DEFAULT_OUTPUT_TEMPLATE: &str = "window_{index}_{start}.json";
+
+#[derive(Clone, Debug, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Index,
+    Start,
+}
+
+/// Per-window file name pattern for `--split-windows`. `{index}` is the
+/// 0-based output record number, `{start}` the window start timestamp.
+#[derive(Clone, Debug, PartialEq)]
+struct OutputTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl OutputTemplate {
+    fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while !rest.is_empty() {
+            let Some(open) = rest.find(['{', '}']) else {
+                parts.push(TemplatePart::Literal(rest.to_string()));
+                break;
+            };
+            if open > 0 {
+                parts.push(TemplatePart::Literal(rest[..open].to_string()));
+            }
+            let close = match rest[open..].find('}') {
+                Some(offset) if rest[open..].starts_with('{') => open + offset,
+                _ => return Err(format!("--output-template '{}' has an unmatched brace", template)),
+            };
+            parts.push(match &rest[open + 1..close] {
+                "index" => TemplatePart::Index,
+                "start" => TemplatePart::Start,
+                other => {
+                    return Err(format!(
+                        "--output-template '{}' uses unknown placeholder {{{}}} (expected {{index}} or {{start}})",
+                        template, other
+                    ))
+                }
+            });
+            rest = &rest[close + 1..];
+        }
+        if !parts.contains(&TemplatePart::Index) && !parts.contains(&TemplatePart::Start) {
+            return Err(format!(
+                "--output-template '{}' needs {{index}} or {{start}} so windows get distinct files",
+                template
+            ));
+        }
+        Ok(OutputTemplate { parts })
+    }
+
+    fn render(&self, index: usize, start: f64) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                TemplatePart::Literal(text) => text.clone(),
+                TemplatePart::Index => index.to_string(),
+                TemplatePart::Start => start.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Writes each window to its own file under `dir`, named by the template
+struct SplitWriter {
+    dir: PathBuf,
+    template: OutputTemplate,
+    format: OutputFormat,
+    next_index: usize,
+}
+
+impl SplitWriter {
+    fn new(dir: &str, template: OutputTemplate, format: OutputFormat) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(SplitWriter { dir: PathBuf::from(dir), template, format, next_index: 0 })
+    }
+
+    fn write_window(&mut self, window: &WindowFeature) -> std::io::Result<()> {
+        let path = self.dir.join(self.template.render(self.next_index, window.window_start));
+        let mut writer = BufWriter::new(File::create(path)?);
+        match self.format {
+            OutputFormat::Json => serde_json::to_writer_pretty(&mut writer, window)?,
+            OutputFormat::Jsonl => serde_json::to_writer(&mut writer, window)?,
+            OutputFormat::Vector => serde_json::to_writer(&mut writer, &feature_vector(window)[..])?,
+        }
+        writer.write_all(b"\n")?;
+        self.next_index += 1;
+        writer.flush()
+    }
+}
+
+// --------------------------
+// SQLite Output
+// --------------------------
+
+const SQLITE_FLOWS_TABLE: &str = "CREATE TABLE IF NOT EXISTS flows (
+    window_id INTEGER NOT NULL REFERENCES windows (id),
+    src_ip TEXT NOT NULL,
+    dst_ip TEXT NOT NULL,
+    src_port INTEGER NOT NULL,
+    dst_port INTEGER NOT NULL,
+    protocol TEXT NOT NULL,
+    icmp_type INTEGER,
+    packet_count INTEGER NOT NULL,
+    total_bytes INTEGER NOT NULL,
+    bytes_pct REAL NOT NULL,
+    duration_seconds REAL NOT NULL,
+    flow_class TEXT NOT NULL,
+    start_timestamp REAL NOT NULL,
+    end_timestamp REAL NOT NULL,
+    estimated_rtt_ms REAL NOT NULL,
+    time_to_first_byte_ms REAL NOT NULL,
+    push_count INTEGER NOT NULL,
+    retransmitted_bytes INTEGER NOT NULL,
+    effective_bytes INTEGER,
+    max_packet_size INTEGER NOT NULL,
+    mtu_sized_packet_count INTEGER NOT NULL,
+    src_label TEXT,
+    dst_label TEXT
+);
+CREATE INDEX IF NOT EXISTS flows_window_id ON flows (window_id);";
+
+const SQLITE_PORTS_TABLE: &str = "CREATE TABLE IF NOT EXISTS ports (
+    window_id INTEGER NOT NULL REFERENCES windows (id),
+    port INTEGER NOT NULL,
+    protocol TEXT NOT NULL,
+    service_name TEXT NOT NULL,
+    packet_count INTEGER NOT NULL,
+    total_bytes INTEGER NOT NULL,
+    bytes_pct REAL NOT NULL,
+    distinct_flows INTEGER NOT NULL,
+    distinct_src_ips INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ports_window_id ON ports (window_id);";
+
+/// How long a writer waits on a database locked by another run (e.g. a
+/// parallel --manifest entry) before giving up
+const SQLITE_BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+fn sqlite_error(error: rusqlite::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, error)
+}
+
+/// Appends windows to a SQLite database for --sqlite-out: one `windows` row
+/// per window holding the feature vector columns, plus its top flows and
+/// ports in `flows` and `ports` keyed by the window's row id. The tables are
+/// created on first use, so any number of runs can share one database.
+struct SqliteWriter {
+    connection: rusqlite::Connection,
+    pcap_file: String,
+    insert_window: String,
+}
+
+impl SqliteWriter {
+    fn open(path: &str, pcap_file: &str) -> std::io::Result<Self> {
+        let connection = rusqlite::Connection::open(path).map_err(sqlite_error)?;
+        connection.busy_timeout(SQLITE_BUSY_TIMEOUT).map_err(sqlite_error)?;
+        // The windows columns follow the feature vector, so a database made
+        // under another schema version can't take these rows
+        let version: u32 = connection.query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(sqlite_error)?;
+        if version != 0 && version != FEATURE_VECTOR_SCHEMA_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "database has feature vector schema version {}, this build writes {}",
+                    version, FEATURE_VECTOR_SCHEMA_VERSION
+                ),
+            ));
+        }
+        // NUMERIC affinity stores whole values (counts, byte totals) as integers
+        let columns: Vec<String> =
+            FEATURE_VECTOR_COLUMNS.iter().map(|column| format!("    {} NUMERIC NOT NULL", column)).collect();
+        let windows_table = format!(
+            "CREATE TABLE IF NOT EXISTS windows (\n    id INTEGER PRIMARY KEY,\n    pcap_file TEXT NOT NULL,\n{}\n);",
+            columns.join(",\n")
+        );
+        connection
+            .execute_batch(&format!(
+                "BEGIN;\n{}\n{}\n{}\nPRAGMA user_version = {};\nCOMMIT;",
+                windows_table, SQLITE_FLOWS_TABLE, SQLITE_PORTS_TABLE, FEATURE_VECTOR_SCHEMA_VERSION
+            ))
+            .map_err(sqlite_error)?;
+        let placeholders = vec!["?"; FEATURE_VECTOR_COLUMNS.len() + 1].join(", ");
+        let insert_window = format!(
+            "INSERT INTO windows (pcap_file, {}) VALUES ({})",
+            FEATURE_VECTOR_COLUMNS.join(", "),
+            placeholders
+        );
+        Ok(SqliteWriter { connection, pcap_file: pcap_file.to_string(), insert_window })
+    }
+
+    /// Insert a window with its flows and ports in one transaction
+    fn write_window(&mut self, window: &WindowFeature) -> std::io::Result<()> {
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        let vector = feature_vector(window);
+        let values = std::iter::once(&self.pcap_file as &dyn rusqlite::ToSql)
+            .chain(vector.iter().map(|value| value as &dyn rusqlite::ToSql));
+        transaction.execute(&self.insert_window, rusqlite::params_from_iter(values)).map_err(sqlite_error)?;
+        let window_id = transaction.last_insert_rowid();
+        {
+            let mut insert_flow = transaction
+                .prepare_cached(
+                    "INSERT INTO flows (window_id, src_ip, dst_ip, src_port, dst_port, protocol, icmp_type, \
+                     packet_count, total_bytes, bytes_pct, duration_seconds, flow_class, start_timestamp, \
+                     end_timestamp, estimated_rtt_ms, time_to_first_byte_ms, push_count, retransmitted_bytes, \
+                     effective_bytes, max_packet_size, mtu_sized_packet_count, src_label, dst_label) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, \
+                     ?20, ?21, ?22, ?23)",
+                )
+                .map_err(sqlite_error)?;
+            for flow in &window.top_flows {
+                insert_flow
+                    .execute(rusqlite::params![
+                        window_id,
+                        flow.src_ip,
+                        flow.dst_ip,
+                        flow.src_port,
+                        flow.dst_port,
+                        flow.protocol.as_str(),
+                        flow.icmp_type,
+                        flow.packet_count,
+                        flow.total_bytes,
+                        flow.bytes_pct,
+                        flow.duration_seconds,
+                        flow.flow_class,
+                        flow.start_timestamp,
+                        flow.end_timestamp,
+                        flow.estimated_rtt_ms,
+                        flow.time_to_first_byte_ms,
+                        flow.push_count,
+                        flow.retransmitted_bytes,
+                        flow.effective_bytes,
+                        flow.max_packet_size,
+                        flow.mtu_sized_packet_count,
+                        flow.src_label,
+                        flow.dst_label,
+                    ])
+                    .map_err(sqlite_error)?;
+            }
+            let mut insert_port = transaction
+                .prepare_cached(
+                    "INSERT INTO ports (window_id, port, protocol, service_name, packet_count, total_bytes, bytes_pct, \
+                     distinct_flows, distinct_src_ips) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                )
+                .map_err(sqlite_error)?;
+            for port in &window.port_stats {
+                insert_port
+                    .execute(rusqlite::params![
+                        window_id,
+                        port.port,
+                        port.protocol,
+                        port.service_name,
+                        port.packet_count,
+                        port.total_bytes,
+                        port.bytes_pct,
+                        port.distinct_flows,
+                        port.distinct_src_ips,
+                    ])
+                    .map_err(sqlite_error)?;
+            }
+        }
+        transaction.commit().map_err(sqlite_error)
+    }
+}
+
+/// End-of-run check that the window count fits the capture's timestamp span.
+/// Returns a summary line and, when the counts differ by more than 10% (and
+/// more than one window), a warning: dropped windows, timestamp gaps or clock
+/// steps all show up here.
+fn window_sanity_report(windows: usize, first: f64, last: f64, window_size: f64) -> (String, Option<String>) {
+    let span = (last - first).max(0.0);
+    let expected = ((span / window_size).ceil() as usize).max(1);
+    let summary = format!(
+        "📊 {} windows produced, {} expected from a {:.1}s timestamp span at {}s per window",
+        windows, expected, span, window_size
+    );
+    let difference = windows.abs_diff(expected);
+    let warning = (difference > 1 && difference * 10 > expected).then(|| {
+        format!(
+            "⚠️  Window count {} differs from the expected {}; the capture may have timestamp gaps \
+             (empty intervals produce no window) or clock steps (see clock_adjustment_events)",
+            windows, expected
+        )
+    });
+    (summary, warning)
+}
+
+/// Nearest-rank percentile of an ascending slice
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+/// Capacity-planning summary of per-window `bytes_per_sec`: p50/p95/p99/max
+/// across the whole run
+fn bitrate_summary(mut rates: Vec<f64>) -> Option<String> {
+    if rates.is_empty() {
+        return None;
+    }
+    rates.sort_by(f64::total_cmp);
+    Some(format!(
+        "📈 bytes_per_sec over {} windows: p50 {:.1}, p95 {:.1}, p99 {:.1}, max {:.1}",
+        rates.len(),
+        percentile(&rates, 0.50),
+        percentile(&rates, 0.95),
+        percentile(&rates, 0.99),
+        rates[rates.len() - 1]
+    ))
+}
+
+/// Whole-run totals written by `--summary-only` instead of the windows.
+/// Windows still run internally; each one is folded in as it closes.
+#[derive(Serialize, Default)]
+struct RunSummary {
+    window_count: usize,
+    start_timestamp: f64,
+    end_timestamp: f64,
+    packet_count: usize,
+    total_bytes: u64,
+    tcp_count: usize,
+    udp_count: usize,
+    sctp_count: usize,
+    icmp_count: usize,
+    other_count: usize,
+    // Busiest window, a lower bound on distinct values across the run
+    max_flow_count: usize,
+    max_unique_src_ips: usize,
+    max_unique_dst_ips: usize,
+    // Nearest-rank percentiles of per-window bytes_per_sec
+    bytes_per_sec_p50: f64,
+    bytes_per_sec_p95: f64,
+    bytes_per_sec_p99: f64,
+    bytes_per_sec_max: f64,
+    // Why packets were counted as other, see parse_error_name
+    parse_error_breakdown: BTreeMap<String, usize>,
+    // From the pcapng Interface Statistics Blocks, when the capture has any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    capture_drops: Option<CaptureDrops>,
+}
+
+impl RunSummary {
+    fn add(&mut self, window: &WindowFeature) {
+        if self.window_count == 0 {
+            self.start_timestamp = window.window_start;
+        }
+        self.window_count += 1;
+        self.end_timestamp = window.window_end;
+        self.packet_count += window.packet_count;
+        self.total_bytes += window.total_bytes;
+        self.tcp_count += window.tcp_count;
+        self.udp_count += window.udp_count;
+        self.sctp_count += window.sctp_count;
+        self.icmp_count += window.icmp_count;
+        self.other_count += window.other_count;
+        self.max_flow_count = self.max_flow_count.max(window.flow_count);
+        self.max_unique_src_ips = self.max_unique_src_ips.max(window.unique_src_ips);
+        self.max_unique_dst_ips = self.max_unique_dst_ips.max(window.unique_dst_ips);
+    }
+
+    /// Fill in the bitrate percentiles once every window has been added
+    fn finish(mut self, mut rates: Vec<f64>) -> Self {
+        if !rates.is_empty() {
+            rates.sort_by(f64::total_cmp);
+            self.bytes_per_sec_p50 = percentile(&rates, 0.50);
+            self.bytes_per_sec_p95 = percentile(&rates, 0.95);
+            self.bytes_per_sec_p99 = percentile(&rates, 0.99);
+            self.bytes_per_sec_max = rates[rates.len() - 1];
+        }
+        self
+    }
+}
+
+/// Throughput summary printed by `--benchmark`
+fn benchmark_report(packets: usize, bytes: u64, elapsed_seconds: f64) -> String {
+    let (packets_per_sec, mb_per_sec) = if elapsed_seconds > 0.0 {
+        (packets as f64 / elapsed_seconds, bytes as f64 / 1e6 / elapsed_seconds)
+    } else {
+        (0.0, 0.0)
+    };
+    format!(
+        "⏱️  Benchmark: {} packets, {} bytes in {:.3}s ({:.0} packets/sec, {:.2} MB/sec)",
+        packets, bytes, elapsed_seconds, packets_per_sec, mb_per_sec
+    )
+}
+
+/// One-line window summary printed by `--verbose` as each window closes
+fn window_summary(window: &WindowFeature) -> String {
+    let protocols = [
+        ("TCP", window.tcp_count),
+        ("UDP", window.udp_count),
+        ("SCTP", window.sctp_count),
+        ("ICMP", window.icmp_count),
+        ("other", window.other_count),
+    ];
+    let (dominant, count) = protocols.iter().fold(protocols[0], |best, &next| if next.1 > best.1 { next } else { best });
+    format!(
+        "Window {:.3}-{:.3}: {} packets, {} bytes, mostly {} ({:.0}%)",
+        window.window_start,
+        window.window_end,
+        window.packet_count,
+        window.total_bytes,
+        dominant,
+        if window.packet_count > 0 { count as f64 * 100.0 / window.packet_count as f64 } else { 0.0 }
+    )
+}
+// --------------------------
+// Terminal Summary
+// --------------------------
+
+/// Talkers kept while folding windows into the terminal summary; more than
+/// are shown, so a host that is never first in a window can still rank
+const TRIAGE_TALKERS_KEPT: usize = 50;
+
+/// Talkers shown in the terminal summary
+const TRIAGE_TALKERS_SHOWN: usize = 5;
+
+/// Width in characters of a full protocol bar in the terminal summary
+const TRIAGE_BAR_WIDTH: usize = 24;
+
+/// Run totals and anomaly flags for the end-of-run terminal summary,
+/// folded in window by window like `RunSummary`
+#[derive(Default)]
+struct TriageSummary {
+    totals: RunSummary,
+    top_talkers: Vec<TalkerStat>,
+    gap_windows: usize,
+    clock_adjustment_events: usize,
+    beaconing_destinations: BTreeSet<String>,
+    slow_scan_sources: BTreeSet<String>,
+}
+
+impl TriageSummary {
+    fn add(&mut self, window: &WindowFeature) {
+        self.totals.add(window);
+        self.top_talkers = merge_talkers([&self.top_talkers, &window.top_src_ips].into_iter(), TRIAGE_TALKERS_KEPT);
+        self.gap_windows += usize::from(window.has_capture_gap);
+        self.clock_adjustment_events += window.clock_adjustment_events;
+        self.beaconing_destinations.extend(window.beaconing_candidates.iter().map(|c| c.dst_ip.clone()));
+        for candidate in window.slow_scan_candidates.iter().flatten() {
+            self.slow_scan_sources.insert(candidate.src_ip.clone());
         }
     }
-    
-    histogram
 }
 
-type FlowKey = (String, u16, String, u16, String);
-type PortKey = (u16, String);
+/// Stderr gets ANSI colors only on a terminal, and never with NO_COLOR set
+/// (see no-color.org)
+fn stderr_color() -> bool {
+    std::io::stderr().is_terminal() && !std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
 
-#[derive(Clone)]
-struct FlowAgg {
-    packet_count: usize,
-    total_bytes: usize,
-    first_ts: f64,
-    last_ts: f64,
+/// Bytes with a decimal unit, e.g. "1.50 MB"
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KB", "MB", "GB", "TB", "PB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", value, UNITS[unit])
 }
 
-#[derive(Clone)]
-struct PortAgg {
-    packet_count: usize,
-    total_bytes: usize,
+/// End-of-run summary for a person at a terminal: totals, a protocol bar
+/// chart, the busiest sources and whatever anomaly flags were raised
+fn triage_report(summary: &TriageSummary, undecoded_packets: usize, color: bool) -> String {
+    let paint = |code: &str, text: String| if color { format!("\x1b[{}m{}\x1b[0m", code, text) } else { text };
+    let totals = &summary.totals;
+    let mut lines = vec![paint("1", "Capture summary".to_string())];
+    lines.push(format!(
+        "  {} packets, {} over {:.1}s in {} windows",
+        totals.packet_count,
+        human_bytes(totals.total_bytes),
+        (totals.end_timestamp - totals.start_timestamp).max(0.0),
+        totals.window_count
+    ));
+
+    lines.push(paint("1", "Protocols".to_string()));
+    let protocols = [
+        ("TCP", totals.tcp_count),
+        ("UDP", totals.udp_count),
+        ("SCTP", totals.sctp_count),
+        ("ICMP", totals.icmp_count),
+        ("other", totals.other_count),
+    ];
+    for (name, count) in protocols {
+        let share = if totals.packet_count > 0 { count as f64 / totals.packet_count as f64 } else { 0.0 };
+        let filled = (share * TRIAGE_BAR_WIDTH as f64).round() as usize;
+        let bar = format!("{}{}", "█".repeat(filled), "░".repeat(TRIAGE_BAR_WIDTH - filled));
+        lines.push(format!("  {:<6} {} {:>5.1}%  {}", name, paint("36", bar), share * 100.0, count));
+    }
+
+    if !summary.top_talkers.is_empty() {
+        lines.push(paint("1", "Top talkers".to_string()));
+        for talker in summary.top_talkers.iter().take(TRIAGE_TALKERS_SHOWN) {
+            lines.push(format!(
+                "  {:<39} {:>10}  {} packets",
+                talker.ip,
+                human_bytes(talker.total_bytes),
+                talker.packet_count
+            ));
+        }
+    }
+
+    let flags = [
+        (summary.gap_windows, "windows with capture gaps"),
+        (summary.clock_adjustment_events, "clock adjustments"),
+        (summary.beaconing_destinations.len(), "beaconing destinations"),
+        (summary.slow_scan_sources.len(), "slow scan sources"),
+        (undecoded_packets, "undecoded packets"),
+    ];
+    lines.push(paint("1", "Anomalies".to_string()));
+    let mut flagged = false;
+    for (count, what) in flags.iter().filter(|(count, _)| *count > 0) {
+        lines.push(format!("  {}", paint("33", format!("⚠ {} {}", count, what))));
+        flagged = true;
+    }
+    if !flagged {
+        lines.push(format!("  {}", paint("32", "none flagged".to_string())));
+    }
+    lines.join("\n")
 }
 
-#[derive(Serialize, Clone)]
-struct FlowStat {
-    src_ip: String,
-    dst_ip: String,
-    src_port: u16,
-    dst_port: u16,
-    protocol: String,
-    packet_count: usize,
-    total_bytes: usize,
-    duration_seconds: f64,
-    start_timestamp: f64,
-    end_timestamp: f64,
+
+// --------------------------
+// pcapng Metadata
+// --------------------------
+
+const PCAPNG_SECTION_HEADER: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+const PCAPNG_OBSOLETE_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_INTERFACE_STATISTICS: u32 = 5;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+// Option codes: epb_flags, and isb_ifrecv, isb_ifdrop and isb_osdrop
+const PCAPNG_EPB_FLAGS: u16 = 2;
+const PCAPNG_ISB_IFRECV: u16 = 4;
+const PCAPNG_ISB_IFDROP: u16 = 5;
+const PCAPNG_ISB_OSDROP: u16 = 7;
+
+/// Link direction a pcapng Enhanced Packet Block records for its packet
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum CaptureDirection {
+    Inbound,
+    Outbound,
 }
 
-#[derive(Serialize, Clone)]
-struct PortStat {
-    port: u16,
-    protocol: String,
-    service_name: String,
-    packet_count: usize,
-    total_bytes: usize,
+/// Counters from the capture's Interface Statistics Blocks, summed over
+/// interfaces; each interface contributes its last block, as they are cumulative
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq)]
+struct CaptureDrops {
+    // Packets the interface saw, whether or not they were captured
+    received: u64,
+    // Lost by the interface itself, and by the OS or the capture buffer
+    interface_dropped: u64,
+    os_dropped: u64,
 }
 
-fn service_name_for_port(port: u16) -> &'static str {
-    match port {
-        80 => "HTTP",
-        443 => "HTTPS",
-        53 => "DNS",
-        22 => "SSH",
-        25 => "SMTP",
-        110 => "POP3",
-        143 => "IMAP",
-        3389 => "RDP",
-        3306 => "MySQL",
-        5432 => "Postgres",
-        _ => "Unknown",
+impl CaptureDrops {
+    fn dropped(&self) -> u64 {
+        self.interface_dropped + self.os_dropped
     }
 }
 
-/// Build flow duration distribution histogram
-fn build_flow_duration_histogram(flow_stats: &HashMap<FlowKey, FlowAgg>) -> HashMap<String, usize> {
-    let mut histogram = HashMap::new();
-    histogram.insert("0-5".to_string(), 0);
-    histogram.insert("5-10".to_string(), 0);
-    histogram.insert("10-20".to_string(), 0);
-    histogram.insert("20-30".to_string(), 0);
-    histogram.insert("30+".to_string(), 0);
-    
-    for agg in flow_stats.values() {
-        let duration = (agg.last_ts - agg.first_ts).max(0.0);
-        
-        if duration <= 5.0 {
-            *histogram.get_mut("0-5").unwrap() += 1;
-        } else if duration <= 10.0 {
-            *histogram.get_mut("5-10").unwrap() += 1;
-        } else if duration <= 20.0 {
-            *histogram.get_mut("10-20").unwrap() += 1;
-        } else if duration <= 30.0 {
-            *histogram.get_mut("20-30").unwrap() += 1;
-        } else {
-            *histogram.get_mut("30+").unwrap() += 1;
+/// Walks the block structure of a pcapng file alongside libpcap, which
+/// decodes the packets but keeps the statistics blocks and the EPB flags to
+/// itself. It is read in step with the packet loop: one packet block per
+/// packet libpcap returns. Classic pcap files, and anything malformed from
+/// the point it goes wrong, simply yield nothing.
+struct PcapngMetadata<R> {
+    reader: R,
+    big_endian: bool,
+    section: usize,
+    // Latest statistics per (section, interface id)
+    statistics: BTreeMap<(usize, u32), CaptureDrops>,
+    // Body of the block just read, without the type, length and trailing length
+    body: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> PcapngMetadata<R> {
+    fn new(reader: R) -> Self {
+        PcapngMetadata {
+            reader,
+            big_endian: false,
+            section: 0,
+            statistics: BTreeMap::new(),
+            body: Vec::new(),
+            done: false,
         }
     }
-    
-    histogram
-}
 
-fn build_top_flows(flow_stats: &HashMap<FlowKey, FlowAgg>, limit: usize) -> Vec<FlowStat> {
-    let mut flows: Vec<FlowStat> = flow_stats
-        .iter()
-        .map(|(key, agg)| {
-            let duration_seconds = (agg.last_ts - agg.first_ts).max(0.0);
-            FlowStat {
-                src_ip: key.0.clone(),
-                src_port: key.1,
-                dst_ip: key.2.clone(),
-                dst_port: key.3,
-                protocol: key.4.clone(),
-                packet_count: agg.packet_count,
-                total_bytes: agg.total_bytes,
-                duration_seconds,
-                start_timestamp: agg.first_ts,
-                end_timestamp: agg.last_ts,
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.body.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.body.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64_at(&self, offset: usize) -> Option<u64> {
+        let bytes: [u8; 8] = self.body.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    /// Offset into the body of the value of option `code`, for the options
+    /// starting at `offset`
+    fn option(&self, mut offset: usize, code: u16) -> Option<usize> {
+        loop {
+            let (option_code, length) = (self.u16_at(offset)?, self.u16_at(offset + 2)? as usize);
+            // opt_endofopt
+            if option_code == 0 {
+                return None;
             }
-        })
-        .collect();
+            if option_code == code {
+                return Some(offset + 4);
+            }
+            offset += 4 + ((length + 3) & !3);
+        }
+    }
 
-    flows.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
-    flows.truncate(limit);
-    flows
+    /// Read the next block into `body` and return its type, or None at the
+    /// end of the file or at anything that isn't pcapng
+    fn read_block(&mut self) -> Option<u32> {
+        if self.done {
+            return None;
+        }
+        let block_type = self.try_read_block();
+        self.done = block_type.is_none();
+        block_type
+    }
+
+    fn try_read_block(&mut self) -> Option<u32> {
+        let mut header = [0; 8];
+        self.reader.read_exact(&mut header).ok()?;
+        let mut header_len = 8;
+        if header[..4] == PCAPNG_SECTION_HEADER {
+            // The byte-order magic settles the endianness of the whole section
+            let mut magic = [0; 4];
+            self.reader.read_exact(&mut magic).ok()?;
+            self.big_endian = match magic {
+                [0x1A, 0x2B, 0x3C, 0x4D] => true,
+                [0x4D, 0x3C, 0x2B, 0x1A] => false,
+                _ => return None,
+            };
+            self.section += 1;
+            header_len = 12;
+        } else if self.section == 0 {
+            return None;
+        }
+        let read_u32 = |bytes: [u8; 4]| if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) };
+        let block_type = read_u32(header[..4].try_into().ok()?);
+        let total_len = read_u32(header[4..].try_into().ok()?) as usize;
+        if total_len < header_len + 4 || total_len % 4 != 0 {
+            return None;
+        }
+        // Read through `take` so a corrupt length can't allocate more than the file holds
+        let body_len = total_len - header_len - 4;
+        self.body.clear();
+        (&mut self.reader).take(body_len as u64).read_to_end(&mut self.body).ok()?;
+        let mut trailer = [0; 4];
+        self.reader.read_exact(&mut trailer).ok()?;
+        (self.body.len() == body_len).then_some(block_type)
+    }
+
+    /// Record the counters of the Interface Statistics Block just read
+    fn record_statistics(&mut self) {
+        // Interface id and a timestamp come before the options
+        let Some(interface) = self.u32_at(0) else { return };
+        let counter = |code| self.option(12, code).and_then(|offset| self.u64_at(offset)).unwrap_or(0);
+        let drops = CaptureDrops {
+            received: counter(PCAPNG_ISB_IFRECV),
+            interface_dropped: counter(PCAPNG_ISB_IFDROP),
+            os_dropped: counter(PCAPNG_ISB_OSDROP),
+        };
+        self.statistics.insert((self.section, interface), drops);
+    }
+
+    /// Direction recorded for the next packet, passing any statistics blocks
+    /// on the way. None when the packet block carries no direction.
+    fn next_packet_direction(&mut self) -> Option<CaptureDirection> {
+        while let Some(block_type) = self.read_block() {
+            match block_type {
+                PCAPNG_ENHANCED_PACKET => {
+                    // Interface id, timestamp, captured and original length,
+                    // then the packet data padded to 32 bits, then the options
+                    let captured = self.u32_at(12)? as usize;
+                    let flags = self.option(20 + ((captured + 3) & !3), PCAPNG_EPB_FLAGS)?;
+                    return match self.u32_at(flags)? & 0b11 {
+                        0b01 => Some(CaptureDirection::Inbound),
+                        0b10 => Some(CaptureDirection::Outbound),
+                        _ => None,
+                    };
+                }
+                PCAPNG_SIMPLE_PACKET | PCAPNG_OBSOLETE_PACKET => return None,
+                PCAPNG_INTERFACE_STATISTICS => self.record_statistics(),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Read the rest of the file, where statistics blocks usually sit, and
+    /// total the counters of every interface that reported any
+    fn finish(mut self) -> Option<CaptureDrops> {
+        while let Some(block_type) = self.read_block() {
+            if block_type == PCAPNG_INTERFACE_STATISTICS {
+                self.record_statistics();
+            }
+        }
+        let mut totals: Option<CaptureDrops> = None;
+        for drops in self.statistics.values() {
+            let total = totals.get_or_insert_with(CaptureDrops::default);
+            total.received += drops.received;
+            total.interface_dropped += drops.interface_dropped;
+            total.os_dropped += drops.os_dropped;
+        }
+        totals
+    }
 }
 
-fn build_top_ports(port_stats: &HashMap<PortKey, PortAgg>, limit: usize) -> Vec<PortStat> {
-    let mut ports: Vec<PortStat> = port_stats
-        .iter()
-        .map(|(key, agg)| PortStat {
-            port: key.0,
-            protocol: key.1.clone(),
-            service_name: service_name_for_port(key.0).to_string(),
-            packet_count: agg.packet_count,
-            total_bytes: agg.total_bytes,
-        })
-        .collect();
+/// Warning for a capture whose statistics blocks report lost packets: every
+/// rate and count in the output undercounts the real traffic
+fn capture_drop_warning(drops: &CaptureDrops) -> Option<String> {
+    if drops.dropped() == 0 {
+        return None;
+    }
+    Some(format!(
+        "⚠️  The capture itself lost {} of {} packets received ({} by the interface, {} by the OS or capture buffer); \
+         counts and rates undercount the real traffic",
+        drops.dropped(),
+        drops.received,
+        drops.interface_dropped,
+        drops.os_dropped
+    ))
+}
 
-    ports.sort_by(|a, b| b.total_bytes.cmp(&a.total_bytes));
-    ports.truncate(limit);
-    ports
+
+// --------------------------
+// Checkpoints
+// --------------------------
+
+/// Extractor state that outlives a window, captured as a window closes.
+/// Everything else is per-window and is rebuilt by replaying the packets of
+/// the window that was open.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct ExtractorCheckpoint {
+    total_packets_processed: usize,
+    first_timestamp: Option<f64>,
+    last_timestamp: f64,
+    windows_finalized: usize,
+    undecoded_packets: usize,
+    parse_error_breakdown: BTreeMap<String, usize>,
+    /// Clock steps already charged to the window being opened
+    clock_adjustment_events: usize,
+    smoother_ema: Option<[f64; SMOOTHED_METRICS.len()]>,
+    smoother_history: Vec<[f64; SMOOTHED_METRICS.len()]>,
+    /// Per-source port last-seen times for --slow-scan-horizon
+    slow_scan_last_seen: HashMap<IpAddr, HashMap<u16, f64>>,
+    /// --sample-prob generator state as of the packet opening the window
+    sample_rng_state: Option<u64>,
+}
+
+/// A resumable point in a run: the first `packets_read` packets of the
+/// capture are fully reflected in the output written so far
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    pcap_file: String,
+    packets_read: usize,
+    windows_emitted: usize,
+    /// Length of the JSONL output; unused with --split-windows
+    output_len: u64,
+    /// Length of the --flows-out file, if any
+    flows_len: u64,
+    window_rates: Vec<f64>,
+    extractor: ExtractorCheckpoint,
+}
+
+impl Checkpoint {
+    /// The saved checkpoint, or None when there is none yet
+    fn load(path: &str) -> Result<Option<Self>, String> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("cannot read checkpoint {}: {}", path, e)),
+        };
+        serde_json::from_reader(std::io::BufReader::new(file))
+            .map(Some)
+            .map_err(|e| format!("invalid checkpoint {}: {}", path, e))
+    }
+
+    /// Write through a temporary file so an interrupted save leaves the
+    /// previous checkpoint intact
+    fn save(&self, path: &str) -> std::io::Result<()> {
+        let temp = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        std::fs::rename(temp, path)
+    }
 }
 
 // --------------------------
-// Window Feature Structure
+// Per-Host Windows
 // --------------------------
+
+/// With `--max-flows`, an over-full flow table is cut back by this fraction
+/// of the cap at once, so finding the smallest flows is not repeated on every
+/// new flow
+const FLOW_EVICTION_DIVISOR: usize = 10;
+
+/// Cap on hosts tracked at once by `--per-host` when `--max-hosts` is unset
+const DEFAULT_MAX_HOSTS: usize = 10_000;
+
+/// One source host's activity within a window (`--per-host` output record)
 #[derive(Serialize)]
-struct WindowFeature {
+struct HostWindowFeature {
+    src_ip: String,
     window_start: f64,
     window_end: f64,
     packet_count: usize,
-    total_bytes: usize,
-    avg_packet_size: f64,
-    min_packet_size: usize,
-    max_packet_size: usize,
-    packet_size_std: f64,
+    total_bytes: u64,
     tcp_count: usize,
     udp_count: usize,
+    sctp_count: usize,
     icmp_count: usize,
     other_count: usize,
-    tcp_ratio: f64,
-    udp_ratio: f64,
-    icmp_ratio: f64,
-    other_ratio: f64,
-    unique_src_ips: usize,
-    unique_dst_ips: usize,
-    unique_src_ratio: f64,
-    unique_dst_ratio: f64,
+    tcp_syn_count: usize,
     flow_count: usize,
-    flow_ratio: f64,
-    avg_flow_packets: f64,
-    avg_flow_bytes: f64,
-    packets_per_sec: f64,
-    bytes_per_sec: f64,
-    port_diversity: f64,
-    // Phase 2: TCP Health Metrics
+    unique_dst_ips: usize,
+    unique_dst_ports: usize,
+    /// The host was evicted to stay under --max-hosts, so this record covers
+    /// only part of the window and the host may reappear in a later record
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    evicted: bool,
+}
+
+#[derive(Default)]
+struct HostWindow {
+    packet_count: usize,
+    total_bytes: u64,
+    tcp_count: usize,
+    udp_count: usize,
+    sctp_count: usize,
+    icmp_count: usize,
+    other_count: usize,
     tcp_syn_count: usize,
-    tcp_ack_count: usize,
-    tcp_rst_count: usize,
-    tcp_fin_count: usize,
-    tcp_retransmissions: usize,
-    // Phase 2: Distribution Histograms
-    packet_size_distribution: HashMap<String, usize>,
-    flow_duration_distribution: HashMap<String, usize>,
-    top_flows: Vec<FlowStat>,
-    port_stats: Vec<PortStat>,
+    flows: HashSet<FlowKey>,
+    dst_ips: HashSet<IpAddr>,
+    dst_ports: HashSet<u16>,
+    /// Position in the LRU order
+    last_seen: u64,
+}
+
+/// Per-source accumulators for `--per-host`, bounded to `max_hosts` by
+/// evicting the least recently seen host
+struct HostTracker {
+    max_hosts: usize,
+    hosts: HashMap<IpAddr, HostWindow>,
+    lru: BTreeMap<u64, IpAddr>,
+    clock: u64,
+    evicted: Vec<(IpAddr, HostWindow)>,
+}
+
+impl HostTracker {
+    fn new(max_hosts: usize) -> Self {
+        HostTracker {
+            max_hosts,
+            hosts: HashMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            evicted: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, flow: FlowKey, bytes: u64, syn: bool) {
+        if !self.hosts.contains_key(&flow.src_ip) && self.hosts.len() >= self.max_hosts {
+            if let Some((_, oldest)) = self.lru.pop_first() {
+                if let Some(host) = self.hosts.remove(&oldest) {
+                    self.evicted.push((oldest, host));
+                }
+            }
+        }
+        self.clock += 1;
+        let host = self.hosts.entry(flow.src_ip).or_default();
+        self.lru.remove(&host.last_seen);
+        host.last_seen = self.clock;
+        self.lru.insert(self.clock, flow.src_ip);
+
+        host.packet_count += 1;
+        host.total_bytes += bytes;
+        match flow.protocol {
+            Protocol::Tcp => host.tcp_count += 1,
+            Protocol::Udp => host.udp_count += 1,
+            Protocol::Sctp => host.sctp_count += 1,
+            Protocol::Icmp => host.icmp_count += 1,
+            Protocol::Other => host.other_count += 1,
+        }
+        if syn {
+            host.tcp_syn_count += 1;
+        }
+        host.flows.insert(flow);
+        host.dst_ips.insert(flow.dst_ip);
+        if matches!(flow.protocol, Protocol::Tcp | Protocol::Udp | Protocol::Sctp) {
+            host.dst_ports.insert(flow.dst_port);
+        }
+    }
+
+    /// Records for every host seen in the window (evicted ones first, then
+    /// the rest by address), leaving the tracker empty for the next window
+    fn finish_window(&mut self, window_start: f64, window_end: f64, aggregate: Option<AggregatePrefix>, sample: f64) -> Vec<HostWindowFeature> {
+        let mut current: Vec<(IpAddr, HostWindow)> = self.hosts.drain().collect();
+        current.sort_by_key(|(addr, _)| *addr);
+        self.lru.clear();
+        let evicted = std::mem::take(&mut self.evicted);
+        let flagged = evicted.into_iter().map(|host| (host, true)).chain(current.into_iter().map(|host| (host, false)));
+        flagged
+            .map(|((addr, host), evicted)| HostWindowFeature {
+                src_ip: format_ip(addr, aggregate),
+                window_start,
+                window_end,
+                // Sampling scales counts, as for the aggregate windows
+                packet_count: scale_count(host.packet_count, sample),
+                total_bytes: scale_bytes(host.total_bytes, sample),
+                tcp_count: scale_count(host.tcp_count, sample),
+                udp_count: scale_count(host.udp_count, sample),
+                sctp_count: scale_count(host.sctp_count, sample),
+                icmp_count: scale_count(host.icmp_count, sample),
+                other_count: scale_count(host.other_count, sample),
+                tcp_syn_count: scale_count(host.tcp_syn_count, sample),
+                flow_count: host.flows.len(),
+                unique_dst_ips: host.dst_ips.len(),
+                unique_dst_ports: host.dst_ports.len(),
+                evicted,
+            })
+            .collect()
+    }
 }
 
 // --------------------------
-// Main Function
+// Feature Extraction
 // --------------------------
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Get command-line arguments
-    let args: Vec<String> = std::env::args().collect();
-    
-    let (pcap_file, output_path) = if args.len() >= 3 {
-        // Use command-line arguments
-        (args[1].clone(), args[2].clone())
+
+/// How far a timestamp may fall behind the window start before it is treated
+/// as a clock step rather than ordinary capture reordering
+const CLOCK_STEP_THRESHOLD_SECONDS: f64 = 1.0;
+
+/// Share of packets without a decodable IP header above which the capture is
+/// probably an unsupported link type or a corrupt file
+const UNDECODED_WARN_RATIO: f64 = 0.5;
+
+/// Beaconing heuristic: packets to a destination closer together than this
+/// (seconds) are one burst, a destination needs this many burst intervals in
+/// the window, and their coefficient of variation must not exceed the maximum
+const BEACON_BURST_GAP: f64 = 1.0;
+const BEACON_MIN_INTERVALS: usize = 4;
+const BEACON_MAX_CV: f64 = 0.1;
+
+/// Warning for a capture where most packets fell into "other" because no IP
+/// header could be decoded. `link_type` names the capture's datalink.
+fn undecoded_warning(undecoded: usize, total: usize, link_type: &str) -> Option<String> {
+    if total == 0 || (undecoded as f64 / total as f64) <= UNDECODED_WARN_RATIO {
+        return None;
+    }
+    let causes = if link_type.starts_with("EN10MB") || link_type.starts_with("LINUX_SLL") {
+        "a truncated or corrupt file, or traffic that is mostly non-IP"
     } else {
-        // Fallback to hardcoded paths
-        ("data/raw/2023_test.pcap".to_string(), "data/processed/2023_test_features.json".to_string())
+        "the link type: only Ethernet and Linux cooked (SLL, SLL2) frames are decoded, \
+         so re-capture on an Ethernet interface or convert the file"
     };
-    
-    let output_file = File::create(&output_path)?;
-    let mut writer = serde_json::Serializer::pretty(output_file);
+    Some(format!(
+        "⚠️  {} of {} packets ({:.1}%) had no decodable IP header and were counted as other. \
+         Link type is {}; likely cause: {}",
+        undecoded,
+        total,
+        undecoded as f64 / total as f64 * 100.0,
+        link_type,
+        causes
+    ))
+}
+
+/// Category of a packet counted in `parse_error_breakdown`: the etherparse
+/// error that stopped decoding, or `non_ip` for a frame that decoded fine but
+/// carried no IP header
+fn parse_error_name(error: &ReadError) -> &'static str {
+    match error {
+        ReadError::IoError(_) => "io_error",
+        ReadError::UnexpectedEndOfSlice(_) => "unexpected_end_of_slice",
+        ReadError::UnexpectedLenOfSlice { .. } => "unexpected_len_of_slice",
+        ReadError::DoubleVlanOuterNonVlanEtherType(_) => "double_vlan_outer_non_vlan_ether_type",
+        ReadError::IpUnsupportedVersion(_) => "ip_unsupported_version",
+        ReadError::Ipv4UnexpectedVersion(_) => "ipv4_unexpected_version",
+        ReadError::Ipv4HeaderLengthBad(_) => "ipv4_header_length_bad",
+        ReadError::Ipv4TotalLengthTooSmall(_) => "ipv4_total_length_too_small",
+        ReadError::Ipv6UnexpectedVersion(_) => "ipv6_unexpected_version",
+        ReadError::Ipv6TooManyHeaderExtensions => "ipv6_too_many_header_extensions",
+        ReadError::Ipv6HopByHopHeaderNotAtStart => "ipv6_hop_by_hop_header_not_at_start",
+        ReadError::IpAuthenticationHeaderTooSmallPayloadLength(_) => {
+            "ip_authentication_header_too_small_payload_length"
+        }
+        ReadError::TcpDataOffsetTooSmall(_) => "tcp_data_offset_too_small",
+        ReadError::Icmpv6PacketTooBig(_) => "icmpv6_packet_too_big",
+    }
+}
+
+/// `--verbose` line listing why packets were counted as other, most common
+/// reason first
+fn parse_error_report(breakdown: &BTreeMap<String, usize>) -> Option<String> {
+    let mut reasons: Vec<_> = breakdown.iter().collect();
+    reasons.sort_by(|a, b| b.1.cmp(a.1));
+    let total: usize = reasons.iter().map(|(_, &count)| count).sum();
+    if total == 0 {
+        return None;
+    }
+    let reasons: Vec<String> = reasons.iter().map(|(reason, count)| format!("{} {}", reason, count)).collect();
+    Some(format!("Undecoded packets: {} ({})", total, reasons.join(", ")))
+}
+
+/// SplitMix64, the generator behind --sample-prob: small and fast, and fully
+/// determined by its seed, so a sampled run can be repeated exactly
+#[derive(Clone, Copy)]
+struct SampleRng {
+    state: u64,
+}
+
+impl SampleRng {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1), from the top 53 bits
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Turns a packet stream into per-window features. Packets go in one at a
+/// time through `process_packet`, which hands back each window as it closes;
+/// `finish` flushes the last partial window.
+struct FeatureExtractor<'a> {
+    options: &'a Options,
+    /// Framing of the capture being read; Ethernet unless set from its datalink
+    link_layer: LinkLayer,
+    /// Direction pcapng recorded for the packet being processed, set before
+    /// each packet with --pcapng-direction; overrides --local-net
+    capture_direction: Option<CaptureDirection>,
+    geoip: Option<&'a GeoIp>,
+    labels: Option<&'a LabelMap>,
+    smoother: Option<Smoother>,
+    slow_scan: Option<SlowScanTracker>,
+    window_start: Option<f64>,
+    window_end: f64,
+    clock_adjustment_events: usize,
+    // Previous packet of the current window and the longest gap so far
+    last_packet_ts: Option<f64>,
+    max_gap_seconds: f64,
+    total_packets_processed: usize,
+    /// Earliest and latest timestamps of the capture, and windows finalized,
+    /// for the end-of-run sanity check
+    first_timestamp: Option<f64>,
+    last_timestamp: f64,
+    windows_finalized: usize,
+    /// Packets (whole capture) where no IP header could be decoded
+    undecoded_packets: usize,
+    /// The same packets by reason, from `parse_error_name`
+    parse_error_breakdown: BTreeMap<String, usize>,
+    sample_skip: usize,
+    /// --sample-prob generator, and its state before the latest packet's draw
+    sample_rng: Option<SampleRng>,
+    sample_rng_before: u64,
+
+    // Counters
+    packet_count: usize,
+    total_bytes: u64,
+    tcp_count: usize,
+    udp_count: usize,
+    sctp_count: usize,
+    quic_count: usize,
+    icmp_count: usize,
+    other_count: usize,
+    ip_packet_count: usize,
+    ip_total_bytes: u64,
+    jumbo_frame_count: usize,
+    payload_packet_count: usize,
+    payload_packet_bytes: u64,
+    packet_sizes: SizeStats,
+    tcp_payload_sizes: Histogram,
+    src_host_stats: HashMap<IpAddr, HostAgg>,
+    dst_host_stats: HashMap<IpAddr, HostAgg>,
+    dst_intervals: HashMap<IpAddr, BurstIntervals>,
+    flow_stats: HashMap<FlowKey, FlowAgg>,
+    port_stats: HashMap<PortKey, PortAgg>,
+    dst_ports: HashSet<u16>,
+    // Same, split by transport: TCP and UDP scans look very different
+    tcp_dst_ports: HashSet<u16>,
+    udp_dst_ports: HashSet<u16>,
+    dst_ports_by_src: HashMap<IpAddr, HashSet<u16>>,
+    // Distinct peer addresses per source and per destination
+    dsts_by_src: HashMap<IpAddr, HashSet<IpAddr>>,
+    srcs_by_dst: HashMap<IpAddr, HashSet<IpAddr>>,
+
+    // Phase 2: TCP Health Metrics counters
+    tcp_syn_count: usize,
+    tcp_ack_count: usize,
+    tcp_rst_count: usize,
+    tcp_fin_count: usize,
+    tcp_retransmissions: usize,
+    fast_retransmit_count: usize,
+    timeout_retransmit_count: usize,
+    repeated_ip_id_count: usize,
+    tcp_ece_count: usize,
+    tcp_cwr_count: usize,
+    tcp_zero_window_count: usize,
+    ecn_marked_count: usize,
+    // Sum of estimated_hops over the window's IP packets
+    hop_total: usize,
+    port_zero_count: usize,
+    evicted_flow_count: usize,
+    // Direction relative to --local-net (IP packets only)
+    inbound_bytes: u64,
+    outbound_bytes: u64,
+    internal_bytes: u64,
+    external_bytes: u64,
+    broadcast_packet_count: usize,
+    broadcast_bytes: u64,
+    multicast_packet_count: usize,
+    multicast_bytes: u64,
+
+    // MPLS label stack tracking
+    mpls_labeled_count: usize,
+    mpls_label_counts: HashMap<u32, usize>,
+    dhcp_message_counts: BTreeMap<String, usize>,
+    dhcp_clients: HashMap<String, DhcpClientStat>,
+    ip_protocol_counts: BTreeMap<String, usize>,
+    quic_version_counts: BTreeMap<String, usize>,
+    tcp_option_counts: BTreeMap<String, usize>,
+    sctp_chunk_counts: BTreeMap<String, usize>,
+    src_heatmap: OctetHeatmap,
+    dst_heatmap: OctetHeatmap,
+    // --per-host accumulators and the records of finished windows
+    hosts: Option<HostTracker>,
+    host_records: Vec<HostWindowFeature>,
+    // Every flow of finished windows, with --flows-out
+    flow_records: Vec<FlowRecord>,
+
+    // Finished windows waiting to fill a --block-size block
+    block: Vec<WindowFeature>,
+    /// With --checkpoint, the state as of the last window boundary; the
+    /// packet that crossed it opened the current window
+    boundary: Option<ExtractorCheckpoint>,
+}
+
+impl<'a> FeatureExtractor<'a> {
+    fn new(options: &'a Options, geoip: Option<&'a GeoIp>, labels: Option<&'a LabelMap>) -> Self {
+        FeatureExtractor {
+            options,
+            link_layer: LinkLayer::Ethernet,
+            capture_direction: None,
+            geoip,
+            labels,
+            smoother: options.smoothing.map(Smoother::new),
+            slow_scan: options.slow_scan_horizon.map(|horizon| SlowScanTracker::new(horizon, options.slow_scan_ports)),
+            window_start: None,
+            window_end: 0.0,
+            clock_adjustment_events: 0,
+            last_packet_ts: None,
+            max_gap_seconds: 0.0,
+            total_packets_processed: 0,
+            first_timestamp: None,
+            last_timestamp: f64::MIN,
+            windows_finalized: 0,
+            undecoded_packets: 0,
+            parse_error_breakdown: BTreeMap::new(),
+            sample_skip: 0,
+            sample_rng: options.sample_prob.map(|_| SampleRng { state: options.seed }),
+            sample_rng_before: 0,
+            packet_count: 0,
+            total_bytes: 0,
+            tcp_count: 0,
+            udp_count: 0,
+            sctp_count: 0,
+            quic_count: 0,
+            icmp_count: 0,
+            other_count: 0,
+            ip_packet_count: 0,
+            ip_total_bytes: 0,
+            jumbo_frame_count: 0,
+            payload_packet_count: 0,
+            payload_packet_bytes: 0,
+            packet_sizes: SizeStats::new(),
+            tcp_payload_sizes: Histogram::new(&TCP_PAYLOAD_SIZE_BUCKETS),
+            src_host_stats: HashMap::new(),
+            dst_host_stats: HashMap::new(),
+            dst_intervals: HashMap::new(),
+            flow_stats: HashMap::new(),
+            port_stats: HashMap::new(),
+            dst_ports: HashSet::new(),
+            tcp_dst_ports: HashSet::new(),
+            udp_dst_ports: HashSet::new(),
+            dst_ports_by_src: HashMap::new(),
+            dsts_by_src: HashMap::new(),
+            srcs_by_dst: HashMap::new(),
+            tcp_syn_count: 0,
+            tcp_ack_count: 0,
+            tcp_rst_count: 0,
+            tcp_fin_count: 0,
+            tcp_retransmissions: 0,
+            fast_retransmit_count: 0,
+            timeout_retransmit_count: 0,
+            repeated_ip_id_count: 0,
+            tcp_ece_count: 0,
+            tcp_cwr_count: 0,
+            tcp_zero_window_count: 0,
+            ecn_marked_count: 0,
+            hop_total: 0,
+            port_zero_count: 0,
+            evicted_flow_count: 0,
+            inbound_bytes: 0,
+            outbound_bytes: 0,
+            internal_bytes: 0,
+            external_bytes: 0,
+            broadcast_packet_count: 0,
+            broadcast_bytes: 0,
+            multicast_packet_count: 0,
+            multicast_bytes: 0,
+            mpls_labeled_count: 0,
+            mpls_label_counts: HashMap::new(),
+            dhcp_message_counts: BTreeMap::new(),
+            dhcp_clients: HashMap::new(),
+            ip_protocol_counts: BTreeMap::new(),
+            quic_version_counts: BTreeMap::new(),
+            tcp_option_counts: BTreeMap::new(),
+            sctp_chunk_counts: BTreeMap::new(),
+            src_heatmap: OctetHeatmap::default(),
+            dst_heatmap: OctetHeatmap::default(),
+            hosts: options.per_host.then(|| HostTracker::new(options.max_hosts)),
+            host_records: Vec::new(),
+            flow_records: Vec::new(),
+            block: Vec::new(),
+            boundary: None,
+        }
+    }
+
+    /// Feed one Ethernet frame captured at `timestamp` (seconds). Returns the
+    /// previous window if this packet closed it.
+    fn process_packet(&mut self, timestamp: f64, data: &[u8]) -> Option<WindowFeature> {
+        // Sizes and parsing both see the frame without its checksum
+        let data = match data.len().checked_sub(ETHERNET_FCS_LEN) {
+            Some(len) if self.options.has_fcs && self.link_layer == LinkLayer::Ethernet => &data[..len],
+            _ => data,
+        };
+
+        // --only-ports and --filter-ip drop everything else up front, as if
+        // it was never captured
+        if !self.passes_filters(data) {
+            return None;
+        }
+
+        // 1-in-N sampling: skipped packets don't even move the window clock
+        if self.sample_skip > 0 {
+            self.sample_skip -= 1;
+            return None;
+        }
+        self.sample_skip = self.options.sample - 1;
+        if let (Some(rng), Some(probability)) = (self.sample_rng.as_mut(), self.options.sample_prob) {
+            // A checkpoint this packet opens must replay its draw on resume
+            self.sample_rng_before = rng.state;
+            if rng.next_f64() >= probability {
+                return None;
+            }
+        }
+
+        if self.window_start.is_none() {
+            self.window_start = Some(timestamp);
+            self.window_end = self.initial_window_end(timestamp);
+        }
+
+        // A packet well before the window start means the capture host's
+        // clock stepped back (NTP correction, DST on a local-time clock).
+        // Start a fresh window rather than mixing the two timelines.
+        let clock_stepped_back = self
+            .window_start
+            .is_some_and(|start| start - timestamp > CLOCK_STEP_THRESHOLD_SECONDS);
+
+        let window_full = match self.options.window_mode {
+            WindowMode::Time => timestamp > self.window_end,
+            WindowMode::Count => self.packet_count >= self.options.window_count,
+        };
+
+        // A packet that closes the window ends its last gap at the window end
+        if let Some(last) = self.last_packet_ts {
+            let gap_end = if window_full { timestamp.min(self.window_end) } else { timestamp };
+            self.max_gap_seconds = self.max_gap_seconds.max(gap_end - last);
+        }
+        let completed = if window_full || clock_stepped_back {
+            let window = self.finalize_window();
+            self.window_start = Some(timestamp);
+            self.window_end = self.initial_window_end(timestamp);
+            if clock_stepped_back {
+                self.clock_adjustment_events += 1;
+            }
+            if self.options.checkpoint_path.is_some() {
+                self.boundary = Some(self.checkpoint());
+            }
+            self.group_into_block(window)
+        } else {
+            None
+        };
+        self.last_packet_ts = Some(timestamp);
+        // A count window ends at its latest packet
+        if self.options.window_mode == WindowMode::Count {
+            self.window_end = self.window_end.max(timestamp);
+        }
+
+        self.packet_count += 1;
+        self.total_packets_processed += 1;
+        let first_timestamp = self.first_timestamp.get_or_insert(timestamp);
+        *first_timestamp = first_timestamp.min(timestamp);
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+        self.total_bytes += data.len() as u64;
+
+        // parse headers using etherparse
+        let mut parsed = parse_frame(self.link_layer, data);
+
+        // etherparse stops at an MPLS EtherType, so strip the label stack
+        // ourselves and re-parse the inner IP header
+        let mpls_payload = match &parsed {
+            Ok(headers) if matches!(
+                headers.payload_ether_type(),
+                Some(MPLS_UNICAST_ETHER_TYPE) | Some(MPLS_MULTICAST_ETHER_TYPE)
+            ) => Some(headers.payload),
+            _ => None,
+        };
+        if let Some(payload) = mpls_payload {
+            self.mpls_labeled_count += 1;
+            if let Some((labels, inner)) = strip_mpls_labels(payload) {
+                for label in labels {
+                    *self.mpls_label_counts.entry(label).or_insert(0) += 1;
+                }
+                if let Some(inner_ether_type) = mpls_inner_ether_type(inner) {
+                    parsed = PacketHeaders::from_ether_type(inner_ether_type, inner);
+                }
+            }
+        }
 
-    let mut cap = Capture::from_file(&pcap_file)?;
-    let window_size = 10.0; // seconds - REDUCED from 60s to get more training windows
-    let mut window_start: Option<f64> = None;
-    let mut window_end: f64 = 0.0;
+        let undecoded_reason = match &parsed {
+            Err(error) => Some(parse_error_name(error)),
+            Ok(headers) if headers.ip.is_none() => Some("non_ip"),
+            Ok(_) => None,
+        };
+        if let Some(reason) = undecoded_reason {
+            *self.parse_error_breakdown.entry(reason.to_string()).or_insert(0) += 1;
+        }
 
-    // Counters
-    let mut packet_count = 0;
-    let mut total_bytes = 0;
-    let mut tcp_count = 0;
-    let mut udp_count = 0;
-    let mut icmp_count = 0;
-    let mut other_count = 0;
-    let mut packet_sizes: Vec<usize> = Vec::new();
-    let mut unique_src_ips: HashSet<String> = HashSet::new();
-    let mut unique_dst_ips: HashSet<String> = HashSet::new();
-    let mut flow_stats: HashMap<FlowKey, FlowAgg> = HashMap::new();
-    let mut port_stats: HashMap<PortKey, PortAgg> = HashMap::new();
-    let mut window_features: Vec<WindowFeature> = Vec::new();
-    
-    // Phase 2: TCP Health Metrics counters
-    let mut tcp_syn_count = 0;
-    let mut tcp_ack_count = 0;
-    let mut tcp_rst_count = 0;
-    let mut tcp_fin_count = 0;
-    let mut tcp_retransmissions = 0; // Placeholder - proper detection requires seq tracking
-    
-    // Phase 2: Flow duration tracking (stored in flow_stats)
-
-    while let Some(packet) = cap.next_packet().ok() {
-        let ts = packet.header.ts;
-        let timestamp = ts.tv_sec as f64 + ts.tv_usec as f64 * 1e-6;
-
-        if window_start.is_none() {
-            window_start = Some(timestamp);
-            window_end = window_start.unwrap() + window_size;
-        }
-
-        if timestamp > window_end {
-            // finalize current window
-            let avg_packet_size = if packet_count > 0 {
-                total_bytes as f64 / packet_count as f64
-            } else { 0.0 };
-            let min_packet_size = *packet_sizes.iter().min().unwrap_or(&0);
-            let max_packet_size = *packet_sizes.iter().max().unwrap_or(&0);
-            let packet_size_std = if packet_count > 0 {
-                let mean = avg_packet_size;
-                (packet_sizes.iter().map(|&s| (s as f64 - mean).powi(2)).sum::<f64>() / packet_count as f64).sqrt()
-            } else { 0.0 };
-
-            let tcp_ratio = if packet_count > 0 { tcp_count as f64 / packet_count as f64 } else { 0.0 };
-            let udp_ratio = if packet_count > 0 { udp_count as f64 / packet_count as f64 } else { 0.0 };
-            let icmp_ratio = if packet_count > 0 { icmp_count as f64 / packet_count as f64 } else { 0.0 };
-            let other_ratio = if packet_count > 0 { other_count as f64 / packet_count as f64 } else { 0.0 };
-
-            let unique_src_ratio = if packet_count > 0 { unique_src_ips.len() as f64 / packet_count as f64 } else { 0.0 };
-            let unique_dst_ratio = if packet_count > 0 { unique_dst_ips.len() as f64 / packet_count as f64 } else { 0.0 };
-
-            let flow_count = flow_stats.len();
-            let flow_ratio = if packet_count > 0 { flow_count as f64 / packet_count as f64 } else { 0.0 };
-            let avg_flow_packets = if flow_count > 0 { packet_count as f64 / flow_count as f64 } else { 0.0 };
-            let avg_flow_bytes = if flow_count > 0 { total_bytes as f64 / flow_count as f64 } else { 0.0 };
-
-            let packets_per_sec = packet_count as f64 / window_size;
-            let bytes_per_sec = total_bytes as f64 / window_size; // bytes/sec
-
-            let port_diversity = port_stats.len() as f64;
-
-            // Phase 2: Build histograms
-            let packet_size_distribution = build_packet_size_histogram(&packet_sizes);
-            let flow_duration_distribution = build_flow_duration_histogram(&flow_stats);
-            let top_flows = build_top_flows(&flow_stats, 10);
-            let top_ports = build_top_ports(&port_stats, 10);
-
-            let window = WindowFeature {
-                window_start: window_start.unwrap(),
-                window_end,
-                packet_count,
-                total_bytes,
-                avg_packet_size,
-                min_packet_size,
-                max_packet_size,
-                packet_size_std,
-                tcp_count,
-                udp_count,
-                icmp_count,
-                other_count,
-                tcp_ratio,
-                udp_ratio,
-                icmp_ratio,
-                other_ratio,
-                unique_src_ips: unique_src_ips.len(),
-                unique_dst_ips: unique_dst_ips.len(),
-                unique_src_ratio,
-                unique_dst_ratio,
-                flow_count,
-                flow_ratio,
-                avg_flow_packets,
-                avg_flow_bytes,
-                packets_per_sec,
-                bytes_per_sec,
-                port_diversity,
-                tcp_syn_count,
-                tcp_ack_count,
-                tcp_rst_count,
-                tcp_fin_count,
-                tcp_retransmissions,
-                packet_size_distribution,
-                flow_duration_distribution,
-                top_flows,
-                port_stats: top_ports,
-            };
-            window_features.push(window);
-
-            // reset counters
-            packet_count = 0;
-            total_bytes = 0;
-            tcp_count = 0;
-            udp_count = 0;
-            icmp_count = 0;
-            other_count = 0;
-            packet_sizes.clear();
-            unique_src_ips.clear();
-            unique_dst_ips.clear();
-            flow_stats.clear();
-            port_stats.clear();
-            
-            // Phase 2: Reset TCP health and flow tracking
-            tcp_syn_count = 0;
-            tcp_ack_count = 0;
-            tcp_rst_count = 0;
-            tcp_fin_count = 0;
-            tcp_retransmissions = 0;
-
-            window_start = Some(timestamp);
-            window_end = window_start.unwrap() + window_size;
-        }
-
-        packet_count += 1;
-        total_bytes += packet.data.len();
-        packet_sizes.push(packet.data.len());
+        // The outer MAC still counts for frames re-parsed out of MPLS
+        let dst_mac = (self.link_layer == LinkLayer::Ethernet).then(|| data.get(..6)).flatten();
+        let dst_ip = parsed.as_ref().ok().and_then(|headers| headers.ip.as_ref()).map(ip_destination);
+        match destination_cast(dst_mac, dst_ip) {
+            Cast::Broadcast => {
+                self.broadcast_packet_count += 1;
+                self.broadcast_bytes += data.len() as u64;
+            }
+            Cast::Multicast => {
+                self.multicast_packet_count += 1;
+                self.multicast_bytes += data.len() as u64;
+            }
+            Cast::Unicast => {}
+        }
 
-        // parse headers using etherparse
-        if let Ok(headers) = PacketHeaders::from_ethernet_slice(&packet.data) {
+        let mut is_ip = false;
+        let protocol = if let Ok(headers) = parsed {
             if let Some(ip) = headers.ip {
-                let (src_ip, dst_ip) = match ip {
+                is_ip = true;
+                let ip_protocol = upper_protocol(&ip);
+                *self.ip_protocol_counts.entry(ip_protocol_name(ip_protocol)).or_insert(0) += 1;
+                let payload_len = headers
+                    .transport
+                    .as_ref()
+                    .map_or(0, |transport| transport_payload_len(&ip, transport, headers.payload.len()));
+                if ip_packet_len(&ip) > self.options.jumbo_threshold {
+                    self.jumbo_frame_count += 1;
+                }
+                if payload_len > 0 {
+                    self.payload_packet_count += 1;
+                    self.payload_packet_bytes += data.len() as u64;
+                }
+
+                // Fragments share their datagram's ID, and many stacks send
+                // ID 0 on every don't-fragment datagram
+                let ip_id = match &ip {
+                    etherparse::IpHeader::Version4(header, _)
+                        if header.identification != 0 && !header.more_fragments && header.fragments_offset == 0 =>
+                    {
+                        Some(header.identification)
+                    }
+                    _ => None,
+                };
+                let (src_ip, dst_ip, ecn, ttl) = match ip {
                     etherparse::IpHeader::Version4(header, _) => {
-                        (Ipv4Addr::from(header.source).to_string(),
-                        Ipv4Addr::from(header.destination).to_string())
+                        (IpAddr::V4(Ipv4Addr::from(header.source)),
+                        IpAddr::V4(Ipv4Addr::from(header.destination)),
+                        header.explicit_congestion_notification,
+                        header.time_to_live)
                     }
                     etherparse::IpHeader::Version6(header, _) => {
-                        (Ipv6Addr::from(header.source).to_string(),
-                        Ipv6Addr::from(header.destination).to_string())
+                        (IpAddr::V6(Ipv6Addr::from(header.source)),
+                        IpAddr::V6(Ipv6Addr::from(header.destination)),
+                        header.traffic_class & 0x03,
+                        header.hop_limit)
                     }
                 };
+                self.hop_total += estimated_hops(ttl);
+                let src_ip = canonical_ip(src_ip, self.options.unmap_ipv4);
+                let dst_ip = canonical_ip(dst_ip, self.options.unmap_ipv4);
+                // Direction is judged on the real addresses, before any aggregation
+                let is_local = |addr: IpAddr| {
+                    self.options.local_nets.iter().any(|&(network, len)| cidr_contains(network, len, addr))
+                };
+                match (self.capture_direction, is_local(src_ip), is_local(dst_ip)) {
+                    (Some(CaptureDirection::Inbound), ..) => self.inbound_bytes += data.len() as u64,
+                    (Some(CaptureDirection::Outbound), ..) => self.outbound_bytes += data.len() as u64,
+                    (None, true, true) => self.internal_bytes += data.len() as u64,
+                    (None, true, false) => self.outbound_bytes += data.len() as u64,
+                    (None, false, true) => self.inbound_bytes += data.len() as u64,
+                    (None, false, false) => self.external_bytes += data.len() as u64,
+                }
+
+                let src_ip = mask_ip(src_ip, self.options.aggregate_prefix);
+                let dst_ip = mask_ip(dst_ip, self.options.aggregate_prefix);
+
+                // ECN codepoint 0b11 is Congestion Experienced (RFC 3168)
+                if ecn == 0x03 { self.ecn_marked_count += 1; }
+
+                // With --tcp-only, non-TCP packets are only counted (as "other"),
+                // skipping the per-host, flow and port bookkeeping
+                let tracked = !self.options.tcp_only
+                    || matches!(headers.transport, Some(etherparse::TransportHeader::Tcp(_)));
+
+                let sctp = match headers.transport {
+                    None if ip_protocol == IP_PROTOCOL_SCTP => parse_sctp(headers.payload),
+                    _ => None,
+                };
+                // Part of the flow key for ICMP flows
+                let icmp_type = if self.options.icmp_flows { icmp_type(&headers.transport) } else { None };
+
+                if tracked {
+                    let src_host = self.src_host_stats.entry(src_ip).or_default();
+                    src_host.packet_count += 1;
+                    src_host.total_bytes += data.len() as u64;
+                    let dst_host = self.dst_host_stats.entry(dst_ip).or_default();
+                    dst_host.packet_count += 1;
+                    dst_host.total_bytes += data.len() as u64;
+                    self.dsts_by_src.entry(src_ip).or_default().insert(dst_ip);
+                    self.srcs_by_dst.entry(dst_ip).or_default().insert(src_ip);
+                    self.dst_intervals
+                        .entry(dst_ip)
+                        .and_modify(|gaps| gaps.add(timestamp))
+                        .or_insert_with(|| BurstIntervals::new(timestamp));
+                    if self.options.heatmap {
+                        self.src_heatmap.add(src_ip, data.len() as u64);
+                        self.dst_heatmap.add(dst_ip, data.len() as u64);
+                    }
+                    if let Some(hosts) = self.hosts.as_mut() {
+                        let (src_port, dst_port, protocol, syn) = match &headers.transport {
+                            Some(etherparse::TransportHeader::Tcp(tcp)) => {
+                                (tcp.source_port, tcp.destination_port, Protocol::Tcp, tcp.syn && !tcp.ack)
+                            }
+                            Some(etherparse::TransportHeader::Udp(udp)) => {
+                                (udp.source_port, udp.destination_port, Protocol::Udp, false)
+                            }
+                            Some(etherparse::TransportHeader::Icmpv4(_)) | Some(etherparse::TransportHeader::Icmpv6(_)) => {
+                                (0, 0, Protocol::Icmp, false)
+                            }
+                            None => match &sctp {
+                                Some(sctp) => (sctp.source_port, sctp.destination_port, Protocol::Sctp, false),
+                                None => (0, 0, Protocol::Other, false),
+                            },
+                        };
+                        let key = FlowKey { src_ip, src_port, dst_ip, dst_port, protocol, icmp_type };
+                        hosts.record(key, data.len() as u64, syn);
+                    }
+                }
+
+                // Port 0 is never used legitimately, so a TCP/UDP header carrying
+                // it is crafted or malformed
+                let port_zero = match &headers.transport {
+                    Some(etherparse::TransportHeader::Tcp(tcp)) => tcp.source_port == 0 || tcp.destination_port == 0,
+                    Some(etherparse::TransportHeader::Udp(udp)) => udp.source_port == 0 || udp.destination_port == 0,
+                    _ => false,
+                };
+                if port_zero { self.port_zero_count += 1; }
+                let excluded = port_zero && self.options.exclude_port_zero;
 
-                unique_src_ips.insert(src_ip.clone());
-                unique_dst_ips.insert(dst_ip.clone());
+                if let Some(etherparse::TransportHeader::Tcp(tcp)) = &headers.transport {
+                    // Phase 2: Track TCP flags
+                    if tcp.syn { self.tcp_syn_count += 1; }
+                    if tcp.ack { self.tcp_ack_count += 1; }
+                    if tcp.rst { self.tcp_rst_count += 1; }
+                    if tcp.fin { self.tcp_fin_count += 1; }
+                    if tcp.ece { self.tcp_ece_count += 1; }
+                    if tcp.cwr { self.tcp_cwr_count += 1; }
+                    self.tcp_payload_sizes.add(tcp_payload_size_bucket(payload_len));
+                    // Receiver stalled: a zero window outside connection setup/teardown
+                    if tcp.window_size == 0 && !tcp.syn && !tcp.fin && !tcp.rst {
+                        self.tcp_zero_window_count += 1;
+                    }
+                    // Connection-setup options form a passive stack fingerprint
+                    if tcp.syn {
+                        for kind in tcp_option_kinds(tcp) {
+                            *self.tcp_option_counts.entry(kind).or_insert(0) += 1;
+                        }
+                    }
+                }
 
                 match headers.transport {
+                    // With --exclude-port-zero these are counted but kept out of
+                    // the flow and port tables
+                    Some(etherparse::TransportHeader::Tcp(_)) if excluded => Protocol::Tcp,
+                    Some(etherparse::TransportHeader::Udp(_)) if excluded && tracked => Protocol::Udp,
                     Some(etherparse::TransportHeader::Tcp(tcp)) => {
-                        tcp_count += 1;
-                        let flow_key = (src_ip.clone(), tcp.source_port, dst_ip.clone(), tcp.destination_port, "TCP".to_string());
-                        let flow_entry = flow_stats.entry(flow_key).or_insert(FlowAgg {
-                            packet_count: 0,
-                            total_bytes: 0,
-                            first_ts: timestamp,
-                            last_ts: timestamp,
+                        let flow_key = FlowKey {
+                            src_ip,
+                            src_port: tcp.source_port,
+                            dst_ip,
+                            dst_port: tcp.destination_port,
+                            protocol: Protocol::Tcp,
+                            icmp_type: None,
+                        };
+                        // Duplicate ACKs arrive on the reverse flow
+                        let reverse_dup_acks = self.flow_stats
+                            .get(&flow_key.reversed())
+                            .map_or(0, |reverse| reverse.dup_ack_run);
+                        let flow_entry = self.flow_stats.entry(flow_key).or_insert_with(|| FlowAgg {
+                            started_with_syn: tcp.syn && !tcp.ack,
+                            ..FlowAgg::new(timestamp)
                         });
+                        let previous_ts = flow_entry.last_ts;
                         flow_entry.packet_count += 1;
-                        flow_entry.total_bytes += packet.data.len();
+                        flow_entry.total_bytes += data.len() as u64;
+                        flow_entry.record_size(data.len());
                         flow_entry.last_ts = timestamp;
 
-                        // Phase 2: Track TCP flags
-                        if tcp.syn { tcp_syn_count += 1; }
-                        if tcp.ack { tcp_ack_count += 1; }
-                        if tcp.rst { tcp_rst_count += 1; }
-                        if tcp.fin { tcp_fin_count += 1; }
+                        // A data segment that ends at or before the highest sequence
+                        // already sent is a retransmission. Keep-alive probes (one
+                        // byte just below the highest sequence) are ignored.
+                        let mut fast_retransmit = false;
+                        if payload_len > 0 {
+                            let seq_end = tcp.sequence_number.wrapping_add(payload_len as u32);
+                            match flow_entry.highest_seq_end {
+                                Some(highest) if !seq_after(seq_end, highest) => {
+                                    if !(payload_len == 1 && seq_end == highest) {
+                                        self.tcp_retransmissions += 1;
+                                        flow_entry.retransmitted_bytes += data.len() as u64;
+                                        if reverse_dup_acks >= FAST_RETRANSMIT_DUP_ACKS {
+                                            self.fast_retransmit_count += 1;
+                                            fast_retransmit = true;
+                                        } else if timestamp - previous_ts >= RTO_MIN_SECONDS {
+                                            self.timeout_retransmit_count += 1;
+                                        }
+                                    }
+                                }
+                                _ => flow_entry.highest_seq_end = Some(seq_end),
+                            }
+                        }
 
-                        let port_key = (tcp.destination_port, "TCP".to_string());
-                        let port_entry = port_stats.entry(port_key).or_insert(PortAgg {
-                            packet_count: 0,
-                            total_bytes: 0,
-                        });
+                        // Track runs of pure duplicate ACKs sent by this side
+                        if tcp.ack && payload_len == 0 && !tcp.syn && !tcp.fin && !tcp.rst {
+                            if flow_entry.last_ack == Some(tcp.acknowledgment_number) {
+                                flow_entry.dup_ack_run += 1;
+                            } else {
+                                flow_entry.last_ack = Some(tcp.acknowledgment_number);
+                                flow_entry.dup_ack_run = 0;
+                            }
+                        } else if tcp.ack && flow_entry.last_ack != Some(tcp.acknowledgment_number) {
+                            flow_entry.last_ack = Some(tcp.acknowledgment_number);
+                            flow_entry.dup_ack_run = 0;
+                        }
+
+                        // Keep the first handshake packet of each kind for RTT estimation
+                        if tcp.syn && !tcp.ack && flow_entry.syn_ts.is_none() {
+                            flow_entry.syn_ts = Some(timestamp);
+                        }
+                        if tcp.syn && tcp.ack && flow_entry.syn_ack_ts.is_none() {
+                            flow_entry.syn_ack_ts = Some(timestamp);
+                        }
+                        if payload_len > 0 && flow_entry.first_payload_ts.is_none() {
+                            flow_entry.first_payload_ts = Some(timestamp);
+                        }
+                        if tcp.psh {
+                            flow_entry.push_count += 1;
+                        }
+
+                        // The dup-ACK run has been answered; don't credit it twice
+                        if fast_retransmit {
+                            if let Some(reverse) = self.flow_stats.get_mut(&flow_key.reversed()) {
+                                reverse.dup_ack_run = 0;
+                            }
+                        }
+
+                        self.dst_ports.insert(tcp.destination_port);
+                        self.tcp_dst_ports.insert(tcp.destination_port);
+                        self.dst_ports_by_src.entry(src_ip).or_default().insert(tcp.destination_port);
+
+                        let port_key = (tcp.destination_port, Protocol::Tcp);
+                        let port_entry = self.port_stats.entry(port_key).or_default();
                         port_entry.packet_count += 1;
-                        port_entry.total_bytes += packet.data.len();
+                        port_entry.total_bytes += data.len() as u64;
+                        port_entry.flows.insert(flow_key);
+                        port_entry.src_ips.insert(src_ip);
+
+                        Protocol::Tcp
                     }
-                    Some(etherparse::TransportHeader::Udp(udp)) => {
-                        udp_count += 1;
-                        let flow_key = (src_ip.clone(), udp.source_port, dst_ip.clone(), udp.destination_port, "UDP".to_string());
-                        let flow_entry = flow_stats.entry(flow_key).or_insert(FlowAgg {
-                            packet_count: 0,
-                            total_bytes: 0,
-                            first_ts: timestamp,
-                            last_ts: timestamp,
-                        });
+                    Some(etherparse::TransportHeader::Udp(udp)) if tracked => {
+                        let flow_key = FlowKey {
+                            src_ip,
+                            src_port: udp.source_port,
+                            dst_ip,
+                            dst_port: udp.destination_port,
+                            protocol: Protocol::Udp,
+                            icmp_type: None,
+                        };
+                        let flow_entry = self.flow_stats.entry(flow_key).or_insert_with(|| FlowAgg::new(timestamp));
                         flow_entry.packet_count += 1;
-                        flow_entry.total_bytes += packet.data.len();
+                        flow_entry.total_bytes += data.len() as u64;
+                        flow_entry.record_size(data.len());
                         flow_entry.last_ts = timestamp;
+                        // A repeated ID hints at a duplicated or retried datagram
+                        if let Some(id) = ip_id.filter(|_| self.options.track_ip_ids) {
+                            if !flow_entry.ip_ids.insert(id) {
+                                self.repeated_ip_id_count += 1;
+                            }
+                        }
 
-                        let port_key = (udp.destination_port, "UDP".to_string());
-                        let port_entry = port_stats.entry(port_key).or_insert(PortAgg {
-                            packet_count: 0,
-                            total_bytes: 0,
-                        });
+                        // QUIC (HTTP/3) rides on UDP/443; spot it by its long header
+                        if udp.source_port == 443 || udp.destination_port == 443 {
+                            if let Some(version) = quic_long_header_version(headers.payload) {
+                                self.quic_count += 1;
+                                if self.options.quic_versions {
+                                    *self.quic_version_counts.entry(quic_version_name(version)).or_insert(0) += 1;
+                                }
+                            }
+                        }
+
+                        // DHCP runs between clients on port 68 and servers or relays on 67
+                        if matches!((udp.source_port, udp.destination_port), (67 | 68, 67 | 68)) {
+                            if let Some(message) = parse_dhcp(headers.payload) {
+                                self.record_dhcp(message);
+                            }
+                        }
+
+                        self.dst_ports.insert(udp.destination_port);
+                        self.udp_dst_ports.insert(udp.destination_port);
+                        self.dst_ports_by_src.entry(src_ip).or_default().insert(udp.destination_port);
+
+                        let port_key = (udp.destination_port, Protocol::Udp);
+                        let port_entry = self.port_stats.entry(port_key).or_default();
                         port_entry.packet_count += 1;
-                        port_entry.total_bytes += packet.data.len();
+                        port_entry.total_bytes += data.len() as u64;
+                        port_entry.flows.insert(flow_key);
+                        port_entry.src_ips.insert(src_ip);
+
+                        Protocol::Udp
                     }
                     Some(etherparse::TransportHeader::Icmpv4(_)) |
-                    Some(etherparse::TransportHeader::Icmpv6(_)) => {
-                        icmp_count += 1;
+                    Some(etherparse::TransportHeader::Icmpv6(_)) if tracked => {
+                        if icmp_type.is_some() {
+                            let flow_key = FlowKey {
+                                src_ip,
+                                src_port: 0,
+                                dst_ip,
+                                dst_port: 0,
+                                protocol: Protocol::Icmp,
+                                icmp_type,
+                            };
+                            let flow_entry = self.flow_stats.entry(flow_key).or_insert_with(|| FlowAgg::new(timestamp));
+                            flow_entry.packet_count += 1;
+                            flow_entry.total_bytes += data.len() as u64;
+                            flow_entry.record_size(data.len());
+                            flow_entry.last_ts = timestamp;
+                        }
+                        Protocol::Icmp
                     }
-                    _ => { other_count += 1; }
+                    None => match &sctp {
+                        Some(sctp) if tracked => {
+                            let flow_key = FlowKey {
+                                src_ip,
+                                src_port: sctp.source_port,
+                                dst_ip,
+                                dst_port: sctp.destination_port,
+                                protocol: Protocol::Sctp,
+                                icmp_type: None,
+                            };
+                            let flow_entry = self.flow_stats.entry(flow_key).or_insert_with(|| FlowAgg::new(timestamp));
+                            flow_entry.packet_count += 1;
+                            flow_entry.total_bytes += data.len() as u64;
+                            flow_entry.record_size(data.len());
+                            flow_entry.last_ts = timestamp;
+
+                            for &chunk_type in &sctp.chunk_types {
+                                *self.sctp_chunk_counts.entry(sctp_chunk_name(chunk_type)).or_insert(0) += 1;
+                            }
+
+                            self.dst_ports.insert(sctp.destination_port);
+                            self.dst_ports_by_src.entry(src_ip).or_default().insert(sctp.destination_port);
+
+                            let port_key = (sctp.destination_port, Protocol::Sctp);
+                            let port_entry = self.port_stats.entry(port_key).or_default();
+                            port_entry.packet_count += 1;
+                            port_entry.total_bytes += data.len() as u64;
+                            port_entry.flows.insert(flow_key);
+                            port_entry.src_ips.insert(src_ip);
+
+                            Protocol::Sctp
+                        }
+                        _ => Protocol::Other,
+                    },
+                    _ => Protocol::Other,
                 }
             } else {
-                other_count += 1; // non-IP packet
+                Protocol::Other // non-IP packet
             }
         } else {
-            other_count += 1; // failed parsing
+            Protocol::Other // failed parsing
+        };
+
+        match protocol {
+            Protocol::Tcp => self.tcp_count += 1,
+            Protocol::Udp => self.udp_count += 1,
+            Protocol::Sctp => self.sctp_count += 1,
+            Protocol::Icmp => self.icmp_count += 1,
+            Protocol::Other => self.other_count += 1,
+        }
+        if self.options.max_flows.is_some_and(|max_flows| self.flow_stats.len() > max_flows) {
+            self.evict_small_flows();
+        }
+
+        if is_ip {
+            self.ip_packet_count += 1;
+            self.ip_total_bytes += data.len() as u64;
+        } else {
+            self.undecoded_packets += 1;
+        }
+        if is_ip || !self.options.ip_size_stats {
+            self.packet_sizes.add(data.len(), self.options.small_threshold, self.options.large_threshold);
+        }
+
+        if self.packet_count % 500_000 == 0 && !self.options.quiet {
+            println!("Processed {} packets...", self.packet_count);
         }
 
-        if packet_count % 500_000 == 0 {
-            println!("Processed {} packets...", packet_count);
+        completed
+    }
+
+    /// Cross-window state, taken between finalizing a window and counting
+    /// the packet that opens the next one
+    fn checkpoint(&self) -> ExtractorCheckpoint {
+        ExtractorCheckpoint {
+            total_packets_processed: self.total_packets_processed,
+            first_timestamp: self.first_timestamp,
+            last_timestamp: self.last_timestamp,
+            windows_finalized: self.windows_finalized,
+            undecoded_packets: self.undecoded_packets,
+            parse_error_breakdown: self.parse_error_breakdown.clone(),
+            clock_adjustment_events: self.clock_adjustment_events,
+            smoother_ema: self.smoother.as_ref().and_then(|smoother| smoother.ema),
+            smoother_history: self.smoother.as_ref().map_or_else(Vec::new, |smoother| smoother.history.iter().copied().collect()),
+            slow_scan_last_seen: self.slow_scan.as_ref().map_or_else(HashMap::new, |tracker| tracker.last_seen.clone()),
+            sample_rng_state: self.sample_rng.map(|_| self.sample_rng_before),
         }
     }
 
-    // Flush last window
-    if packet_count > 0 {
-        let avg_packet_size = if packet_count > 0 { total_bytes as f64 / packet_count as f64 } else { 0.0 };
-        let min_packet_size = *packet_sizes.iter().min().unwrap_or(&0);
-        let max_packet_size = *packet_sizes.iter().max().unwrap_or(&0);
-        let packet_size_std = if packet_count > 0 {
-            let mean = avg_packet_size;
-            (packet_sizes.iter().map(|&s| (s as f64 - mean).powi(2)).sum::<f64>() / packet_count as f64).sqrt()
+    /// Pick up from `checkpoint` on a fresh extractor, before replaying the
+    /// packets from the one that opened the checkpoint's window
+    fn restore(&mut self, checkpoint: &ExtractorCheckpoint) {
+        self.total_packets_processed = checkpoint.total_packets_processed;
+        self.first_timestamp = checkpoint.first_timestamp;
+        self.last_timestamp = checkpoint.last_timestamp;
+        self.windows_finalized = checkpoint.windows_finalized;
+        self.undecoded_packets = checkpoint.undecoded_packets;
+        self.parse_error_breakdown = checkpoint.parse_error_breakdown.clone();
+        self.clock_adjustment_events = checkpoint.clock_adjustment_events;
+        if let Some(smoother) = self.smoother.as_mut() {
+            smoother.ema = checkpoint.smoother_ema;
+            smoother.history = checkpoint.smoother_history.iter().copied().collect();
+        }
+        if let Some(tracker) = self.slow_scan.as_mut() {
+            tracker.last_seen = checkpoint.slow_scan_last_seen.clone();
+        }
+        if let (Some(rng), Some(state)) = (self.sample_rng.as_mut(), checkpoint.sample_rng_state) {
+            rng.state = state;
+        }
+    }
+
+    /// Flush the last window, if any packets arrived since the previous one closed
+    fn finish(&mut self) -> Option<WindowFeature> {
+        if self.packet_count > 0 {
+            let window = self.finalize_window();
+            if let Some(block) = self.group_into_block(window) {
+                return Some(block);
+            }
+        }
+        // A trailing partial block is still emitted
+        if self.block.is_empty() {
+            None
+        } else {
+            let windows = std::mem::take(&mut self.block);
+            Some(merge_windows(&windows, self.options.top_n, self.options.top_flows_by, self.options.elephants))
+        }
+    }
+
+    /// Count a DHCP message by type, and the client behind a DISCOVER or REQUEST
+    fn record_dhcp(&mut self, message: DhcpMessage) {
+        *self.dhcp_message_counts.entry(dhcp_message_type_name(message.message_type)).or_insert(0) += 1;
+        if !message.from_client || !matches!(message.message_type, Some(DHCP_DISCOVER | DHCP_REQUEST)) {
+            return;
+        }
+        let client = self.dhcp_clients.entry(message.client_mac.clone()).or_insert_with(|| DhcpClientStat {
+            client_mac: message.client_mac,
+            discover_count: 0,
+            request_count: 0,
+            requested_ip: None,
+        });
+        match message.message_type {
+            Some(DHCP_DISCOVER) => client.discover_count += 1,
+            _ => client.request_count += 1,
+        }
+        if let Some(ip) = message.requested_ip {
+            client.requested_ip = Some(ip.to_string());
+        }
+    }
+
+    /// Close the open time window once the wall clock, `now`, is past its
+    /// end. A live capture calls this between packets, since on a quiet link
+    /// no packet may arrive to close it; the next packet opens a new window.
+    fn close_elapsed(&mut self, now: f64) -> Option<WindowFeature> {
+        if self.options.window_mode != WindowMode::Time || self.packet_count == 0 || now <= self.window_end {
+            return None;
+        }
+        // The window has been silent since its last packet
+        if let Some(last) = self.last_packet_ts {
+            self.max_gap_seconds = self.max_gap_seconds.max(self.window_end - last);
+        }
+        let window = self.finalize_window();
+        self.window_start = None;
+        self.group_into_block(window)
+    }
+
+    /// With --block-size, hold windows back until a full block can be merged
+    fn group_into_block(&mut self, window: WindowFeature) -> Option<WindowFeature> {
+        let Some(block_size) = self.options.block_size else {
+            return Some(window);
+        };
+        self.block.push(window);
+        if self.block.len() < block_size {
+            return None;
+        }
+        let windows = std::mem::take(&mut self.block);
+        Some(merge_windows(&windows, self.options.top_n, self.options.top_flows_by, self.options.elephants))
+    }
+
+    /// Bring the flow table back under --max-flows by dropping the flows with
+    /// the fewest bytes, which are the least likely to reach the top N
+    fn evict_small_flows(&mut self) {
+        let Some(max_flows) = self.options.max_flows else {
+            return;
+        };
+        let target = max_flows - max_flows / FLOW_EVICTION_DIVISOR;
+        let mut sizes: Vec<(u64, FlowKey)> = self.flow_stats.iter().map(|(key, agg)| (agg.total_bytes, *key)).collect();
+        let excess = sizes.len() - target;
+        sizes.select_nth_unstable_by_key(excess - 1, |&(bytes, _)| bytes);
+        for (_, key) in &sizes[..excess] {
+            self.flow_stats.remove(key);
+        }
+        self.evicted_flow_count += excess;
+    }
+
+    fn passes_filters(&self, data: &[u8]) -> bool {
+        let (ports, nets) = (&self.options.only_ports, &self.options.filter_ips);
+        if ports.is_empty() && nets.is_empty() {
+            return true;
+        }
+        let Some(headers) = decode_frame(self.link_layer, data) else {
+            return false;
+        };
+        let ports_match = ports.is_empty()
+            || header_ports(&headers).is_some_and(|(src, dst)| ports.contains(&src) || ports.contains(&dst));
+        let in_nets = |addr| nets.iter().any(|&(network, len)| cidr_contains(network, len, addr));
+        let ips_match = nets.is_empty()
+            || header_addresses(&headers).is_some_and(|(src, dst)| {
+                let unmap = self.options.unmap_ipv4;
+                in_nets(canonical_ip(src, unmap)) || in_nets(canonical_ip(dst, unmap))
+            });
+        ports_match && ips_match
+    }
+
+    /// Where a window opened by a packet at `timestamp` ends, as far as is
+    /// known yet: a count window grows as its packets arrive
+    fn initial_window_end(&self, timestamp: f64) -> f64 {
+        match self.options.window_mode {
+            WindowMode::Time => timestamp + self.options.window_size,
+            WindowMode::Count => timestamp,
+        }
+    }
+
+    /// Seconds the current window covers, the denominator of its rates
+    fn window_seconds(&self) -> f64 {
+        match self.options.window_mode {
+            WindowMode::Time => self.options.window_size,
+            WindowMode::Count => self.window_end - self.window_start.unwrap(),
+        }
+    }
+
+    /// Build the feature record for the current window and reset the
+    /// per-window counters for the next one
+    fn finalize_window(&mut self) -> WindowFeature {
+        self.windows_finalized += 1;
+        let window_seconds = self.window_seconds();
+        if let Some(hosts) = self.hosts.as_mut() {
+            let records = hosts.finish_window(
+                self.window_start.unwrap(),
+                self.window_end,
+                self.options.aggregate_prefix,
+                self.options.sample_scale,
+            );
+            self.host_records.extend(records);
+        }
+        // Size statistics optionally cover only IP packets, leaving L2 noise out
+        let (size_count, size_bytes) = if self.options.ip_size_stats {
+            (self.ip_packet_count, self.ip_total_bytes)
+        } else {
+            (self.packet_count, self.total_bytes)
+        };
+        let avg_packet_size = if size_count > 0 {
+            size_bytes as f64 / size_count as f64
+        } else { 0.0 };
+        // Same mean, but skipping pure ACKs and other header-only packets
+        let avg_payload_packet_size = if self.payload_packet_count > 0 {
+            self.payload_packet_bytes as f64 / self.payload_packet_count as f64
         } else { 0.0 };
+        let min_packet_size = self.packet_sizes.min();
+        let max_packet_size = self.packet_sizes.max;
+        let packet_size_std = self.packet_sizes.std();
+        // Approximate (P²) beyond the first few packets
+        let median_packet_size = self.packet_sizes.median.value();
+        let p95_packet_size = self.packet_sizes.p95.value();
+        let packet_size_bimodality = self.packet_sizes.bimodality();
+        // Share of tiny control packets vs. large data packets at the configured cut points
+        let (small_packet_ratio, large_packet_ratio) = if size_count > 0 {
+            (self.packet_sizes.small as f64 / size_count as f64, self.packet_sizes.large as f64 / size_count as f64)
+        } else { (0.0, 0.0) };
 
-        let tcp_ratio = if packet_count > 0 { tcp_count as f64 / packet_count as f64 } else { 0.0 };
-        let udp_ratio = if packet_count > 0 { udp_count as f64 / packet_count as f64 } else { 0.0 };
-        let icmp_ratio = if packet_count > 0 { icmp_count as f64 / packet_count as f64 } else { 0.0 };
-        let other_ratio = if packet_count > 0 { other_count as f64 / packet_count as f64 } else { 0.0 };
+        let tcp_ratio = if self.packet_count > 0 { self.tcp_count as f64 / self.packet_count as f64 } else { 0.0 };
+        let udp_ratio = if self.packet_count > 0 { self.udp_count as f64 / self.packet_count as f64 } else { 0.0 };
+        let icmp_ratio = if self.packet_count > 0 { self.icmp_count as f64 / self.packet_count as f64 } else { 0.0 };
+        let other_ratio = if self.packet_count > 0 { self.other_count as f64 / self.packet_count as f64 } else { 0.0 };
 
-        let unique_src_ratio = if packet_count > 0 { unique_src_ips.len() as f64 / packet_count as f64 } else { 0.0 };
-        let unique_dst_ratio = if packet_count > 0 { unique_dst_ips.len() as f64 / packet_count as f64 } else { 0.0 };
+        let unique_src_ratio = if self.packet_count > 0 { self.src_host_stats.len() as f64 / self.packet_count as f64 } else { 0.0 };
+        let avg_estimated_hops =
+            if self.ip_packet_count > 0 { self.hop_total as f64 / self.ip_packet_count as f64 } else { 0.0 };
+        let unique_dst_ratio = if self.packet_count > 0 { self.dst_host_stats.len() as f64 / self.packet_count as f64 } else { 0.0 };
+        let src_ip_entropy = shannon_entropy(self.src_host_stats.values().map(|host| host.packet_count));
+        let dst_ip_entropy = shannon_entropy(self.dst_host_stats.values().map(|host| host.packet_count));
 
-        let flow_count = flow_stats.len();
-        let flow_ratio = if packet_count > 0 { flow_count as f64 / packet_count as f64 } else { 0.0 };
-        let avg_flow_packets = if flow_count > 0 { packet_count as f64 / flow_count as f64 } else { 0.0 };
-        let avg_flow_bytes = if flow_count > 0 { total_bytes as f64 / flow_count as f64 } else { 0.0 };
+        let flow_count = self.flow_stats.len();
+        let tcp_connection_count = self.flow_stats.values().filter(|agg| agg.started_with_syn).count();
+        let (answered_syn_count, unanswered_syn_count) = syn_answers(&self.flow_stats);
+        let max_concurrent_flows = max_concurrent_flows(&self.flow_stats);
+        // Flows with no reverse traffic: a one-path capture point under asymmetric routing
+        let unidirectional_flow_count = self
+            .flow_stats
+            .keys()
+            .filter(|key| !self.flow_stats.contains_key(&key.reversed()))
+            .count();
+        let unidirectional_flow_ratio = if flow_count > 0 {
+            unidirectional_flow_count as f64 / flow_count as f64
+        } else { 0.0 };
+        let elephant_flow_count = self
+            .flow_stats
+            .values()
+            .filter(|agg| {
+                let bytes = scale_bytes(agg.total_bytes, self.options.sample_scale);
+                self.options.elephants.classify(bytes, (agg.last_ts - agg.first_ts).max(0.0)) == "elephant"
+            })
+            .count();
+        let flow_size_gini = flow_size_gini(&self.flow_stats);
+        let flow_birth_series = flow_birth_series(
+            &self.flow_stats,
+            self.window_start.unwrap(),
+            window_seconds,
+            self.options.flow_birth_buckets,
+        );
+        let flow_ratio = if self.packet_count > 0 { flow_count as f64 / self.packet_count as f64 } else { 0.0 };
+        let avg_flow_packets = if flow_count > 0 { self.packet_count as f64 / flow_count as f64 } else { 0.0 };
+        let avg_flow_bytes = if flow_count > 0 { self.total_bytes as f64 / flow_count as f64 } else { 0.0 };
 
-        let packets_per_sec = packet_count as f64 / window_size;
-        let bytes_per_sec = total_bytes as f64 / window_size; // bytes/sec
+        // A count window whose packets share one timestamp has no rate
+        let per_sec = |count: f64| if window_seconds > 0.0 { count / window_seconds } else { 0.0 };
+        let packets_per_sec = per_sec(self.packet_count as f64);
+        let syn_rate = per_sec(self.tcp_syn_count as f64);
+        // Utilization counts wire bytes when --wire-bytes is on
+        let wire_bytes = self
+            .options
+            .wire_overhead
+            .map(|overhead| self.total_bytes + self.packet_count as u64 * overhead as u64);
+        let bytes_per_sec = per_sec(wire_bytes.unwrap_or(self.total_bytes) as f64); // bytes/sec
 
-        let port_diversity = port_stats.len() as f64;
+        let port_diversity = self.port_stats.len() as f64;
+        let unique_dst_ports = self.dst_ports.len();
+        let tcp_unique_dst_ports = self.tcp_dst_ports.len();
+        let udp_unique_dst_ports = self.udp_dst_ports.len();
+        let unique_dst_ports_per_src = self.dst_ports_by_src.values().map(HashSet::len).max().unwrap_or(0);
 
-        // Phase 2: Build histograms for final window
-        let packet_size_distribution = build_packet_size_histogram(&packet_sizes);
-        let flow_duration_distribution = build_flow_duration_histogram(&flow_stats);
-        let top_flows = build_top_flows(&flow_stats, 10);
-        let top_ports = build_top_ports(&port_stats, 10);
+        // Phase 2: Build histograms
+        let packet_size_distribution = self.packet_sizes.histogram.clone();
+        let flow_duration_distribution = build_flow_duration_histogram(&self.flow_stats, &self.options.duration_buckets);
+        let ip_protocol_distribution = self.ip_protocol_counts.clone();
+        let quic_versions = self.options.quic_versions.then(|| self.quic_version_counts.clone());
+        let tcp_option_counts = self.tcp_option_counts.clone();
+        let sctp_chunk_counts = self.sctp_chunk_counts.clone();
+        let src_octet_heatmap = self.options.heatmap.then(|| self.src_heatmap.clone());
+        let dst_octet_heatmap = self.options.heatmap.then(|| self.dst_heatmap.clone());
+        let shape_fingerprint = self
+            .options
+            .shape_fingerprint
+            .then(|| shape_fingerprint(&packet_size_distribution, [tcp_ratio, udp_ratio, icmp_ratio, other_ratio]));
+        // --flows-out gets every flow, so rank them all once and keep the top
+        let flow_limit = if self.options.flows_out.is_some() { usize::MAX } else { self.options.top_n };
+        let mut top_flows = build_top_flows(&self.flow_stats, flow_limit, self.options, self.labels, self.total_bytes);
+        if self.options.flows_out.is_some() {
+            let window_index = self.windows_finalized - 1;
+            let window_start = self.window_start.unwrap();
+            self.flow_records.extend(top_flows.iter().map(|flow| {
+                let mut flow = flow.clone();
+                flow.scale_sampled(self.options.sample_scale);
+                FlowRecord { window_index, window_start, flow }
+            }));
+            top_flows.truncate(self.options.top_n);
+        }
+        let top_ports =
+            build_top_ports(&self.port_stats, self.options.top_n, self.options.merge_tcp_udp_ports, self.total_bytes);
+        let beaconing_candidates =
+            build_beaconing_candidates(&self.dst_intervals, self.options.top_n, self.options.aggregate_prefix);
+        let slow_scan_candidates = self.slow_scan.as_mut().map(|tracker| {
+            tracker.update(&self.dst_ports_by_src, self.window_end, self.options.top_n, self.options.aggregate_prefix)
+        });
+        let top_mpls_labels = build_top_mpls_labels(&self.mpls_label_counts, self.options.top_n);
+        let dhcp_clients = build_dhcp_clients(&self.dhcp_clients, self.options.top_n);
+        let top_src_ips = build_top_talkers(&self.src_host_stats, self.options.top_n, self.options.aggregate_prefix, self.geoip);
+        let top_dst_ips = build_top_talkers(&self.dst_host_stats, self.options.top_n, self.options.aggregate_prefix, self.geoip);
+        let top_fanout = build_top_fan(&self.dsts_by_src, self.options.top_n, self.options.aggregate_prefix);
+        let top_fanin = build_top_fan(&self.srcs_by_dst, self.options.top_n, self.options.aggregate_prefix);
 
-        let window = WindowFeature {
-            window_start: window_start.unwrap(),
-            window_end,
-            packet_count,
-            total_bytes,
+        let mut window = WindowFeature {
+            window_index: self.windows_finalized - 1,
+            window_start: self.window_start.unwrap(),
+            window_end: self.window_end,
+            clock_adjustment_events: self.clock_adjustment_events,
+            max_gap_seconds: self.max_gap_seconds,
+            has_capture_gap: self.max_gap_seconds > self.options.gap_fraction * window_seconds,
+            packet_count: self.packet_count,
+            total_bytes: self.total_bytes,
+            wire_bytes,
+            ip_packet_count: self.ip_packet_count,
+            ip_total_bytes: self.ip_total_bytes,
+            jumbo_frame_count: self.jumbo_frame_count,
             avg_packet_size,
+            avg_payload_packet_size,
             min_packet_size,
             max_packet_size,
             packet_size_std,
-            tcp_count,
-            udp_count,
-            icmp_count,
-            other_count,
+            median_packet_size,
+            p95_packet_size,
+            packet_size_bimodality,
+            small_packet_ratio,
+            large_packet_ratio,
+            tcp_count: self.tcp_count,
+            udp_count: self.udp_count,
+            sctp_count: self.sctp_count,
+            quic_count: self.quic_count,
+            icmp_count: self.icmp_count,
+            other_count: self.other_count,
             tcp_ratio,
             udp_ratio,
             icmp_ratio,
             other_ratio,
-            unique_src_ips: unique_src_ips.len(),
-            unique_dst_ips: unique_dst_ips.len(),
+            unique_src_ips: self.src_host_stats.len(),
+            unique_dst_ips: self.dst_host_stats.len(),
             unique_src_ratio,
             unique_dst_ratio,
+            src_ip_entropy,
+            dst_ip_entropy,
+            top_src_ips,
+            top_dst_ips,
+            top_fanout,
+            top_fanin,
             flow_count,
+            tcp_connection_count,
+            answered_syn_count,
+            unanswered_syn_count,
+            unanswered_syn_ratio: syn_ratio(answered_syn_count, unanswered_syn_count),
+            max_concurrent_flows,
+            unidirectional_flow_count,
+            unidirectional_flow_ratio,
+            elephant_flow_count,
+            mice_flow_count: flow_count - elephant_flow_count,
             flow_ratio,
             avg_flow_packets,
             avg_flow_bytes,
+            flow_size_gini,
+            flow_birth_series,
             packets_per_sec,
             bytes_per_sec,
             port_diversity,
-            tcp_syn_count,
-            tcp_ack_count,
-            tcp_rst_count,
-            tcp_fin_count,
-            tcp_retransmissions,
+            unique_dst_ports,
+            tcp_unique_dst_ports,
+            udp_unique_dst_ports,
+            unique_dst_ports_per_src,
+            tcp_syn_count: self.tcp_syn_count,
+            syn_rate,
+            connection_storm: None,
+            tcp_ack_count: self.tcp_ack_count,
+            tcp_rst_count: self.tcp_rst_count,
+            tcp_fin_count: self.tcp_fin_count,
+            tcp_retransmissions: self.tcp_retransmissions,
+            fast_retransmit_count: self.fast_retransmit_count,
+            timeout_retransmit_count: self.timeout_retransmit_count,
+            repeated_ip_id_count: self.options.track_ip_ids.then_some(self.repeated_ip_id_count),
+            tcp_ece_count: self.tcp_ece_count,
+            tcp_cwr_count: self.tcp_cwr_count,
+            tcp_zero_window_count: self.tcp_zero_window_count,
+            tcp_option_counts,
+            sctp_chunk_counts,
+            ecn_marked_count: self.ecn_marked_count,
+            avg_estimated_hops,
+            port_zero_count: self.port_zero_count,
+            evicted_flow_count: self.evicted_flow_count,
+            inbound_bytes: self.inbound_bytes,
+            outbound_bytes: self.outbound_bytes,
+            internal_bytes: self.internal_bytes,
+            external_bytes: self.external_bytes,
+            broadcast_packet_count: self.broadcast_packet_count,
+            broadcast_bytes: self.broadcast_bytes,
+            multicast_packet_count: self.multicast_packet_count,
+            multicast_bytes: self.multicast_bytes,
+            mpls_labeled_count: self.mpls_labeled_count,
+            top_mpls_labels,
+            dhcp_message_counts: self.dhcp_message_counts.clone(),
+            dhcp_clients,
             packet_size_distribution,
+            tcp_payload_size_distribution: self.tcp_payload_sizes.clone(),
             flow_duration_distribution,
+            ip_protocol_distribution,
+            quic_versions,
             top_flows,
+            conversations: None,
             port_stats: top_ports,
+            beaconing_candidates,
+            slow_scan_candidates,
+            src_octet_heatmap,
+            dst_octet_heatmap,
+            shape_fingerprint,
+            smoothed: BTreeMap::new(),
         };
-        window_features.push(window);
+        if self.options.sample_scale > 1.0 {
+            window.scale_sampled(self.options.sample_scale);
+        }
+        // Judged on the scaled rate, so a threshold means the same with --sample
+        if let Some(threshold) = self.options.syn_rate_threshold {
+            window.connection_storm = Some(window.syn_rate > threshold);
+        }
+        if self.options.conversations {
+            window.conversations = Some(build_conversations(&window.top_flows));
+        }
+        if let Some(smoother) = self.smoother.as_mut() {
+            smoother.apply(&mut window);
+        }
+
+        self.reset_window();
+        window
+    }
+
+    fn reset_window(&mut self) {
+        self.clock_adjustment_events = 0;
+        self.packet_count = 0;
+        self.last_packet_ts = None;
+        self.max_gap_seconds = 0.0;
+        self.total_bytes = 0;
+        self.tcp_count = 0;
+        self.udp_count = 0;
+        self.sctp_count = 0;
+        self.quic_count = 0;
+        self.icmp_count = 0;
+        self.other_count = 0;
+        self.ip_packet_count = 0;
+        self.ip_total_bytes = 0;
+        self.jumbo_frame_count = 0;
+        self.payload_packet_count = 0;
+        self.payload_packet_bytes = 0;
+        self.packet_sizes = SizeStats::new();
+        self.tcp_payload_sizes = Histogram::new(&TCP_PAYLOAD_SIZE_BUCKETS);
+        self.src_host_stats.clear();
+        self.dst_host_stats.clear();
+        self.dst_intervals.clear();
+        self.flow_stats.clear();
+        self.port_stats.clear();
+        self.dst_ports.clear();
+        self.tcp_dst_ports.clear();
+        self.udp_dst_ports.clear();
+        self.dst_ports_by_src.clear();
+        self.dsts_by_src.clear();
+        self.srcs_by_dst.clear();
+
+        // Phase 2: Reset TCP health and flow tracking
+        self.tcp_syn_count = 0;
+        self.tcp_ack_count = 0;
+        self.tcp_rst_count = 0;
+        self.tcp_fin_count = 0;
+        self.tcp_retransmissions = 0;
+        self.fast_retransmit_count = 0;
+        self.timeout_retransmit_count = 0;
+        self.repeated_ip_id_count = 0;
+        self.tcp_ece_count = 0;
+        self.tcp_cwr_count = 0;
+        self.tcp_zero_window_count = 0;
+        self.ecn_marked_count = 0;
+        self.hop_total = 0;
+        self.port_zero_count = 0;
+        self.evicted_flow_count = 0;
+        self.inbound_bytes = 0;
+        self.outbound_bytes = 0;
+        self.internal_bytes = 0;
+        self.external_bytes = 0;
+        self.broadcast_packet_count = 0;
+        self.broadcast_bytes = 0;
+        self.multicast_packet_count = 0;
+        self.multicast_bytes = 0;
+        self.mpls_labeled_count = 0;
+        self.mpls_label_counts.clear();
+        self.dhcp_message_counts.clear();
+        self.dhcp_clients.clear();
+        self.ip_protocol_counts.clear();
+        self.quic_version_counts.clear();
+        self.tcp_option_counts.clear();
+        self.sctp_chunk_counts.clear();
+        self.src_heatmap.clear();
+        self.dst_heatmap.clear();
+    }
+}
+
+// --------------------------
+// Errors
+// --------------------------
+
+/// Failure classes of a run, each with its own process exit code so scripts
+/// can tell a bad invocation from an unreadable capture or a full disk
+#[derive(Debug, thiserror::Error)]
+enum ExtractorError {
+    #[error("{0}")]
+    Argument(String),
+    #[error("cannot open capture {path}: {source}")]
+    PcapOpen { path: String, source: pcap::Error },
+    #[error("cannot list capture interfaces: {0}")]
+    Interfaces(pcap::Error),
+    #[error("cannot open GeoIP database: {0}")]
+    GeoIp(#[from] maxminddb::MaxMindDBError),
+    #[error("{0}")]
+    Labels(String),
+    #[error("cannot create output {path}: {source}")]
+    OutputCreate { path: String, source: std::io::Error },
+    #[error("write failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("cannot serialize output: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("capture {0} contained no packets (--fail-on-empty)")]
+    EmptyCapture(String),
+    #[error("{0}")]
+    Checkpoint(String),
+    /// Some manifest entries failed; each was reported as it happened
+    #[error("{failed} of {total} manifest entries failed")]
+    Manifest { failed: usize, total: usize, exit_code: i32 },
+}
+
+impl ExtractorError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            ExtractorError::Argument(_) => 2,
+            ExtractorError::PcapOpen { .. } | ExtractorError::Interfaces(_) => 3,
+            ExtractorError::GeoIp(_) | ExtractorError::Labels(_) => 4,
+            ExtractorError::OutputCreate { .. } | ExtractorError::Io(_) => 5,
+            ExtractorError::Serialization(_) => 6,
+            ExtractorError::EmptyCapture(_) => 7,
+            ExtractorError::Checkpoint(_) => 8,
+            // The code of the first failing entry
+            ExtractorError::Manifest { exit_code, .. } => *exit_code,
+        }
+    }
+}
+
+// --------------------------
+// Manifests
+// --------------------------
+
+/// One capture listed in a `--manifest` file
+struct ManifestEntry {
+    input: String,
+    output: Option<String>,
+}
+
+/// Manifest lines are `<input>` or `<input><TAB><output>`, so paths may hold
+/// spaces. Blank lines and `#` comments are skipped.
+fn parse_manifest(text: &str) -> Result<Vec<ManifestEntry>, String> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t').map(str::trim);
+        let input = fields.next().unwrap_or_default().to_string();
+        let output = fields.next().filter(|output| !output.is_empty()).map(str::to_string);
+        if fields.next().is_some() {
+            return Err(format!("manifest line {}: expected <input> or <input><TAB><output>", number + 1));
+        }
+        entries.push(ManifestEntry { input, output });
+    }
+    Ok(entries)
+}
+
+/// Output path for a manifest entry that names none: the input's file stem
+/// under `dir`, as a directory with --split-windows
+fn manifest_output(dir: &str, input: &str, options: &Options) -> String {
+    let stem = std::path::Path::new(input).file_stem().map_or_else(|| input.into(), |stem| stem.to_string_lossy());
+    let name = match (options.split_template.is_some(), options.format) {
+        (true, _) => format!("{}_features", stem),
+        (false, OutputFormat::Json) => format!("{}_features.json", stem),
+        (false, OutputFormat::Jsonl | OutputFormat::Vector) => format!("{}_features.jsonl", stem),
+    };
+    PathBuf::from(dir).join(name).to_string_lossy().into_owned()
+}
+
+/// Extract every manifest entry, `--jobs` at a time. A failing entry is
+/// reported and the rest still run.
+fn run_manifest(options: &Options, path: &str) -> Result<(), ExtractorError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ExtractorError::Argument(format!("cannot read manifest {}: {}", path, e)))?;
+    let entries = parse_manifest(&text).map_err(ExtractorError::Argument)?;
+    if !options.benchmark && entries.iter().any(|entry| entry.output.is_none()) {
+        std::fs::create_dir_all(&options.output_path)
+            .map_err(|source| ExtractorError::OutputCreate { path: options.output_path.clone(), source })?;
+    }
+    let runs: Vec<Options> = entries
+        .into_iter()
+        .map(|entry| Options {
+            output_path: entry.output.unwrap_or_else(|| manifest_output(&options.output_path, &entry.input, options)),
+            pcap_file: entry.input,
+            manifest: None,
+            ..options.clone()
+        })
+        .collect();
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let failures = std::sync::Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.min(runs.len()) {
+            scope.spawn(|| {
+                while let Some(run) = runs.get(next.fetch_add(1, std::sync::atomic::Ordering::Relaxed)) {
+                    if let Err(error) = extract(run) {
+                        eprintln!("Error: {}: {}", run.pcap_file, error);
+                        failures.lock().unwrap().push((run.pcap_file.clone(), error.exit_code()));
+                    }
+                }
+            });
+        }
+    });
+
+    let failures = failures.into_inner().unwrap();
+    if !options.quiet {
+        println!("✅ Processed {} of {} manifest entries", runs.len() - failures.len(), runs.len());
+    }
+    // Report the code of the earliest failing entry in manifest order
+    let first_failure = runs.iter().find_map(|run| failures.iter().find(|(input, _)| *input == run.pcap_file));
+    match first_failure {
+        Some(&(_, exit_code)) => Err(ExtractorError::Manifest { failed: failures.len(), total: runs.len(), exit_code }),
+        None => Ok(()),
+    }
+}
+
+// --------------------------
+// Main Function
+// --------------------------
+fn main() {
+    if let Err(error) = run() {
+        match &error {
+            ExtractorError::Argument(message) => eprintln!("Error: {}\n\n{}", message, USAGE),
+            _ => eprintln!("Error: {}", error),
+        }
+        std::process::exit(error.exit_code());
+    }
+}
+
+/// Whether any of `names` is given as an option, i.e. before a `--`
+fn has_flag(args: &[String], names: &[&str]) -> bool {
+    args.iter().skip(1).take_while(|arg| *arg != "--").any(|arg| names.contains(&arg.as_str()))
+}
+
+fn run() -> Result<(), ExtractorError> {
+    // Get command-line arguments
+    let args: Vec<String> = std::env::args().collect();
+    if has_flag(&args, &["--help", "-h"]) {
+        print!("{}", USAGE);
+        return Ok(());
+    }
+    if has_flag(&args, &["--version", "-V"]) {
+        println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if has_flag(&args, &["--list-interfaces"]) {
+        return list_interfaces().map_err(ExtractorError::Interfaces);
+    }
+    if has_flag(&args, &["--describe-schema"]) {
+        println!("{}", serde_json::to_string_pretty(&describe_schema())?);
+        return Ok(());
+    }
+    let options = parse_args(&args).map_err(ExtractorError::Argument)?;
+    match &options.manifest {
+        Some(path) => run_manifest(&options, path),
+        None => extract(&options),
+    }
+}
+
+/// Extract one capture into `options.output_path`
+fn extract(options: &Options) -> Result<(), ExtractorError> {
+    let output_error = |source| ExtractorError::OutputCreate { path: options.output_path.clone(), source };
+
+    let resume_from = match &options.checkpoint_path {
+        Some(path) if options.resume => Checkpoint::load(path).map_err(ExtractorError::Checkpoint)?,
+        _ => None,
+    };
+    if let Some(checkpoint) = &resume_from {
+        if checkpoint.pcap_file != options.pcap_file {
+            return Err(ExtractorError::Checkpoint(format!(
+                "checkpoint is for {}, not {}",
+                checkpoint.pcap_file, options.pcap_file
+            )));
+        }
+    }
+
+    let (json_file, mut jsonl_writer, mut split_writer) = match &options.split_template {
+        _ if options.benchmark => (None, None, None),
+        Some(template) => {
+            let mut writer = SplitWriter::new(&options.output_path, template.clone(), options.format).map_err(output_error)?;
+            writer.next_index = resume_from.as_ref().map_or(0, |checkpoint| checkpoint.windows_emitted);
+            (None, None, Some(writer))
+        }
+        None => {
+            let output_file = match &resume_from {
+                Some(checkpoint) => reopen_output(&options.output_path, checkpoint.output_len),
+                None => File::create(&options.output_path),
+            }
+            .map_err(output_error)?;
+            match options.format {
+                OutputFormat::Json => (Some(output_file), None, None),
+                OutputFormat::Jsonl => (None, Some(JsonlWriter::new(output_file, options.flush_interval)), None),
+                OutputFormat::Vector => {
+                    let mut writer = JsonlWriter::new(output_file, options.flush_interval);
+                    if resume_from.is_none() {
+                        writer.write_record(&FeatureVectorSchema::current()).map_err(output_error)?;
+                    }
+                    (None, Some(writer), None)
+                }
+            }
+        }
+    };
+    if options.format == OutputFormat::Vector && !options.benchmark {
+        let path = feature_vector_schema_path(options);
+        let file = File::create(&path)
+            .map_err(|source| ExtractorError::OutputCreate { path: path.display().to_string(), source })?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &FeatureVectorSchema::current())?;
+    }
+
+    let mut flows_writer = match &options.flows_out {
+        Some(path) if !options.benchmark => {
+            let file = match &resume_from {
+                Some(checkpoint) => reopen_output(path, checkpoint.flows_len),
+                None => File::create(path),
+            }
+            .map_err(|source| ExtractorError::OutputCreate { path: path.clone(), source })?;
+            Some(JsonlWriter::new(file, options.flush_interval))
+        }
+        _ => None,
+    };
+
+    let mut sqlite_writer = match &options.sqlite_out {
+        Some(path) if !options.benchmark => Some(
+            SqliteWriter::open(path, &options.pcap_file)
+                .map_err(|source| ExtractorError::OutputCreate { path: path.clone(), source })?,
+        ),
+        _ => None,
+    };
+
+    let geoip = if options.geoip_paths.is_empty() {
+        None
+    } else {
+        Some(GeoIp::open(&options.geoip_paths)?)
+    };
+
+    let labels = match &options.labels_path {
+        Some(path) => Some(LabelMap::load(path).map_err(ExtractorError::Labels)?),
+        None => None,
+    };
+
+    let mut extractor = FeatureExtractor::new(options, geoip.as_ref(), labels.as_ref());
+    let mut window_features: Vec<WindowFeature> = Vec::new();
+    let mut host_features: Vec<HostWindowFeature> = Vec::new();
+    let mut window_count = 0;
+    let mut window_rates: Vec<f64> = Vec::new();
+    let mut run_summary = options.summary_only.then(RunSummary::default);
+    let mut triage = (options.color || (!options.quiet && std::io::stderr().is_terminal())).then(TriageSummary::default);
+    if let Some(checkpoint) = &resume_from {
+        extractor.restore(&checkpoint.extractor);
+        window_count = checkpoint.windows_emitted;
+        window_rates = checkpoint.window_rates.clone();
+    }
+    // With --per-host the aggregate window is dropped in favour of its host
+    // records. A window boundary the extractor recorded is checkpointed as
+    // the point just before the `packets_read`-th packet, which crossed it.
+    let mut emit = |mut window: WindowFeature, extractor: &mut FeatureExtractor, packets_read: usize| -> std::io::Result<()> {
+        window_count += 1;
+        if options.window_index == WindowNumbering::Emitted {
+            window.window_index = window_count - 1;
+        }
+        window_rates.push(window.bytes_per_sec);
+        if let Some(triage) = triage.as_mut() {
+            triage.add(&window);
+        }
+        if options.verbose {
+            eprintln!("{}", window_summary(&window));
+        }
+        for record in extractor.flow_records.drain(..) {
+            if let Some(writer) = flows_writer.as_mut() {
+                writer.write_record(&record)?;
+            }
+        }
+        if options.per_host {
+            for host in extractor.host_records.drain(..) {
+                match jsonl_writer.as_mut() {
+                    Some(writer) => writer.write_record(&host)?,
+                    None if options.benchmark => {}
+                    None => host_features.push(host),
+                }
+            }
+            return Ok(());
+        }
+        if let Some(summary) = run_summary.as_mut() {
+            summary.add(&window);
+            return Ok(());
+        }
+        if let Some(writer) = sqlite_writer.as_mut() {
+            writer.write_window(&window)?;
+        }
+        if let Some(writer) = split_writer.as_mut() {
+            writer.write_window(&window)?;
+        } else {
+            match jsonl_writer.as_mut() {
+                Some(writer) if options.format == OutputFormat::Vector => {
+                    writer.write_record(&feature_vector(&window)[..])?
+                }
+                Some(writer) => writer.write_record(&window)?,
+                None if options.benchmark => {}
+                None => window_features.push(window),
+            }
+        }
+        match (&options.checkpoint_path, extractor.boundary.take()) {
+            (Some(path), Some(state)) if window_count % options.flush_interval == 0 => {
+                let output_len = match jsonl_writer.as_mut() {
+                    Some(writer) => writer.flush_position()?,
+                    None => 0,
+                };
+                let flows_len = match flows_writer.as_mut() {
+                    Some(writer) => writer.flush_position()?,
+                    None => 0,
+                };
+                Checkpoint {
+                    pcap_file: options.pcap_file.clone(),
+                    packets_read: packets_read - 1,
+                    windows_emitted: window_count,
+                    output_len,
+                    flows_len,
+                    window_rates: window_rates.clone(),
+                    extractor: state,
+                }
+                .save(path)
+            }
+            _ => Ok(()),
+        }
+    };
+
+    let open_error = |source| ExtractorError::PcapOpen { path: options.pcap_file.clone(), source };
+    let mut cap: Capture<dyn Activated> = if options.live {
+        // The read timeout wakes the loop on a quiet link to check the clock
+        Capture::from_device(options.pcap_file.as_str())
+            .and_then(|capture| capture.timeout(LIVE_POLL_MILLIS).open())
+            .map_err(open_error)?
+            .into()
+    } else {
+        Capture::from_file(&options.pcap_file).map_err(open_error)?.into()
+    };
+    let link_type = cap.get_datalink();
+    extractor.link_layer = LinkLayer::from_linktype(link_type);
+    // A second read of the same file, for the pcapng blocks libpcap doesn't expose
+    let mut pcapng = if options.live {
+        None
+    } else {
+        File::open(&options.pcap_file).ok().map(|file| PcapngMetadata::new(BufReader::new(file)))
+    };
+    let started = std::time::Instant::now();
+    let (mut packets_read, mut bytes_read) = (0, 0);
+    // pcap files can't seek by packet, so read past what the checkpoint covers
+    if let Some(checkpoint) = &resume_from {
+        while packets_read < checkpoint.packets_read && cap.next_packet().is_ok() {
+            packets_read += 1;
+            if let Some(pcapng) = &mut pcapng {
+                pcapng.next_packet_direction();
+            }
+        }
+    }
+    loop {
+        match cap.next_packet() {
+            Ok(packet) => {
+                packets_read += 1;
+                bytes_read += packet.data.len() as u64;
+                let direction = pcapng.as_mut().and_then(PcapngMetadata::next_packet_direction);
+                if options.pcapng_direction {
+                    extractor.capture_direction = direction;
+                }
+                let ts = packet.header.ts;
+                let timestamp = ts.tv_sec as f64 + ts.tv_usec as f64 * 1e-6;
+                if let Some(window) = extractor.process_packet(timestamp, packet.data) {
+                    emit(window, &mut extractor, packets_read)?;
+                }
+            }
+            Err(pcap::Error::TimeoutExpired) => {}
+            // The end of a file, or a live capture that failed
+            Err(_) => break,
+        }
+        // Checked after packets too, as filtered or sampled ones don't move the window clock
+        if options.live {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            if let Some(window) = extractor.close_elapsed(now.as_secs_f64()) {
+                emit(window, &mut extractor, packets_read)?;
+            }
+        }
+    }
+
+    // Flush last window
+    let total_packets_processed = extractor.total_packets_processed;
+    let undecoded_packets = extractor.undecoded_packets;
+    let parse_error_breakdown = extractor.parse_error_breakdown.clone();
+    if let Some(window) = extractor.finish() {
+        emit(window, &mut extractor, packets_read)?;
+    }
+    // A live capture has no statistics blocks, but libpcap counts its drops
+    let capture_drops = match pcapng {
+        Some(pcapng) => pcapng.finish(),
+        None => cap.stats().ok().map(|stat| CaptureDrops {
+            received: stat.received as u64,
+            interface_dropped: stat.if_dropped as u64,
+            os_dropped: stat.dropped as u64,
+        }),
+    };
+    if let Some(warning) = capture_drops.as_ref().and_then(capture_drop_warning) {
+        eprintln!("{}", warning);
+    }
+    // Count windows have no expected number to check against
+    if let (Some(first), WindowMode::Time) = (extractor.first_timestamp, options.window_mode) {
+        let (summary, warning) =
+            window_sanity_report(extractor.windows_finalized, first, extractor.last_timestamp, options.window_size);
+        if !options.quiet {
+            println!("{}", summary);
+        }
+        if let Some(warning) = warning {
+            eprintln!("{}", warning);
+        }
+    }
+    if options.benchmark {
+        eprintln!("{}", benchmark_report(packets_read, bytes_read, started.elapsed().as_secs_f64()));
+    }
+    if let Some(triage) = &triage {
+        eprintln!("{}", triage_report(triage, undecoded_packets, stderr_color()));
     }
 
     // Serialize to JSON
-    window_features.serialize(&mut writer)?;
-    println!("✅ Finished processing {} windows", window_features.len());
+    let run_summary = run_summary.map(|summary| RunSummary {
+        parse_error_breakdown: parse_error_breakdown.clone(),
+        capture_drops,
+        ..summary.finish(window_rates.clone())
+    });
+    if let Some(output_file) = json_file {
+        let mut writer = serde_json::Serializer::pretty(output_file);
+        if let Some(summary) = &run_summary {
+            summary.serialize(&mut writer)?;
+        } else if options.per_host {
+            host_features.serialize(&mut writer)?;
+        } else {
+            window_features.serialize(&mut writer)?;
+        }
+    }
+    if let Some(mut writer) = jsonl_writer {
+        if let Some(summary) = &run_summary {
+            writer.write_record(summary)?;
+        }
+        writer.finish()?;
+    }
+    if let Some(writer) = flows_writer {
+        writer.finish()?;
+    }
+    // The run is complete, so a later --resume must start over
+    if let Some(path) = &options.checkpoint_path {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    // An empty capture still produces valid (empty) output, so make it visible
+    if total_packets_processed == 0 {
+        eprintln!("⚠️  No packets were read from {}", options.pcap_file);
+        if options.fail_on_empty {
+            return Err(ExtractorError::EmptyCapture(options.pcap_file.clone()));
+        }
+    }
+    let link_name = match (link_type.get_name(), link_type.get_description()) {
+        (Ok(name), Ok(description)) => format!("{} ({})", name, description),
+        (Ok(name), Err(_)) => name,
+        _ => format!("DLT {}", link_type.0),
+    };
+    if let Some(warning) = undecoded_warning(undecoded_packets, total_packets_processed, &link_name) {
+        eprintln!("{}", warning);
+    }
+    if options.verbose {
+        if let Some(report) = parse_error_report(&parse_error_breakdown) {
+            eprintln!("{}", report);
+        }
+    }
+    if !options.quiet {
+        if let Some(summary) = bitrate_summary(window_rates) {
+            println!("{}", summary);
+        }
+        println!("✅ Finished processing {} windows", window_count);
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,1580 @@
+//! Extraction tests over synthetic packet streams.
+
+mod packets;
+
+use super::*;
+use packets::*;
+
+/// Options as parsed from the command line, with placeholder paths
+fn options(flags: &[&str]) -> Options {
+    let mut args = vec!["rust_extractor".to_string(), "in.pcap".to_string(), "out.json".to_string()];
+    args.extend(flags.iter().map(|flag| flag.to_string()));
+    parse_args(&args).unwrap()
+}
+
+/// Run a whole packet stream through the extractor
+fn extract_features<'p>(options: &Options, packets: impl IntoIterator<Item = &'p (f64, Vec<u8>)>) -> Vec<WindowFeature> {
+    extract_labeled(options, None, packets)
+}
+
+fn extract_labeled<'p>(
+    options: &Options,
+    labels: Option<&LabelMap>,
+    packets: impl IntoIterator<Item = &'p (f64, Vec<u8>)>,
+) -> Vec<WindowFeature> {
+    let mut extractor = FeatureExtractor::new(options, None, labels);
+    let mut windows: Vec<WindowFeature> = packets
+        .into_iter()
+        .filter_map(|(timestamp, frame)| extractor.process_packet(*timestamp, frame))
+        .collect();
+    windows.extend(extractor.finish());
+    windows
+}
+
+#[test]
+fn empty_stream_produces_no_windows() {
+    assert!(extract_features(&options(&[]), &[]).is_empty());
+}
+
+#[test]
+fn counts_protocol_mix() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+        (0.1, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.2, icmp_echo(CLIENT, SERVER)),
+        (0.3, arp()),
+    ];
+    let windows = extract_features(&options(&[]), &packets);
+    assert_eq!(windows.len(), 1);
+
+    let window = &windows[0];
+    assert_eq!(window.packet_count, 4);
+    assert_eq!(window.ip_packet_count, 3);
+    assert_eq!((window.tcp_count, window.udp_count, window.icmp_count, window.other_count), (1, 1, 1, 1));
+    assert_eq!(window.tcp_ratio, 0.25);
+    assert_eq!(window.unique_src_ips, 1);
+    assert_eq!(window.flow_count, 2);
+    assert_eq!(window.tcp_syn_count, 1);
+    assert_eq!(window.total_bytes, packets.iter().map(|(_, frame)| frame.len() as u64).sum::<u64>());
+}
+
+#[test]
+fn evicts_smallest_flows_beyond_max_flows() {
+    let mut packets = vec![(0.0, udp((CLIENT, 40000), (SERVER, 53), &[0; 1000]))];
+    packets.extend((1..20).map(|i| (i as f64 * 0.01, udp((CLIENT, 40000 + i), (SERVER, 53), b"query"))));
+    let window = &extract_features(&options(&["--max-flows", "10"]), &packets)[0];
+    assert!(window.flow_count <= 10);
+    assert_eq!(window.flow_count + window.evicted_flow_count, 20);
+    assert_eq!(window.top_flows[0].src_port, 40000);
+
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!((window.flow_count, window.evicted_flow_count), (20, 0));
+}
+
+#[test]
+fn counts_distinct_flows_and_sources_per_port() {
+    let other = [10, 0, 0, 3];
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+        (0.1, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+        (0.2, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.3, udp((other, 40000), (SERVER, 53), b"query")),
+        (0.4, tcp((other, 40000), (SERVER, 53), 1, SYN, &[])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    let dns = window.port_stats.iter().find(|port| port.protocol == "UDP").unwrap();
+    assert_eq!((dns.distinct_flows, dns.distinct_src_ips), (3, 2));
+
+    let window = &extract_features(&options(&["--merge-tcp-udp-ports"]), &packets)[0];
+    let dns = &window.port_stats[0];
+    assert_eq!((dns.protocol, dns.distinct_flows, dns.distinct_src_ips), ("TCP+UDP", 4, 2));
+}
+
+#[test]
+fn tracks_sctp_flows_and_chunks() {
+    let packets = vec![
+        (0.0, sctp((CLIENT, 2905), (SERVER, 2905), &[(1, 16)])),
+        (0.1, sctp((CLIENT, 2905), (SERVER, 2905), &[(0, 13), (3, 12)])),
+        (0.2, sctp((CLIENT, 2905), (SERVER, 2905), &[(0, 20), (0, 4)])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!((window.sctp_count, window.other_count), (3, 0));
+    assert_eq!(window.sctp_chunk_counts.get("data"), Some(&3));
+    assert_eq!(window.sctp_chunk_counts.get("init"), Some(&1));
+    assert_eq!(window.sctp_chunk_counts.get("sack"), Some(&1));
+
+    let flow = &window.top_flows[0];
+    assert!(flow.protocol == Protocol::Sctp);
+    assert_eq!((flow.src_port, flow.dst_port, flow.packet_count), (2905, 2905, 3));
+    assert_eq!((window.port_stats[0].protocol, window.port_stats[0].service_name.as_str()), ("SCTP", "M3UA"));
+
+    let window = &extract_features(&options(&["--only-ports", "2905"]), &packets)[0];
+    assert_eq!(window.sctp_count, 3);
+}
+
+#[test]
+fn tcp_only_counts_everything_else_as_other() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+        (0.1, udp(([192, 0, 2, 1], 40001), (SERVER, 53), b"query")),
+        (0.2, icmp_echo(CLIENT, SERVER)),
+        (0.3, arp()),
+    ];
+    let window = &extract_features(&options(&["--tcp-only"]), &packets)[0];
+    assert_eq!((window.tcp_count, window.udp_count, window.icmp_count, window.other_count), (1, 0, 0, 3));
+    assert_eq!(window.flow_count, 1);
+    assert_eq!(window.unique_src_ips, 1);
+    assert!(window.port_stats.iter().all(|port| port.protocol == "TCP"));
+}
+
+#[test]
+fn tracks_icmp_flows_by_type() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.1, icmp_echo(CLIENT, SERVER)),
+        (0.2, icmp_echo(CLIENT, SERVER)),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.flow_count, 1);
+
+    let window = &extract_features(&options(&["--icmp-flows"]), &packets)[0];
+    assert_eq!(window.flow_count, 2);
+    let icmp = window.top_flows.iter().find(|flow| flow.protocol == Protocol::Icmp).unwrap();
+    assert_eq!((icmp.src_port, icmp.dst_port, icmp.icmp_type), (0, 0, Some(8)));
+    assert_eq!(icmp.packet_count, 2);
+}
+
+#[test]
+fn splits_windows_on_window_size() {
+    let packets: Vec<_> = [0.0, 5.0, 11.0, 12.0]
+        .iter()
+        .map(|&ts| (ts, udp((CLIENT, 40000), (SERVER, 53), b"query")))
+        .collect();
+
+    let windows = extract_features(&options(&[]), &packets);
+    assert_eq!(windows.iter().map(|w| w.packet_count).collect::<Vec<_>>(), vec![2, 2]);
+    assert_eq!(windows[1].window_start, 11.0);
+
+    let windows = extract_features(&options(&["--window-size", "2"]), &packets);
+    assert_eq!(windows.iter().map(|w| w.packet_count).collect::<Vec<_>>(), vec![1, 1, 2]);
+}
+
+#[test]
+fn flags_windows_with_capture_gaps() {
+    // 10s windows: steady traffic, then a 6s pause, then a quiet tail that
+    // is closed by a packet far in the future
+    let mut packets: Vec<_> = (0..5).map(|i| (i as f64 * 0.5, udp((CLIENT, 40000), (SERVER, 53), b"query"))).collect();
+    packets.push((8.0, udp((CLIENT, 40000), (SERVER, 53), b"query")));
+    packets.push((10.5, udp((CLIENT, 40000), (SERVER, 53), b"query")));
+    packets.push((11.0, udp((CLIENT, 40000), (SERVER, 53), b"query")));
+    packets.push((30.0, udp((CLIENT, 40000), (SERVER, 53), b"query")));
+    let windows = extract_features(&options(&[]), &packets);
+    assert_eq!(windows.len(), 3);
+    assert!((windows[0].max_gap_seconds - 6.0).abs() < 1e-9);
+    assert!(windows[0].has_capture_gap);
+    // Silent from 11.0 until the window ends at 20.5
+    assert!((windows[1].max_gap_seconds - 9.5).abs() < 1e-9);
+
+    let windows = extract_features(&options(&["--gap-fraction", "0.7"]), &packets);
+    assert!(!windows[0].has_capture_gap);
+    assert!(parse_args(&["x", "in", "out", "--gap-fraction", "0"].map(String::from)).is_err());
+}
+
+#[test]
+fn splits_window_when_clock_steps_back() {
+    let packets: Vec<_> = [100.0, 101.0, 40.0, 41.0]
+        .iter()
+        .map(|&ts| (ts, udp((CLIENT, 40000), (SERVER, 53), b"query")))
+        .collect();
+    let windows = extract_features(&options(&[]), &packets);
+    assert_eq!(windows.len(), 2);
+    assert_eq!((windows[0].packet_count, windows[0].clock_adjustment_events), (2, 0));
+    assert_eq!((windows[1].packet_count, windows[1].clock_adjustment_events), (2, 1));
+    assert_eq!(windows[1].window_start, 40.0);
+    assert!(windows.iter().all(|w| w.packets_per_sec >= 0.0));
+}
+
+#[test]
+fn decodes_linux_cooked_captures() {
+    let options = options(&[]);
+    for (link_type, frame) in [
+        (Linktype::LINUX_SLL, sll_udp((CLIENT, 5000), (SERVER, 53), b"query")),
+        (Linktype::LINUX_SLL2, sll2_udp((CLIENT, 5000), (SERVER, 53), b"query")),
+    ] {
+        let mut extractor = FeatureExtractor::new(&options, None, None);
+        extractor.link_layer = LinkLayer::from_linktype(link_type);
+        extractor.process_packet(0.0, &frame);
+        let window = extractor.finish().unwrap();
+        assert_eq!((window.udp_count, window.other_count), (1, 0));
+        assert_eq!(window.top_flows[0].dst_port, 53);
+    }
+}
+
+#[test]
+fn resumes_from_window_boundary() {
+    let options = options(&["--window-size", "1", "--format", "jsonl", "--checkpoint", "run.ckpt", "--ema-alpha", "0.5"]);
+    let packets: Vec<(f64, Vec<u8>)> = (0..40)
+        .map(|i| (i as f64 * 0.3, udp((CLIENT, 5000 + i % 3), (SERVER, 53), &vec![0; i as usize])))
+        .collect();
+    let full: Vec<String> = extract_features(&options, &packets)
+        .iter()
+        .map(|w| serde_json::to_string(w).unwrap())
+        .collect();
+
+    // Interrupt after the third window closes, then start a fresh extractor
+    // from its checkpoint and replay the open window's packets
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    let mut output = Vec::new();
+    let mut resume_at = None;
+    for (index, (timestamp, frame)) in packets.iter().enumerate() {
+        if let Some(window) = extractor.process_packet(*timestamp, frame) {
+            output.push(serde_json::to_string(&window).unwrap());
+            if output.len() == 3 {
+                resume_at = extractor.boundary.take().map(|state| (index, state));
+                break;
+            }
+        }
+    }
+    let (index, state) = resume_at.unwrap();
+    let mut resumed = FeatureExtractor::new(&options, None, None);
+    resumed.restore(&state);
+    for (timestamp, frame) in &packets[index..] {
+        output.extend(resumed.process_packet(*timestamp, frame).map(|w| serde_json::to_string(&w).unwrap()));
+    }
+    output.extend(resumed.finish().map(|w| serde_json::to_string(&w).unwrap()));
+
+    assert_eq!(output, full);
+    assert_eq!(resumed.total_packets_processed, packets.len());
+}
+
+#[test]
+fn checkpoint_requires_streamed_output() {
+    assert!(parse_args(&["x", "in", "out", "--checkpoint", "run.ckpt"].map(String::from)).is_err());
+    assert!(parse_args(&["x", "in", "out", "--resume"].map(String::from)).is_err());
+    let blocks = ["x", "in", "out", "--checkpoint", "run.ckpt", "--format", "jsonl", "--block-size", "2"];
+    assert!(parse_args(&blocks.map(String::from)).is_err());
+    let split = ["x", "in", "out", "--checkpoint", "run.ckpt", "--split-windows", "--resume"];
+    assert!(parse_args(&split.map(String::from)).is_ok());
+}
+
+#[test]
+fn sanity_report_flags_missing_windows() {
+    let options = options(&[]);
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    for i in 0..30 {
+        extractor.process_packet(i as f64, &udp((CLIENT, 40000), (SERVER, 53), b"query"));
+    }
+    extractor.process_packet(100.0, &udp((CLIENT, 40000), (SERVER, 53), b"query"));
+    extractor.finish();
+    assert_eq!((extractor.first_timestamp, extractor.last_timestamp), (Some(0.0), 100.0));
+    assert_eq!(extractor.windows_finalized, 4);
+
+    let (summary, warning) = window_sanity_report(4, 0.0, 100.0, 10.0);
+    assert!(summary.contains("4 windows produced, 10 expected"), "{}", summary);
+    assert!(warning.is_some());
+    assert!(window_sanity_report(3, 0.0, 29.0, 10.0).1.is_none());
+    assert!(window_sanity_report(1, 5.0, 5.0, 10.0).1.is_none());
+}
+
+#[test]
+fn merges_windows_into_blocks() {
+    // One packet every 1.5s, so each 1s window holds one; blocks pair them up
+    let packets: Vec<_> = (0..5)
+        .map(|i| (i as f64 * 1.5, udp((CLIENT, 40000), (SERVER, 53), &vec![0; 100 * i as usize])))
+        .collect();
+    let windows = extract_features(&options(&["--window-size", "1"]), &packets);
+    let blocks = extract_features(&options(&["--window-size", "1", "--block-size", "2"]), &packets);
+    assert_eq!(windows.len(), 5);
+    assert_eq!(blocks.len(), 3);
+
+    let block = &blocks[0];
+    assert_eq!(block.window_start, windows[0].window_start);
+    assert_eq!(block.window_end, windows[1].window_end);
+    assert_eq!(block.packet_count, 2);
+    assert_eq!(block.total_bytes, windows[0].total_bytes + windows[1].total_bytes);
+    assert_eq!((block.min_packet_size, block.max_packet_size), (windows[0].min_packet_size, windows[1].max_packet_size));
+    assert!((block.packet_size_std - 50.0).abs() < 1e-9);
+    assert_eq!(block.packet_size_distribution.total(), 2);
+    assert_eq!(block.top_flows.len(), 1);
+    assert_eq!(block.top_flows[0].packet_count, 2);
+
+    // The trailing partial block holds the last window alone
+    assert_eq!(blocks[2].packet_count, 1);
+    // The flow spans both windows of a block, and still counts once
+    for block in &blocks {
+        assert_eq!(block.elephant_flow_count + block.mice_flow_count, block.flow_count);
+    }
+    assert_eq!(block.mice_flow_count, 1);
+}
+
+#[test]
+fn closes_idle_live_windows_on_the_clock() {
+    let options = options(&["--live", "--format", "jsonl", "--window-size", "10"]);
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    assert!(extractor.close_elapsed(100.0).is_none());
+    extractor.process_packet(100.0, &udp((CLIENT, 40000), (SERVER, 53), b"query"));
+    assert!(extractor.close_elapsed(110.0).is_none());
+    let window = extractor.close_elapsed(110.5).unwrap();
+    assert_eq!((window.window_start, window.window_end, window.packet_count), (100.0, 110.0, 1));
+    assert_eq!(window.max_gap_seconds, 10.0);
+    assert!(extractor.close_elapsed(200.0).is_none());
+    // The next packet opens a fresh window at its own timestamp
+    extractor.process_packet(300.0, &udp((CLIENT, 40000), (SERVER, 53), b"query"));
+    assert_eq!(extractor.finish().unwrap().window_start, 300.0);
+
+    let parse = |flags: &[&str]| {
+        parse_args(&[&["x", "eth0", "out.jsonl"][..], flags].concat().iter().map(|s| s.to_string()).collect::<Vec<_>>())
+    };
+    assert!(parse(&["--live"]).err().unwrap().contains("--format jsonl"));
+    assert!(parse(&["--live", "--format", "jsonl", "--summary-only"]).is_err());
+    // Live output is flushed every window unless asked otherwise
+    assert_eq!(parse(&["--live", "--format", "jsonl"]).unwrap().flush_interval, 1);
+    assert_eq!(parse(&["--live", "--format", "jsonl", "--flush-interval", "5"]).unwrap().flush_interval, 5);
+    assert_eq!(parse(&["--format", "jsonl"]).unwrap().flush_interval, DEFAULT_FLUSH_INTERVAL);
+}
+
+#[test]
+fn closes_count_windows_after_a_fixed_number_of_packets() {
+    let packets: Vec<_> = [0.0, 0.5, 2.0, 10.0, 30.0]
+        .iter()
+        .map(|&ts| (ts, udp((CLIENT, 40000), (SERVER, 53), b"query")))
+        .collect();
+    let windows = extract_features(&options(&["--window-mode", "count", "--window-count", "2"]), &packets);
+    assert_eq!(windows.iter().map(|w| w.packet_count).collect::<Vec<_>>(), [2, 2, 1]);
+    assert_eq!((windows[1].window_start, windows[1].window_end), (2.0, 10.0));
+    assert_eq!(windows[1].packets_per_sec, 0.25);
+    // A lone packet spans no time, so has no rate
+    assert_eq!(windows[2].packets_per_sec, 0.0);
+
+    assert!(parse_args(&["x", "in", "out", "--window-count", "2"].map(String::from)).is_err());
+    assert!(parse_args(&["x", "in", "out", "--window-mode", "bytes"].map(String::from)).is_err());
+}
+
+#[test]
+fn numbers_windows_as_finalized() {
+    let packets: Vec<_> = (0..5).map(|i| (i as f64 * 1.5, udp((CLIENT, 40000), (SERVER, 53), b"query"))).collect();
+    let windows = extract_features(&options(&["--window-size", "1"]), &packets);
+    assert_eq!(windows.iter().map(|w| w.window_index).collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+    let blocks = extract_features(&options(&["--window-size", "1", "--block-size", "2"]), &packets);
+    assert_eq!(blocks.iter().map(|w| w.window_index).collect::<Vec<_>>(), [0, 2, 4]);
+
+    let emitted = options(&["--window-index", "emitted"]);
+    assert!(emitted.window_index == WindowNumbering::Emitted);
+    assert!(parse_args(&["x", "in", "out", "--window-index", "dropped"].map(String::from)).is_err());
+}
+
+#[test]
+fn estimates_rtt_from_handshake() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 443), 100, SYN, &[])),
+        (0.05, tcp((SERVER, 443), (CLIENT, 40000), 500, syn_ack(101), &[])),
+        (0.06, tcp((CLIENT, 40000), (SERVER, 443), 101, ack(501), &[])),
+    ];
+    let windows = extract_features(&options(&[]), &packets);
+    let window = &windows[0];
+    assert_eq!(window.tcp_connection_count, 1);
+
+    let client_flow = window.top_flows.iter().find(|flow| flow.dst_port == 443).unwrap();
+    assert!((client_flow.estimated_rtt_ms - 50.0).abs() < 1e-6);
+}
+
+#[test]
+fn counts_unanswered_syns() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 80), 100, SYN, &[])),
+        (0.1, tcp((SERVER, 80), (CLIENT, 40000), 500, syn_ack(101), &[])),
+        (0.2, tcp((CLIENT, 40001), (SERVER, 22), 100, SYN, &[])),
+        (0.3, tcp((CLIENT, 40002), (SERVER, 23), 100, SYN, &[])),
+        (0.4, tcp((CLIENT, 40002), (SERVER, 23), 100, SYN, &[])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!((window.answered_syn_count, window.unanswered_syn_count), (1, 2));
+    assert!((window.unanswered_syn_ratio - 2.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn measures_time_to_first_byte() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 443), 100, SYN, &[])),
+        (0.05, tcp((SERVER, 443), (CLIENT, 40000), 500, syn_ack(101), &[])),
+        (0.06, tcp((CLIENT, 40000), (SERVER, 443), 101, ack(501), &[])),
+        (0.12, tcp((CLIENT, 40000), (SERVER, 443), 101, ack(501), b"hello")),
+        (0.2, udp((CLIENT, 5000), (SERVER, 53), b"query")),
+    ];
+    let windows = extract_features(&options(&[]), &packets);
+    let window = &windows[0];
+
+    for flow in window.top_flows.iter().filter(|flow| flow.dst_port == 443 || flow.src_port == 443) {
+        assert!((flow.time_to_first_byte_ms - 120.0).abs() < 1e-6);
+    }
+    let dns = window.top_flows.iter().find(|flow| flow.dst_port == 53).unwrap();
+    assert_eq!(dns.time_to_first_byte_ms, -1.0);
+}
+
+#[test]
+fn counts_pushed_segments_per_flow() {
+    let push = TcpFlags { psh: true, ..ack(1) };
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 80), 1, push, b"request")),
+        (0.1, tcp((CLIENT, 40000), (SERVER, 80), 8, ack(1), b"more")),
+        (0.2, tcp((CLIENT, 40000), (SERVER, 80), 12, push, b"end")),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.top_flows[0].push_count, 2);
+}
+
+#[test]
+fn reports_each_flow_and_port_share_of_window_bytes() {
+    let big = udp((CLIENT, 40000), (SERVER, 9000), &[0; 258]);
+    let small = udp((CLIENT, 40001), (SERVER, 53), &[0; 58]);
+    assert_eq!((big.len(), small.len()), (300, 100));
+    let packets = vec![(0.0, big.clone()), (0.1, big), (0.2, small)];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.top_flows[0].bytes_pct, 600.0 * 100.0 / 700.0);
+    assert_eq!(window.port_stats[1].bytes_pct, 100.0 * 100.0 / 700.0);
+
+    // Blocks recompute the share against the block's bytes
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 9000), &[0; 258])),
+        (1.5, udp((CLIENT, 40001), (SERVER, 53), &[0; 258])),
+    ];
+    let block = &extract_features(&options(&["--window-size", "1", "--block-size", "2"]), &packets)[0];
+    assert_eq!(block.top_flows.iter().map(|flow| flow.bytes_pct).collect::<Vec<_>>(), [50.0, 50.0]);
+}
+
+#[test]
+fn separates_broadcast_and_multicast_traffic() {
+    let dhcp = udp((CLIENT, 68), ([255, 255, 255, 255], 67), b"discover");
+    let v6 = |addr: &str| addr.parse::<Ipv6Addr>().unwrap().octets();
+    let packets = vec![
+        (0.0, arp()),
+        (0.1, dhcp.clone()),
+        (0.2, udp((CLIENT, 5353), ([224, 0, 0, 251], 5353), b"query")),
+        (0.3, udp6((v6("fe80::1"), 5353), (v6("ff02::fb"), 5353), b"query")),
+        (0.4, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!((window.broadcast_packet_count, window.broadcast_bytes), (2, (arp().len() + dhcp.len()) as u64));
+    assert_eq!(window.multicast_packet_count, 2);
+    assert_eq!(destination_cast(Some(&[0x01, 0x00, 0x5e, 0, 0, 0xfb]), None), Cast::Multicast);
+    assert_eq!(destination_cast(Some(&[0x02, 0, 0, 0, 0, 0x02]), Some(IpAddr::from(SERVER))), Cast::Unicast);
+}
+
+#[test]
+fn counts_repeated_ip_ids_on_udp_flows() {
+    let packets = vec![
+        (0.0, udp_with_ip_id((CLIENT, 40000), (SERVER, 5683), 7, b"get")),
+        (0.1, udp_with_ip_id((CLIENT, 40000), (SERVER, 5683), 7, b"get")),
+        (0.2, udp_with_ip_id((CLIENT, 40000), (SERVER, 5683), 8, b"get")),
+        // Same ID on another flow, and ID 0, are not repeats
+        (0.3, udp_with_ip_id((CLIENT, 40001), (SERVER, 5683), 7, b"get")),
+        (0.4, udp_with_ip_id((CLIENT, 40000), (SERVER, 5683), 0, b"get")),
+        (0.5, udp_with_ip_id((CLIENT, 40000), (SERVER, 5683), 0, b"get")),
+    ];
+    assert_eq!(extract_features(&options(&["--track-ip-ids"]), &packets)[0].repeated_ip_id_count, Some(1));
+    assert_eq!(extract_features(&options(&[]), &packets)[0].repeated_ip_id_count, None);
+}
+
+#[test]
+fn fingerprints_windows_by_traffic_shape() {
+    // Four queries in the first window, eight in the second
+    let query = udp((CLIENT, 40000), (SERVER, 53), b"query");
+    let packets: Vec<_> = (0..12)
+        .map(|i| (if i < 4 { i as f64 * 0.1 } else { DEFAULT_WINDOW_SIZE + i as f64 * 0.1 }, query.clone()))
+        .collect();
+    let windows = extract_features(&options(&["--shape-fingerprint"]), &packets);
+    let first = windows[0].shape_fingerprint.as_ref().unwrap();
+    let second = windows[1].shape_fingerprint.as_ref().unwrap();
+    assert_eq!(first.len(), PACKET_SIZE_BUCKETS.len() + 4);
+    // Twice the traffic with the same mix lands on the same unit vector
+    let dot: f64 = first.iter().zip(second).map(|(a, b)| a * b).sum();
+    assert!((dot - 1.0).abs() < 1e-9);
+    // All small UDP: the 64-byte bucket and udp_ratio, equally weighted
+    let half = 0.5_f64.sqrt();
+    assert!((first[0] - half).abs() < 1e-9 && (first[PACKET_SIZE_BUCKETS.len() + 1] - half).abs() < 1e-9);
+    assert_eq!(extract_features(&options(&[]), &packets)[0].shape_fingerprint, None);
+}
+
+#[test]
+fn flags_slow_scans_across_windows() {
+    // Four new ports per window, never more than the threshold in one window;
+    // each group starts past the previous window's end so it opens a new one
+    let packets: Vec<_> = (0..16u16)
+        .map(|i| {
+            let window = (i / 4) as f64 * (DEFAULT_WINDOW_SIZE + 0.5);
+            (window + (i % 4) as f64, tcp((CLIENT, 40000), (SERVER, 1000 + i), 1, SYN, &[]))
+        })
+        .collect();
+    let flagged = |flags: &[&str]| -> Vec<usize> {
+        extract_features(&options(flags), &packets)
+            .iter()
+            .map(|w| w.slow_scan_candidates.as_ref().unwrap().iter().map(|c| c.distinct_dst_ports).sum())
+            .collect()
+    };
+    assert_eq!(flagged(&["--slow-scan-horizon", "60", "--slow-scan-ports", "10"]), [0, 0, 12, 16]);
+    // A 20s horizon keeps only the last two windows' ports
+    assert_eq!(flagged(&["--slow-scan-horizon", "20", "--slow-scan-ports", "8"]), [0, 8, 8, 8]);
+    let windows = extract_features(&options(&["--slow-scan-horizon", "60", "--slow-scan-ports", "10"]), &packets);
+    let candidate = &windows[3].slow_scan_candidates.as_ref().unwrap()[0];
+    assert_eq!((candidate.src_ip.as_str(), candidate.window_dst_ports), ("10.0.0.1", 4));
+    assert_eq!(extract_features(&options(&[]), &packets)[0].slow_scan_candidates.as_ref().map(Vec::len), None);
+    assert!(parse_args(&["x", "in", "out", "--slow-scan-ports", "10"].map(String::from)).is_err());
+}
+
+#[test]
+fn classifies_elephant_and_mice_flows() {
+    // A 3-second bulk transfer next to a one-off DNS query
+    let mut packets: Vec<_> = (0..4)
+        .map(|i| (i as f64, tcp((CLIENT, 40000), (SERVER, 80), 1 + i * 1000, ack(1), &[0; 1000])))
+        .collect();
+    packets.push((0.5, udp((CLIENT, 40001), (SERVER, 53), b"query")));
+    let class = |flags: &[&str]| {
+        let window = extract_features(&options(flags), &packets).remove(0);
+        let classes: Vec<_> = window.top_flows.iter().map(|flow| flow.flow_class).collect();
+        (classes, window.elephant_flow_count, window.mice_flow_count)
+    };
+    assert_eq!(class(&[]), (vec!["mice", "mice"], 0, 2));
+    assert_eq!(class(&["--elephant-bytes", "4000"]), (vec!["elephant", "mice"], 1, 1));
+    assert_eq!(class(&["--elephant-bytes", "4000", "--elephant-duration", "5"]), (vec!["mice", "mice"], 0, 2));
+}
+
+#[test]
+fn counts_dhcp_messages_and_requesting_clients() {
+    let (laptop, phone) = ([0x02, 0, 0, 0, 0, 0xaa], [0x02, 0, 0, 0, 0, 0xbb]);
+    let (client, server) = (([0, 0, 0, 0], 68), ([255, 255, 255, 255], 67));
+    let packets = vec![
+        (0.0, udp(client, server, &dhcp(1, laptop, &[53, 1, 1, 255]))),
+        (0.1, udp((SERVER, 67), (CLIENT, 68), &dhcp(2, laptop, &[53, 1, 2, 255]))),
+        // Pad bytes, then the requested address
+        (0.2, udp(client, server, &dhcp(1, laptop, &[0, 0, 53, 1, 3, 50, 4, 10, 0, 0, 7, 255]))),
+        (0.3, udp((SERVER, 67), (CLIENT, 68), &dhcp(2, laptop, &[53, 1, 5, 255]))),
+        (0.4, udp(client, server, &dhcp(1, phone, &[53, 1, 1, 255]))),
+        // An option running past the end stops the walk but keeps what was read
+        (0.5, udp((SERVER, 67), (CLIENT, 68), &dhcp(2, phone, &[53, 1, 6, 12, 40, b'x']))),
+        // Too short to be DHCP at all
+        (0.6, udp(client, server, &[1, 1, 6, 0])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    let counts: Vec<_> = window.dhcp_message_counts.iter().map(|(kind, &count)| (kind.as_str(), count)).collect();
+    assert_eq!(counts, [("ack", 1), ("discover", 2), ("nak", 1), ("offer", 1), ("request", 1)]);
+
+    let clients: Vec<_> = window
+        .dhcp_clients
+        .iter()
+        .map(|c| (c.client_mac.as_str(), c.discover_count, c.request_count, c.requested_ip.as_deref()))
+        .collect();
+    assert_eq!(clients, [("02:00:00:00:00:aa", 1, 1, Some("10.0.0.7")), ("02:00:00:00:00:bb", 1, 0, None)]);
+}
+
+#[test]
+fn counts_mtu_sized_packets_per_flow() {
+    // The 1400-byte maximum arrives late; the 1396-byte packet before it
+    // still counts, the 1000-byte one never does
+    let sizes = [1000, 1396, 1400, 1400, 40];
+    let packets: Vec<_> = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, &size)| (i as f64 * 0.1, udp((CLIENT, 40000), (SERVER, 9000), &vec![0; size])))
+        .collect();
+    let flow = &extract_features(&options(&[]), &packets)[0].top_flows[0];
+    assert_eq!(flow.max_packet_size, udp((CLIENT, 40000), (SERVER, 9000), &[0; 1400]).len());
+    assert_eq!(flow.mtu_sized_packet_count, 3);
+}
+
+#[test]
+fn flags_periodic_destinations_as_beaconing() {
+    const NOISY: [u8; 4] = [10, 0, 0, 3];
+    let mut packets = Vec::new();
+    for beat in 0..6 {
+        let start = beat as f64 * 10.0;
+        // One burst per beat: its second packet must not count as an interval
+        packets.push((start, udp((CLIENT, 5000), (SERVER, 8080), b"ping")));
+        packets.push((start + 0.1, udp((CLIENT, 5000), (SERVER, 8080), b"ping")));
+    }
+    for time in [1.0, 3.0, 19.0, 22.0, 44.0, 58.0] {
+        packets.push((time, udp((CLIENT, 5001), (NOISY, 8080), b"data")));
+    }
+    packets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let windows = extract_features(&options(&["--window-size", "60"]), &packets);
+    let candidates = &windows[0].beaconing_candidates;
+    assert_eq!(candidates.len(), 1);
+    assert_eq!(candidates[0].dst_ip, "10.0.0.2");
+    assert!((candidates[0].interval_regularity - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn counts_tcp_options_on_syns() {
+    // MSS, SACK-permitted, timestamps, NOP, window scale
+    let linux = [2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, 7];
+    // MSS, two NOPs, then an unassigned kind that ends parsing
+    let unusual = [2, 4, 0x05, 0xb4, 1, 1, 30, 2];
+    let packets = vec![
+        (0.0, syn_with_options((CLIENT, 40000), (SERVER, 443), &linux)),
+        (0.1, syn_with_options((CLIENT, 40001), (SERVER, 443), &unusual)),
+        (0.2, tcp((CLIENT, 40000), (SERVER, 443), 101, ack(501), &[])),
+    ];
+    let windows = extract_features(&options(&[]), &packets);
+
+    let counts: Vec<(&str, usize)> =
+        windows[0].tcp_option_counts.iter().map(|(kind, &count)| (kind.as_str(), count)).collect();
+    assert_eq!(
+        counts,
+        [("kind_30", 1), ("mss", 2), ("nop", 2), ("sack_permitted", 1), ("timestamp", 1), ("window_scale", 1)]
+    );
+}
+
+#[test]
+fn ranks_top_flows_by_chosen_key() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 1000), (SERVER, 53), &[0; 1200])),
+        (0.0, udp((CLIENT, 2000), (SERVER, 53), b"a")),
+        (0.1, udp((CLIENT, 2000), (SERVER, 53), b"b")),
+        (0.2, udp((CLIENT, 2000), (SERVER, 53), b"c")),
+        (1.0, udp((CLIENT, 3000), (SERVER, 53), b"start")),
+        (8.0, udp((CLIENT, 3000), (SERVER, 53), b"end")),
+    ];
+    let top_port = |key: &str| {
+        let windows = extract_features(&options(&["--top-n", "1", "--top-flows-by", key]), &packets);
+        windows[0].top_flows[0].src_port
+    };
+    assert_eq!(top_port("bytes"), 1000);
+    assert_eq!(top_port("packets"), 2000);
+    assert_eq!(top_port("duration"), 3000);
+}
+
+#[test]
+fn estimates_hops_from_ttl() {
+    let packets = vec![
+        (0.0, udp_with_ttl((CLIENT, 40000), (SERVER, 53), 64, b"query")),
+        (0.1, udp_with_ttl((CLIENT, 40000), (SERVER, 53), 120, b"query")),
+        (0.2, udp_with_ttl((CLIENT, 40000), (SERVER, 53), 250, b"query")),
+        (0.3, arp()),
+    ];
+    // 0, 8 and 5 hops from initial TTLs 64, 128 and 255; ARP has no TTL
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert!((window.avg_estimated_hops - 13.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn measures_address_entropy() {
+    // One source spreading evenly over four destinations
+    let packets: Vec<(f64, Vec<u8>)> = (1..=4)
+        .map(|host| (host as f64 * 0.1, udp((CLIENT, 5000), ([10, 0, 1, host], 80), b"probe")))
+        .collect();
+    let windows = extract_features(&options(&[]), &packets);
+
+    assert_eq!(windows[0].src_ip_entropy, 0.0);
+    assert!((windows[0].dst_ip_entropy - 2.0).abs() < 1e-9);
+}
+
+#[test]
+fn ranks_hosts_by_fan_out_and_fan_in() {
+    let mut packets: Vec<_> =
+        (1..=5).map(|host| (host as f64 * 0.1, udp((CLIENT, 40000), ([10, 0, 1, host], 53), b"probe"))).collect();
+    for host in 1..=3 {
+        packets.push((1.0, udp(([10, 0, 2, host], 40000), (SERVER, 443), b"hello")));
+        // Repeat traffic to the same peer adds nothing
+        packets.push((1.1, udp(([10, 0, 2, host], 40000), (SERVER, 443), b"hello")));
+    }
+    let window = &extract_features(&options(&["--top-n", "2"]), &packets)[0];
+    let fan = |hosts: &[FanStat]| hosts.iter().map(|h| (h.ip.clone(), h.distinct_peers)).collect::<Vec<_>>();
+    assert_eq!(fan(&window.top_fanout), [("10.0.0.1".to_string(), 5), ("10.0.2.1".to_string(), 1)]);
+    assert_eq!(fan(&window.top_fanin), [("10.0.0.2".to_string(), 3), ("10.0.1.1".to_string(), 1)]);
+}
+
+#[test]
+fn strips_ethernet_fcs_before_sizing() {
+    let frame = udp((CLIENT, 40000), (SERVER, 53), b"query");
+    let mut with_fcs = frame.clone();
+    with_fcs.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+    let packets = [(0.0, with_fcs)];
+    let window = &extract_features(&options(&["--has-fcs"]), &packets)[0];
+    assert_eq!((window.total_bytes, window.max_packet_size), (frame.len() as u64, frame.len()));
+    assert_eq!(window.top_flows[0].dst_port, 53);
+    assert_eq!(extract_features(&options(&[]), &packets)[0].total_bytes, frame.len() as u64 + 4);
+}
+
+#[test]
+fn flags_connection_storms_by_syn_rate() {
+    // Five SYNs in the first two-second window, one in the second
+    let mut packets: Vec<_> =
+        (0..5u16).map(|i| (i as f64 * 0.1, tcp((CLIENT, 40000 + i), (SERVER, 80), 1, SYN, &[]))).collect();
+    packets.push((2.5, tcp((CLIENT, 41000), (SERVER, 80), 1, SYN, &[])));
+    let windows = extract_features(&options(&["--window-size", "2", "--syn-rate-threshold", "2"]), &packets);
+    assert_eq!(windows.iter().map(|w| w.syn_rate).collect::<Vec<_>>(), [2.5, 0.5]);
+    assert_eq!(windows.iter().map(|w| w.connection_storm).collect::<Vec<_>>(), [Some(true), Some(false)]);
+    assert_eq!(extract_features(&options(&["--window-size", "2"]), &packets)[0].connection_storm, None);
+}
+
+#[test]
+fn groups_top_flows_into_conversations() {
+    let third = [10, 0, 0, 3];
+    let packets = [
+        (0.0, udp((CLIENT, 1000), (SERVER, 53), b"a small query")),
+        (0.1, udp((SERVER, 53), (CLIENT, 1000), &[0; 200])),
+        (0.2, udp((third, 2000), (SERVER, 443), &[0; 150])),
+        (0.3, udp((CLIENT, 3000), (SERVER, 80), b"x")),
+    ];
+    let window = &extract_features(&options(&["--conversations"]), &packets)[0];
+    let conversations = window.conversations.as_ref().unwrap();
+    let pairs: Vec<_> =
+        conversations.iter().map(|c| (c.src_ip.as_str(), c.dst_ip.as_str(), c.flows.len())).collect();
+    // Both directions of CLIENT <-> SERVER land together, named after the reply
+    assert_eq!(pairs, [("10.0.0.2", "10.0.0.1", 3), ("10.0.0.3", "10.0.0.2", 1)]);
+    assert_eq!(conversations[0].total_bytes, conversations[0].flows.iter().map(|f| f.total_bytes).sum::<u64>());
+    assert_eq!(window.top_flows.len(), 4);
+    assert!(extract_features(&options(&[]), &packets)[0].conversations.is_none());
+}
+
+#[test]
+fn records_every_flow_for_flows_out() {
+    let options = options(&["--window-size", "1", "--top-n", "1", "--flows-out", "flows.jsonl"]);
+    let packets = [
+        (0.0, udp((CLIENT, 1000), (SERVER, 53), b"a")),
+        (0.1, udp((CLIENT, 2000), (SERVER, 53), b"b")),
+        (0.2, udp((CLIENT, 3000), (SERVER, 53), b"c")),
+        (5.0, udp((CLIENT, 4000), (SERVER, 53), b"d")),
+    ];
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    let mut windows: Vec<WindowFeature> =
+        packets.iter().filter_map(|(timestamp, frame)| extractor.process_packet(*timestamp, frame)).collect();
+    windows.extend(extractor.finish());
+
+    assert!(windows.iter().all(|w| w.top_flows.len() == 1));
+    let records: Vec<(usize, f64, u16)> = extractor
+        .flow_records
+        .iter()
+        .map(|record| (record.window_index, record.window_start, record.flow.src_port))
+        .collect();
+    assert_eq!(records, [(0, 0.0, 1000), (0, 0.0, 2000), (0, 0.0, 3000), (1, 5.0, 4000)]);
+    let line = serde_json::to_value(&extractor.flow_records[0]).unwrap();
+    assert_eq!((line["window_index"].as_u64(), line["src_port"].as_u64()), (Some(0), Some(1000)));
+}
+
+#[test]
+fn formats_ipv6_and_unmaps_ipv4_mapped_addresses() {
+    let mapped = |v4: [u8; 4]| Ipv4Addr::from(v4).to_ipv6_mapped().octets();
+    let documentation = "2001:db8:0:0:1:0:0:1".parse::<Ipv6Addr>().unwrap().octets();
+    let packets = [
+        (0.0, udp((CLIENT, 5000), (SERVER, 53), b"native")),
+        (0.1, udp6((mapped(CLIENT), 5000), (mapped(SERVER), 53), b"mapped")),
+        (0.2, udp6((documentation, 5000), (mapped(SERVER), 53), b"v6")),
+    ];
+    let sources = |flags: &[&str]| {
+        let windows = extract_features(&options(flags), &packets);
+        let mut sources: Vec<String> = windows[0].top_flows.iter().map(|flow| flow.src_ip.clone()).collect();
+        sources.sort();
+        sources
+    };
+
+    assert_eq!(sources(&[]), ["10.0.0.1", "2001:db8::1:0:0:1", "::ffff:10.0.0.1"]);
+    // The mapped flow merges with the native IPv4 one
+    assert_eq!(sources(&["--unmap-ipv4"]), ["10.0.0.1", "2001:db8::1:0:0:1"]);
+}
+
+#[test]
+fn classifies_retransmissions() {
+    let data = [0u8; 100];
+    let packets = vec![
+        (0.00, tcp((CLIENT, 40000), (SERVER, 80), 1000, ack(1), &data)),
+        (0.01, tcp((CLIENT, 40000), (SERVER, 80), 1100, ack(1), &data)),
+        // The second segment is lost; the receiver keeps acking the first
+        (0.02, tcp((SERVER, 80), (CLIENT, 40000), 1, ack(1100), &[])),
+        (0.03, tcp((SERVER, 80), (CLIENT, 40000), 1, ack(1100), &[])),
+        (0.04, tcp((SERVER, 80), (CLIENT, 40000), 1, ack(1100), &[])),
+        (0.05, tcp((SERVER, 80), (CLIENT, 40000), 1, ack(1100), &[])),
+        (0.06, tcp((CLIENT, 40000), (SERVER, 80), 1100, ack(1), &data)),
+        // No dup ACKs this time, just a long silence
+        (1.00, tcp((CLIENT, 40000), (SERVER, 80), 1100, ack(1), &data)),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.tcp_retransmissions, 2);
+    assert_eq!(window.fast_retransmit_count, 1);
+    assert_eq!(window.timeout_retransmit_count, 1);
+}
+
+#[test]
+fn reports_effective_bytes_without_retransmissions() {
+    let data = [0u8; 100];
+    let segment = |seq| tcp((CLIENT, 40000), (SERVER, 80), seq, ack(1), &data);
+    let packets = vec![(0.0, segment(1000)), (0.1, segment(1100)), (0.2, segment(1100))];
+    let flow = &extract_features(&options(&["--effective-bytes"]), &packets)[0].top_flows[0];
+    let size = segment(1000).len() as u64;
+    assert_eq!((flow.total_bytes, flow.retransmitted_bytes), (3 * size, size));
+    assert_eq!(flow.effective_bytes, Some(2 * size));
+    assert_eq!(extract_features(&options(&[]), &packets)[0].top_flows[0].effective_bytes, None);
+}
+
+#[test]
+fn counts_zero_window_segments() {
+    let packets = vec![
+        (0.0, tcp_with_window((SERVER, 80), (CLIENT, 40000), 1, ack(1), 0, &[])),
+        (0.1, tcp_with_window((SERVER, 80), (CLIENT, 40000), 1, ack(1), 0, &[])),
+        (0.2, tcp_with_window((SERVER, 80), (CLIENT, 40000), 1, TcpFlags { rst: true, ..ack(1) }, 0, &[])),
+        (0.3, tcp((SERVER, 80), (CLIENT, 40000), 1, ack(1), &[])),
+    ];
+    assert_eq!(extract_features(&options(&[]), &packets)[0].tcp_zero_window_count, 2);
+}
+
+#[test]
+fn finds_peak_concurrent_flows() {
+    let flow = |port: u16, ts: f64| (ts, udp((CLIENT, port), (SERVER, 53), b"query"));
+    // Port 1000 spans the window; 1001 and 1002 never overlap each other
+    let packets = vec![flow(1000, 0.0), flow(1001, 1.0), flow(1001, 2.0), flow(1002, 3.0), flow(1002, 4.0), flow(1000, 5.0)];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.flow_count, 3);
+    assert_eq!(window.max_concurrent_flows, 2);
+}
+
+#[test]
+fn series_counts_flow_births_per_sub_interval() {
+    // Four new flows in the first second of a 10 s window, one more at 7.5 s;
+    // repeat packets on an existing flow are not births
+    let mut packets: Vec<_> = (0..4)
+        .map(|i| (i as f64 * 0.2, udp((CLIENT, 40000 + i), (SERVER, 53), b"query")))
+        .collect();
+    packets.push((5.0, udp((CLIENT, 40000), (SERVER, 53), b"query")));
+    packets.push((7.5, udp((CLIENT, 41000), (SERVER, 53), b"query")));
+
+    let window = &extract_features(&options(&["--flow-birth-buckets", "4"]), &packets)[0];
+    assert_eq!(window.flow_birth_series, vec![4, 0, 0, 1]);
+    assert_eq!(extract_features(&options(&[]), &packets)[0].flow_birth_series.len(), 10);
+}
+
+#[test]
+fn counts_flows_without_reverse_traffic() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+        (0.1, tcp((SERVER, 80), (CLIENT, 40000), 1, syn_ack(2), &[])),
+        (0.2, tcp((CLIENT, 40001), (SERVER, 443), 1, SYN, &[])),
+        (0.3, udp((CLIENT, 40002), (SERVER, 53), b"query")),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.unidirectional_flow_count, 2);
+    assert_eq!(window.unidirectional_flow_ratio, 0.5);
+}
+
+#[test]
+fn measures_flow_size_concentration() {
+    let even: Vec<_> = (0..4).map(|i| (0.1 * i as f64, udp((CLIENT, 40000 + i), (SERVER, 53), b"query"))).collect();
+    assert_eq!(extract_features(&options(&[]), &even)[0].flow_size_gini, 0.0);
+
+    let mut skewed = even.clone();
+    skewed.push((0.5, udp((CLIENT, 40000), (SERVER, 53), &[0; 1400])));
+    let gini = extract_features(&options(&[]), &skewed)[0].flow_size_gini;
+    assert!(gini > 0.5 && gini < 1.0, "gini {}", gini);
+}
+
+#[test]
+fn splits_bytes_by_direction() {
+    let outside = [192, 0, 2, 1];
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), b"internal")),
+        (0.1, udp((CLIENT, 40000), (outside, 53), b"out")),
+        (0.2, udp((outside, 53), (CLIENT, 40000), b"in")),
+        (0.3, udp((outside, 53), ([198, 51, 100, 1], 53), b"external")),
+    ];
+    let size = |i: usize| packets[i].1.len() as u64;
+    let window = &extract_features(&options(&["--local-net", "10.0.0.0/8", "--local-net", "fd00::/8"]), &packets)[0];
+    assert_eq!(window.internal_bytes, size(0));
+    assert_eq!(window.outbound_bytes, size(1));
+    assert_eq!(window.inbound_bytes, size(2));
+    assert_eq!(window.external_bytes, size(3));
+
+    assert!(parse_args(&["x", "in", "out", "--local-net", "10.0.0.0/40"].map(String::from)).is_err());
+}
+
+#[test]
+fn reads_pcapng_statistics_and_packet_direction() {
+    // A little-endian pcapng block: type, total length, body, total length
+    let block = |block_type: u32, body: Vec<u8>| {
+        let total = (body.len() as u32 + 12).to_le_bytes();
+        [&block_type.to_le_bytes()[..], &total, &body, &total].concat()
+    };
+    let option = |code: u16, value: &[u8]| [&code.to_le_bytes()[..], &(value.len() as u16).to_le_bytes(), value].concat();
+    let packet = |flags: Option<u32>| {
+        // Interface 0, a zero timestamp, 5 captured and original bytes padded to 8
+        let mut body = [0u32, 0, 0, 5, 5].map(u32::to_le_bytes).concat();
+        body.extend_from_slice(&[0xaa; 5]);
+        body.extend_from_slice(&[0; 3]);
+        if let Some(flags) = flags {
+            body.extend(option(2, &flags.to_le_bytes()));
+            body.extend([0; 4]);
+        }
+        block(6, body)
+    };
+    let statistics = |received: u64, dropped: u64| {
+        let mut body = [0u32, 0, 0].map(u32::to_le_bytes).concat();
+        body.extend(option(4, &received.to_le_bytes()));
+        body.extend(option(5, &dropped.to_le_bytes()));
+        body.extend([0; 4]);
+        block(5, body)
+    };
+    let capture = [
+        block(0x0A0D0D0A, [&[0x4D, 0x3C, 0x2B, 0x1A][..], &[1, 0, 0, 0], &[0xff; 8]].concat()),
+        block(1, vec![1, 0, 0, 0, 0, 0, 0, 0]),
+        packet(Some(0b01)),
+        statistics(40, 1),
+        packet(Some(0b10)),
+        packet(None),
+        // Counters are cumulative, so only the last block of an interface counts
+        statistics(100, 3),
+    ]
+    .concat();
+
+    let mut pcapng = PcapngMetadata::new(capture.as_slice());
+    assert_eq!(pcapng.next_packet_direction(), Some(CaptureDirection::Inbound));
+    assert_eq!(pcapng.next_packet_direction(), Some(CaptureDirection::Outbound));
+    assert_eq!(pcapng.next_packet_direction(), None);
+    let drops = pcapng.finish().unwrap();
+    assert_eq!(drops, CaptureDrops { received: 100, interface_dropped: 3, os_dropped: 0 });
+    assert!(capture_drop_warning(&drops).unwrap().contains("lost 3 of 100 packets"));
+    assert!(capture_drop_warning(&CaptureDrops::default()).is_none());
+    // A classic pcap file has no blocks to read
+    assert!(PcapngMetadata::new(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0][..]).finish().is_none());
+
+    // With --pcapng-direction the recorded direction wins over --local-net
+    let options = options(&["--local-net", "10.0.0.0/8", "--pcapng-direction"]);
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    let frame = udp((CLIENT, 40000), (SERVER, 53), b"query");
+    extractor.capture_direction = Some(CaptureDirection::Inbound);
+    extractor.process_packet(0.0, &frame);
+    extractor.capture_direction = None;
+    extractor.process_packet(0.1, &frame);
+    let window = extractor.finish().unwrap();
+    assert_eq!(window.inbound_bytes, frame.len() as u64);
+    assert_eq!(window.internal_bytes, frame.len() as u64);
+}
+
+#[test]
+fn counts_and_optionally_excludes_port_zero() {
+    let packets = vec![
+        (0.0, tcp((CLIENT, 40000), (SERVER, 0), 1, SYN, &[])),
+        (0.1, udp((CLIENT, 0), (SERVER, 53), b"query")),
+        (0.2, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.port_zero_count, 2);
+    assert_eq!(window.flow_count, 3);
+
+    let window = &extract_features(&options(&["--exclude-port-zero"]), &packets)[0];
+    assert_eq!(window.port_zero_count, 2);
+    assert_eq!(window.flow_count, 1);
+    assert_eq!(window.port_stats.len(), 1);
+    assert_eq!((window.tcp_count, window.udp_count, window.tcp_syn_count), (1, 2, 1));
+}
+
+#[test]
+fn recognises_quic_long_headers_on_udp_443() {
+    let initial_v1 = [0xc3, 0x00, 0x00, 0x00, 0x01, 0x08];
+    let packets = vec![
+        (0.0, udp((CLIENT, 50000), (SERVER, 443), &initial_v1)),
+        (0.1, udp((SERVER, 443), (CLIENT, 50000), &[0xd0, 0x6b, 0x33, 0x43, 0xcf, 0x08])),
+        // Short header, and a long header on another port
+        (0.2, udp((CLIENT, 50000), (SERVER, 443), &[0x43, 0x01, 0x02, 0x03, 0x04])),
+        (0.3, udp((CLIENT, 50001), (SERVER, 8443), &initial_v1)),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.quic_count, 2);
+    assert!(window.quic_versions.is_none());
+
+    let window = &extract_features(&options(&["--quic-versions"]), &packets)[0];
+    let versions = window.quic_versions.as_ref().unwrap();
+    assert_eq!(versions.get("v1"), Some(&1));
+    assert_eq!(versions.get("v2"), Some(&1));
+    assert_eq!(quic_version_name(0xff00_001d), "draft-29");
+}
+
+#[test]
+fn per_host_records_each_source_with_lru_eviction() {
+    let third = [10, 0, 0, 3];
+    let options = options(&["--per-host", "--max-hosts", "2"]);
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    let packets = [
+        (0.0, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+        (0.1, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.2, udp((SERVER, 53), (CLIENT, 40001), b"answer")),
+        // Two hosts already tracked: the least recently seen (CLIENT) is evicted,
+        // then SERVER makes room when CLIENT comes back
+        (0.3, icmp_echo(third, SERVER)),
+        (0.4, udp((CLIENT, 40002), (SERVER, 123), b"ntp")),
+    ];
+    for (timestamp, frame) in &packets {
+        extractor.process_packet(*timestamp, frame);
+    }
+    extractor.finish();
+
+    let records: Vec<_> = extractor
+        .host_records
+        .iter()
+        .map(|r| (r.src_ip.as_str(), r.packet_count, r.flow_count, r.unique_dst_ports, r.evicted))
+        .collect();
+    assert_eq!(
+        records,
+        vec![
+            ("10.0.0.1", 2, 2, 2, true),
+            ("10.0.0.2", 1, 1, 1, true),
+            ("10.0.0.1", 1, 1, 1, false),
+            ("10.0.0.3", 1, 1, 0, false),
+        ]
+    );
+    assert_eq!(extractor.host_records[0].tcp_syn_count, 1);
+    assert!(parse_args(&["x", "in", "out", "--per-host", "--block-size", "3"].map(String::from)).is_err());
+}
+
+#[test]
+fn only_ports_keeps_matching_packets() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+        (0.1, udp((SERVER, 53), (CLIENT, 40000), b"answer")),
+        (0.2, tcp((CLIENT, 40001), (SERVER, 443), 1, SYN, &[])),
+        (0.3, tcp((CLIENT, 40002), (SERVER, 80), 1, SYN, &[])),
+        (0.4, icmp_echo(CLIENT, SERVER)),
+        (0.5, arp()),
+    ];
+    let window = &extract_features(&options(&["--only-ports", "53, 80"]), &packets)[0];
+    assert_eq!(window.packet_count, 3);
+    assert_eq!((window.udp_count, window.tcp_count, window.other_count), (2, 1, 0));
+
+    assert!(parse_args(&["x", "in", "out", "--only-ports", "53,http"].map(String::from)).is_err());
+}
+
+#[test]
+fn counts_unique_dst_ports_per_transport() {
+    let mut packets: Vec<_> = (1..=5u16)
+        .map(|port| (port as f64 * 0.1, tcp((CLIENT, 40000), (SERVER, port), 1, SYN, &[])))
+        .collect();
+    packets.push((0.6, udp((CLIENT, 40000), (SERVER, 1), b"probe")));
+    packets.push((0.7, udp((CLIENT, 40000), (SERVER, 53), b"query")));
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!((window.tcp_unique_dst_ports, window.udp_unique_dst_ports), (5, 2));
+    assert_eq!(window.unique_dst_ports, 6);
+}
+
+#[test]
+fn filter_ip_keeps_traffic_touching_the_hosts() {
+    let outside = [192, 0, 2, 1];
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (outside, 53), b"query")),
+        (0.1, udp((outside, 53), (CLIENT, 40000), b"answer")),
+        (0.2, udp((SERVER, 40000), (outside, 53), b"query")),
+        (0.3, icmp_echo(outside, [198, 51, 100, 7])),
+        (0.4, arp()),
+    ];
+    let window = &extract_features(&options(&["--filter-ip", "10.0.0.1"]), &packets)[0];
+    assert_eq!(window.packet_count, 2);
+
+    let filters = ["--filter-ip", "198.51.100.0/24", "--filter-ip", "2001:db8::/32"];
+    let window = &extract_features(&options(&filters), &packets)[0];
+    assert_eq!((window.packet_count, window.icmp_count), (1, 1));
+
+    let combined = ["--filter-ip", "10.0.0.0/8", "--only-ports", "53"];
+    assert_eq!(extract_features(&options(&combined), &packets)[0].packet_count, 3);
+}
+
+#[test]
+fn merges_tcp_and_udp_port_rows() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+        (0.1, tcp((CLIENT, 40001), (SERVER, 53), 1, SYN, &[])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.port_stats.len(), 2);
+
+    let window = &extract_features(&options(&["--merge-tcp-udp-ports"]), &packets)[0];
+    assert_eq!(window.port_stats.len(), 1);
+    assert_eq!(window.port_stats[0].protocol, "TCP+UDP");
+    assert_eq!(window.port_stats[0].packet_count, 2);
+}
+
+#[test]
+fn streams_packet_size_stats() {
+    // A fixed permutation of 60..=1500 so arrival order isn't sorted
+    let sizes: Vec<usize> = (0..1441).map(|i| 60 + (i * 677) % 1441).collect();
+    let mut stats = SizeStats::new();
+    for &size in &sizes {
+        stats.add(size, 100, 1000);
+    }
+    let n = sizes.len() as f64;
+    let mean = sizes.iter().sum::<usize>() as f64 / n;
+    let std = (sizes.iter().map(|&s| (s as f64 - mean).powi(2)).sum::<f64>() / n).sqrt();
+    assert_eq!((stats.min(), stats.max), (60, 1500));
+    assert!((stats.std() - std).abs() < 1e-9);
+    assert!((stats.median.value() - 780.0).abs() < 780.0 * 0.02, "{}", stats.median.value());
+    assert!((stats.p95.value() - 1428.0).abs() < 1428.0 * 0.02, "{}", stats.p95.value());
+    assert_eq!((stats.small, stats.large), (40, 500));
+    // A uniform distribution sits at the 5/9 bimodality threshold
+    assert!((stats.bimodality() - 5.0 / 9.0).abs() < 0.01, "{}", stats.bimodality());
+
+    // Control packets interleaved with full-size data packets
+    let mut mixed = SizeStats::new();
+    for i in 0..1000 {
+        mixed.add(if i % 2 == 0 { 60 + i % 7 } else { 1500 - i % 5 }, 100, 1000);
+    }
+    assert!(mixed.bimodality() > 0.9, "{}", mixed.bimodality());
+
+    // Exact while only a few samples have been seen
+    let mut few = P2Quantile::new(0.5);
+    for size in [300.0, 100.0, 200.0] {
+        few.add(size);
+    }
+    assert_eq!(few.value(), 200.0);
+    assert_eq!(SizeStats::new().min(), 0);
+}
+
+#[test]
+fn serializes_histograms_in_bucket_order() {
+    let packets: Vec<_> = (0..20)
+        .map(|i| (i as f64 * 0.1, udp((CLIENT, 40000 + i), (SERVER, 53), &vec![0; 20 * i as usize])))
+        .collect();
+    let first = serde_json::to_string(&extract_features(&options(&[]), &packets)).unwrap();
+    let second = serde_json::to_string(&extract_features(&options(&[]), &packets)).unwrap();
+    assert_eq!(first, second);
+    assert!(first.contains(r#""packet_size_distribution":{"64":2,"128":3,"256":6,"512":9,"1024":0,"1500":0}"#));
+    assert!(first.contains(r#""flow_duration_distribution":{"0-5":20,"5-10":0,"10-20":0,"20-30":0,"30+":0}"#));
+}
+
+#[test]
+fn buckets_flow_durations_by_configured_bounds() {
+    // Flows lasting 0, 0.05, 0.3 and 2 seconds
+    let mut packets = Vec::new();
+    for (port, duration) in [(40000, 0.0_f64), (40001, 0.05), (40002, 0.3), (40003, 2.0)] {
+        packets.push((0.0, udp((CLIENT, port), (SERVER, 53), b"query")));
+        packets.push((duration, udp((CLIENT, port), (SERVER, 53), b"query")));
+    }
+    packets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let window = &extract_features(&options(&["--duration-buckets", "0.05, 0.5,1"]), &packets)[0];
+    let json = serde_json::to_string(&window.flow_duration_distribution).unwrap();
+    assert_eq!(json, r#"{"0-0.05":2,"0.05-0.5":1,"0.5-1":0,"1+":1}"#);
+
+    for bad in ["", "1,1", "2,1", "0,1", "1,x"] {
+        assert!(parse_args(&["x", "in", "out", "--duration-buckets", bad].map(String::from)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn buckets_tcp_segments_by_payload_size() {
+    let (client, server) = ((CLIENT, 40000), (SERVER, 80));
+    // Ethernet pads the bare ACK to the 60-byte minimum; padding isn't payload
+    let mut padded_ack = tcp(server, client, 1, ack(2), b"");
+    padded_ack.resize(60, 0);
+    let packets = vec![
+        (0.0, tcp(client, server, 1, SYN, b"")),
+        (0.1, padded_ack),
+        (0.2, tcp(client, server, 2, ack(2), &[b'a'; 100])),
+        (0.3, tcp(server, client, 2, ack(102), &[b'b'; 1400])),
+        (0.4, udp(client, server, &[b'c'; 100])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    let json = serde_json::to_string(&window.tcp_payload_size_distribution).unwrap();
+    assert_eq!(json, r#"{"0":2,"64":0,"128":1,"256":0,"512":0,"1024":0,"1460":1}"#);
+}
+
+#[test]
+fn labels_flows_by_most_specific_range() {
+    let labels = LabelMap::from_entries([
+        ("10.0.0.0/8".to_string(), "internal".to_string()),
+        ("10.0.0.2".to_string(), "internal-server".to_string()),
+    ])
+    .unwrap();
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+        (0.1, udp(([192, 0, 2, 1], 40000), (SERVER, 53), b"query")),
+    ];
+    let window = &extract_labeled(&options(&[]), Some(&labels), &packets)[0];
+
+    let internal = window.top_flows.iter().find(|flow| flow.src_ip == "10.0.0.1").unwrap();
+    assert_eq!(internal.src_label.as_deref(), Some("internal"));
+    assert_eq!(internal.dst_label.as_deref(), Some("internal-server"));
+    let external = window.top_flows.iter().find(|flow| flow.src_ip == "192.0.2.1").unwrap();
+    assert_eq!(external.src_label, None);
+
+    assert!(LabelMap::from_entries([("10.0.0.0/33".to_string(), "bad".to_string())]).is_err());
+}
+
+#[test]
+fn buckets_ipv4_heatmap_by_octets() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), b"query")),
+        (0.1, udp(([10, 9, 0, 1], 40000), (SERVER, 53), b"query")),
+    ];
+    assert!(extract_features(&options(&[]), &packets)[0].src_octet_heatmap.is_none());
+
+    let window = &extract_features(&options(&["--heatmap"]), &packets)[0];
+    let src = window.src_octet_heatmap.as_ref().unwrap();
+    assert_eq!(src.slash8["10.0.0.0/8"].packet_count, 2);
+    assert_eq!(src.slash16.keys().collect::<Vec<_>>(), vec!["10.0.0.0/16", "10.9.0.0/16"]);
+    let dst = window.dst_octet_heatmap.as_ref().unwrap();
+    assert_eq!(dst.slash16["10.0.0.0/16"].total_bytes, window.total_bytes);
+}
+
+#[test]
+fn sampling_scales_counts() {
+    let packets: Vec<_> = (0..4)
+        .map(|i| (i as f64 * 0.1, udp((CLIENT, 40000), (SERVER, 53), b"query")))
+        .collect();
+    let window = &extract_features(&options(&["--sample", "2"]), &packets)[0];
+    assert_eq!(window.packet_count, 4);
+    assert_eq!(window.udp_count, 4);
+    assert_eq!(window.udp_ratio, 1.0);
+}
+
+#[test]
+fn seeded_probabilistic_sampling_is_reproducible() {
+    let packets: Vec<_> =
+        (0..1000).map(|i| (i as f64 * 0.001, udp((CLIENT, 40000), (SERVER, 53), b"query"))).collect();
+    let sampled = |flags: &[&str]| serde_json::to_string(&extract_features(&options(flags), &packets)).unwrap();
+    let run = sampled(&["--sample-prob", "0.25", "--seed", "7"]);
+    assert_eq!(run, sampled(&["--sample-prob", "0.25", "--seed", "7"]));
+    assert_ne!(run, sampled(&["--sample-prob", "0.25", "--seed", "8"]));
+
+    // Kept packets are scaled by 1/p, so the estimate lands near the real count
+    let window = &extract_features(&options(&["--sample-prob", "0.25", "--seed", "7"]), &packets)[0];
+    assert_eq!(window.packet_count % 4, 0);
+    assert!((800..=1200).contains(&window.packet_count), "{}", window.packet_count);
+    assert_eq!(window.udp_ratio, 1.0);
+
+    for bad in [&["--sample-prob", "0"][..], &["--sample-prob", "1.5"], &["--seed", "1"], &["--sample-prob", "0.5", "--sample", "2"]] {
+        let args: Vec<String> = ["x", "in", "out"].iter().chain(bad).map(|arg| arg.to_string()).collect();
+        assert!(parse_args(&args).is_err(), "{:?}", bad);
+    }
+}
+
+#[test]
+fn wire_bytes_add_per_frame_overhead() {
+    let packets: Vec<_> = (0..2)
+        .map(|i| (i as f64 * 0.1, udp((CLIENT, 40000), (SERVER, 53), b"query")))
+        .collect();
+    let captured = packets[0].1.len() as u64 * 2;
+
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.wire_bytes, None);
+    assert_eq!(window.bytes_per_sec, captured as f64 / DEFAULT_WINDOW_SIZE);
+
+    let window = &extract_features(&options(&["--wire-bytes"]), &packets)[0];
+    assert_eq!(window.total_bytes, captured);
+    assert_eq!(window.wire_bytes, Some(captured + 2 * 24));
+    assert_eq!(window.bytes_per_sec, (captured + 2 * 24) as f64 / DEFAULT_WINDOW_SIZE);
+
+    let window = &extract_features(&options(&["--wire-overhead", "20"]), &packets)[0];
+    assert_eq!(window.wire_bytes, Some(captured + 2 * 20));
+}
+
+#[test]
+fn splits_small_and_large_packet_ratios() {
+    // Frames carry 42 bytes of Ethernet/IPv4/UDP headers
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 53), &[0; 10])),
+        (0.1, udp((CLIENT, 40000), (SERVER, 53), &[0; 500])),
+        (0.2, udp((CLIENT, 40000), (SERVER, 53), &[0; 1200])),
+        (0.3, udp((CLIENT, 40000), (SERVER, 53), &[0; 1400])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    assert_eq!(window.small_packet_ratio, 0.25);
+    assert_eq!(window.large_packet_ratio, 0.5);
+
+    let window = &extract_features(&options(&["--small-threshold", "600", "--large-threshold", "1300"]), &packets)[0];
+    assert_eq!(window.small_packet_ratio, 0.5);
+    assert_eq!(window.large_packet_ratio, 0.25);
+
+    let args = ["x", "in", "out", "--small-threshold", "2000"].map(String::from);
+    assert!(parse_args(&args).is_err());
+}
+
+#[test]
+fn counts_jumbo_frames_above_the_mtu() {
+    // 20-byte IPv4 + 8-byte UDP headers: 1472 bytes of payload fills a 1500-byte MTU
+    let packets = vec![
+        (0.0, udp((CLIENT, 40000), (SERVER, 4789), &[0; 1472])),
+        (0.1, udp((CLIENT, 40000), (SERVER, 4789), &[0; 1473])),
+        (0.2, udp((CLIENT, 40000), (SERVER, 4789), &[0; 8000])),
+    ];
+    assert_eq!(extract_features(&options(&[]), &packets)[0].jumbo_frame_count, 2);
+    assert_eq!(extract_features(&options(&["--jumbo-threshold", "9000"]), &packets)[0].jumbo_frame_count, 0);
+}
+
+#[test]
+fn warns_when_most_packets_are_undecoded() {
+    let options = options(&[]);
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    extractor.process_packet(0.0, &arp());
+    extractor.process_packet(0.1, &[0xde, 0xad]);
+    extractor.process_packet(0.2, &udp((CLIENT, 40000), (SERVER, 53), b"query"));
+    assert_eq!(extractor.undecoded_packets, 2);
+
+    let warning = undecoded_warning(2, 3, "RAW (Raw IP)").unwrap();
+    assert!(warning.contains("RAW (Raw IP)"));
+    assert!(undecoded_warning(1, 2, "EN10MB (Ethernet)").is_none());
+    assert!(undecoded_warning(0, 0, "EN10MB (Ethernet)").is_none());
+}
+
+#[test]
+fn breaks_down_undecoded_packets_by_reason() {
+    let options = options(&[]);
+    let mut extractor = FeatureExtractor::new(&options, None, None);
+    extractor.process_packet(0.0, &arp());
+    extractor.process_packet(0.1, &[0xde, 0xad]);
+    extractor.process_packet(0.2, &[0xbe, 0xef]);
+    extractor.process_packet(0.3, &udp((CLIENT, 40000), (SERVER, 53), b"query"));
+    let breakdown = &extractor.parse_error_breakdown;
+    assert_eq!(breakdown.get("unexpected_end_of_slice"), Some(&2));
+    assert_eq!(breakdown.get("non_ip"), Some(&1));
+    assert_eq!(breakdown.values().sum::<usize>(), extractor.undecoded_packets);
+
+    let report = parse_error_report(breakdown).unwrap();
+    assert_eq!(report, "Undecoded packets: 3 (unexpected_end_of_slice 2, non_ip 1)");
+    assert!(parse_error_report(&BTreeMap::new()).is_none());
+}
+
+#[test]
+fn renders_split_window_file_names() {
+    let split = options(&["--output-template", "win_{index}_{start}.json"]);
+    let template = split.split_template.unwrap();
+    assert_eq!(template.render(3, 1700000010.5), "win_3_1700000010.5.json");
+    assert!(options(&[]).split_template.is_none());
+    assert_eq!(
+        options(&["--split-windows"]).split_template.unwrap().render(0, 20.0),
+        "window_0_20.json"
+    );
+
+    for bad in ["windows.json", "win_{idx}.json", "win_{index.json", "win_index}.json"] {
+        assert!(OutputTemplate::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn summarises_bitrate_percentiles() {
+    let rates: Vec<f64> = (1..=100).rev().map(|rate| rate as f64).collect();
+    assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0], 0.5), 2.0);
+    let summary = bitrate_summary(rates).unwrap();
+    assert!(summary.contains("over 100 windows: p50 50.0, p95 95.0, p99 99.0, max 100.0"), "{}", summary);
+    assert!(bitrate_summary(Vec::new()).is_none());
+}
+
+#[test]
+fn summarizes_the_whole_run() {
+    let packets: Vec<_> = (0..4).map(|i| (i as f64 * 1.5, udp((CLIENT, 40000), (SERVER, 53), b"query"))).collect();
+    let windows = extract_features(&options(&["--window-size", "1"]), &packets);
+    let mut summary = RunSummary::default();
+    for window in &windows {
+        summary.add(window);
+    }
+    let summary = summary.finish(windows.iter().map(|w| w.bytes_per_sec).collect());
+    assert_eq!(summary.window_count, 4);
+    assert_eq!((summary.start_timestamp, summary.end_timestamp), (windows[0].window_start, windows[3].window_end));
+    assert_eq!(summary.packet_count, 4);
+    assert_eq!(summary.udp_count, 4);
+    assert_eq!(summary.max_flow_count, 1);
+    assert_eq!(summary.bytes_per_sec_max, windows[0].bytes_per_sec);
+
+    assert!(parse_args(&["x", "in", "out", "--summary-only"].map(String::from)).unwrap().summary_only);
+    assert!(parse_args(&["x", "in", "out", "--summary-only", "--per-host"].map(String::from)).is_err());
+}
+
+#[test]
+fn writes_windows_as_feature_vectors() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.5, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    let vector = feature_vector(window);
+    let fields = serde_json::to_value(window).unwrap();
+    for (column, value) in FEATURE_VECTOR_COLUMNS.iter().zip(vector) {
+        let expected = match &fields[column] {
+            serde_json::Value::Bool(flag) => f64::from(u8::from(*flag)),
+            other => other.as_f64().unwrap_or_else(|| panic!("{} is not numeric", column)),
+        };
+        assert_eq!(value, expected, "{}", column);
+    }
+
+    let options = parse_args(&["x", "in", "out.jsonl", "--format", "vector"].map(String::from)).unwrap();
+    assert!(options.format == OutputFormat::Vector);
+    assert_eq!(feature_vector_schema_path(&options), PathBuf::from("out.jsonl.schema.json"));
+    assert!(parse_args(&["x", "in", "out", "--format", "vector", "--per-host"].map(String::from)).is_err());
+}
+
+#[test]
+fn appends_windows_to_a_sqlite_database() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.5, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    let path = std::env::temp_dir().join(format!("rust_extractor_test_{}.sqlite", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = std::fs::remove_file(path);
+    // The second run finds the tables in place and appends after the first
+    for pcap_file in ["a.pcap", "b.pcap"] {
+        SqliteWriter::open(path, pcap_file).unwrap().write_window(window).unwrap();
+    }
+
+    let connection = rusqlite::Connection::open(path).unwrap();
+    let count = |sql: &str| connection.query_row(sql, [], |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!(count("SELECT COUNT(*) FROM windows WHERE pcap_file = 'b.pcap'"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM flows JOIN windows ON windows.id = window_id"), 4);
+    assert_eq!(count("SELECT COUNT(*) FROM ports WHERE window_id = 2"), 2);
+    assert_eq!(count("SELECT SUM(total_bytes) FROM windows"), 2 * window.total_bytes as i64);
+    std::fs::remove_file(path).unwrap();
+
+    assert!(parse_args(&["x", "in", "out", "--sqlite-out", "db", "--per-host"].map(String::from)).is_err());
+}
+
+#[test]
+fn renders_terminal_summary() {
+    let packets = vec![
+        (0.0, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.1, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.2, tcp((SERVER, 80), (CLIENT, 40000), 1, SYN, &[])),
+        (0.3, arp()),
+    ];
+    let mut summary = TriageSummary::default();
+    for window in extract_features(&options(&[]), &packets) {
+        summary.add(&window);
+    }
+    let report = triage_report(&summary, 1, false);
+    assert!(report.contains("4 packets"), "{}", report);
+    assert!(report.contains(&format!("  UDP    {}{}  50.0%  2", "█".repeat(12), "░".repeat(12))), "{}", report);
+    assert!(report.lines().any(|line| line.starts_with("  10.0.0.1 ")), "{}", report);
+    assert!(report.contains("⚠ 1 undecoded packets") && !report.contains('\x1b'), "{}", report);
+    assert!(triage_report(&summary, 0, true).contains("\x1b[32mnone flagged"));
+    assert_eq!(human_bytes(1_500_000), "1.50 MB");
+}
+
+#[test]
+fn describes_every_output_field() {
+    // Every optional field switched on, so each described field can be found
+    let flags = [
+        "--wire-bytes",
+        "--track-ip-ids",
+        "--shape-fingerprint",
+        "--conversations",
+        "--syn-rate-threshold",
+        "1",
+        "--effective-bytes",
+        "--slow-scan-horizon",
+        "60",
+        "--slow-scan-ports",
+        "1",
+        "--quic-versions",
+        "--heatmap",
+        "--icmp-flows",
+        "--ema-alpha",
+        "0.5",
+    ];
+    let labels = LabelMap::from_entries([("10.0.0.0/8".to_string(), "internal".to_string())]).unwrap();
+    let packets = vec![
+        (0.0, icmp_echo(CLIENT, SERVER)),
+        (0.1, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+    ];
+    let window = &extract_labeled(&options(&flags), Some(&labels), &packets)[0];
+    let schema = serde_json::to_value(describe_schema()).unwrap();
+    let records = [
+        ("window", serde_json::to_value(window).unwrap()),
+        ("flow", serde_json::to_value(&window.top_flows[0]).unwrap()),
+        ("port", serde_json::to_value(&window.port_stats[0]).unwrap()),
+    ];
+    for (record, value) in records {
+        let described: Vec<_> = schema[record].as_array().unwrap().iter().collect();
+        let names: BTreeSet<&str> = described.iter().map(|field| field["name"].as_str().unwrap()).collect();
+        let keys: BTreeSet<&str> = value.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys.difference(&names).collect::<Vec<_>>(), Vec::<&&str>::new(), "undescribed {} fields", record);
+        for field in described {
+            let name = field["name"].as_str().unwrap();
+            assert!(field["optional"] == true || keys.contains(name), "{} field {} missing", record, name);
+        }
+    }
+}
+
+#[test]
+fn reads_capture_manifests() {
+    let text = "# nightly batch\na.pcap\n\n  caps/b day.pcapng\tout/b.json\n";
+    let entries = parse_manifest(text).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!((entries[0].input.as_str(), entries[0].output.as_deref()), ("a.pcap", None));
+    assert_eq!((entries[1].input.as_str(), entries[1].output.as_deref()), ("caps/b day.pcapng", Some("out/b.json")));
+    assert!(parse_manifest("a.pcap\tb.json\tc.json").is_err());
+
+    let options = parse_args(&["x", "--manifest", "list.txt", "out", "--jobs", "4"].map(String::from)).unwrap();
+    assert_eq!((options.manifest.as_deref(), options.jobs), (Some("list.txt"), 4));
+    assert_eq!(PathBuf::from(manifest_output("out", "caps/a.pcap", &options)), PathBuf::from("out/a_features.json"));
+    assert!(parse_args(&["x", "in", "out", "--jobs", "4"].map(String::from)).is_err());
+    assert!(parse_args(&["x", "--manifest", "list.txt", "out", "--flows-out", "flows.jsonl"].map(String::from)).is_err());
+}
+
+#[test]
+fn benchmark_needs_no_output_path() {
+    let args = ["x", "in.pcap", "--benchmark"].map(String::from);
+    assert!(parse_args(&args).unwrap().benchmark);
+    assert!(parse_args(&args[..2]).is_err());
+
+    let report = benchmark_report(2_000_000, 500_000_000, 2.0);
+    assert!(report.contains("1000000 packets/sec"), "{}", report);
+    assert!(report.contains("250.00 MB/sec"), "{}", report);
+}
+
+#[test]
+fn verbose_summarizes_each_window() {
+    let args = ["x", "in", "out", "--verbose"].map(String::from);
+    assert!(parse_args(&args).unwrap().verbose);
+    assert!(parse_args(&["x", "in", "out", "--verbose", "--quiet"].map(String::from)).is_err());
+
+    let packets = vec![
+        (0.0, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (0.5, udp((CLIENT, 40001), (SERVER, 53), b"query")),
+        (1.0, tcp((CLIENT, 40000), (SERVER, 80), 1, SYN, &[])),
+    ];
+    let window = &extract_features(&options(&[]), &packets)[0];
+    let summary = window_summary(window);
+    assert!(summary.contains("3 packets"), "{}", summary);
+    assert!(summary.contains("mostly UDP (67%)"), "{}", summary);
+}
+
+#[test]
+fn error_classes_map_to_distinct_exit_codes() {
+    let io = || std::io::Error::new(std::io::ErrorKind::PermissionDenied, "denied");
+    let errors = [
+        ExtractorError::Argument("bad".to_string()),
+        ExtractorError::PcapOpen { path: "in.pcap".to_string(), source: pcap::Error::PcapError("bad".to_string()) },
+        ExtractorError::Labels("bad".to_string()),
+        ExtractorError::OutputCreate { path: "out.json".to_string(), source: io() },
+        ExtractorError::Serialization(serde_json::from_str::<u8>("x").unwrap_err()),
+        ExtractorError::EmptyCapture("in.pcap".to_string()),
+        ExtractorError::Checkpoint("bad".to_string()),
+    ];
+    let codes: Vec<i32> = errors.iter().map(ExtractorError::exit_code).collect();
+    assert_eq!(codes, vec![2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(errors[3].to_string(), "cannot create output out.json: denied");
+    assert_eq!(ExtractorError::from(io()).exit_code(), 5);
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert!(parse_args(&args(&["rust_extractor", "in.pcap"])).is_err());
+    assert!(parse_args(&args(&["rust_extractor", "in.pcap", "out.json", "--bogus"])).is_err());
+    assert!(parse_args(&args(&["rust_extractor", "in.pcap", "out.json", "--sample", "0"])).is_err());
+    // After `--` an option-like name is a path
+    let options = parse_args(&args(&["rust_extractor", "--top-n", "3", "--", "--live.pcap", "out.json"])).unwrap();
+    assert_eq!((options.pcap_file.as_str(), options.output_path.as_str(), options.top_n), ("--live.pcap", "out.json", 3));
+    assert!(parse_args(&args(&["rust_extractor", "--", "in.pcap", "out.json", "--top-n"])).is_err());
+    // --version and the other standalone flags only count before `--`
+    assert!(has_flag(&args(&["rust_extractor", "in.pcap", "out.json", "-V"]), &["--version", "-V"]));
+    assert!(!has_flag(&args(&["rust_extractor", "--", "--version", "out.jsonl"]), &["--version", "-V"]));
+    assert_eq!(parse_args(&args(&["rust_extractor", "--", "--version", "out.jsonl"])).unwrap().pcap_file, "--version");
+    // Numeric errors name the flag and the accepted range
+    let error = |list: &[&str]| parse_args(&args(list)).err().unwrap();
+    assert_eq!(
+        error(&["rust_extractor", "in.pcap", "out.json", "--gap-fraction", "1.5"]),
+        "--gap-fraction expects a fraction in (0, 1], got '1.5'"
+    );
+    assert_eq!(
+        error(&["rust_extractor", "in.pcap", "out.json", "--window-size", "ten"]),
+        "--window-size expects seconds > 0, got 'ten'"
+    );
+    assert_eq!(
+        error(&["rust_extractor", "in.pcap", "out.json", "--sample-prob", "NaN"]),
+        "--sample-prob expects a probability in (0, 1], got 'NaN'"
+    );
+}
@@ -0,0 +1,198 @@
+//! Hand-built Ethernet frames for feeding the extractor in tests.
+
+use etherparse::{ip_number, IpHeader, Ipv4Header, PacketBuilder};
+
+pub const CLIENT: [u8; 4] = [10, 0, 0, 1];
+pub const SERVER: [u8; 4] = [10, 0, 0, 2];
+
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+/// TCP flags to set on a segment; `ack` carries the acknowledgment number
+#[derive(Default, Clone, Copy)]
+pub struct TcpFlags {
+    pub syn: bool,
+    pub fin: bool,
+    pub rst: bool,
+    pub psh: bool,
+    pub ack: Option<u32>,
+}
+
+pub const SYN: TcpFlags = TcpFlags { syn: true, fin: false, rst: false, psh: false, ack: None };
+
+pub fn ack(number: u32) -> TcpFlags {
+    TcpFlags { ack: Some(number), ..TcpFlags::default() }
+}
+
+pub fn syn_ack(number: u32) -> TcpFlags {
+    TcpFlags { syn: true, ack: Some(number), ..TcpFlags::default() }
+}
+
+pub fn tcp(src: ([u8; 4], u16), dst: ([u8; 4], u16), seq: u32, flags: TcpFlags, payload: &[u8]) -> Vec<u8> {
+    tcp_with_window(src, dst, seq, flags, 64240, payload)
+}
+
+/// A TCP segment advertising receive window `window`
+pub fn tcp_with_window(
+    src: ([u8; 4], u16),
+    dst: ([u8; 4], u16),
+    seq: u32,
+    flags: TcpFlags,
+    window: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ipv4(src.0, dst.0, 64)
+        .tcp(src.1, dst.1, seq, window);
+    if flags.syn {
+        builder = builder.syn();
+    }
+    if flags.fin {
+        builder = builder.fin();
+    }
+    if flags.rst {
+        builder = builder.rst();
+    }
+    if flags.psh {
+        builder = builder.psh();
+    }
+    if let Some(number) = flags.ack {
+        builder = builder.ack(number);
+    }
+    let mut frame = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut frame, payload).unwrap();
+    frame
+}
+
+/// A SYN carrying the raw TCP option bytes `options` (padded to 4 bytes)
+pub fn syn_with_options(src: ([u8; 4], u16), dst: ([u8; 4], u16), options: &[u8]) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ipv4(src.0, dst.0, 64)
+        .tcp(src.1, dst.1, 100, 64240)
+        .syn()
+        .options_raw(options)
+        .unwrap();
+    let mut frame = Vec::with_capacity(builder.size(0));
+    builder.write(&mut frame, &[]).unwrap();
+    frame
+}
+
+pub fn udp(src: ([u8; 4], u16), dst: ([u8; 4], u16), payload: &[u8]) -> Vec<u8> {
+    udp_with_ttl(src, dst, 64, payload)
+}
+
+/// A UDP datagram sent with IP time-to-live `ttl`
+pub fn udp_with_ttl(src: ([u8; 4], u16), dst: ([u8; 4], u16), ttl: u8, payload: &[u8]) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ipv4(src.0, dst.0, ttl)
+        .udp(src.1, dst.1);
+    let mut frame = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut frame, payload).unwrap();
+    frame
+}
+
+/// A UDP datagram whose IPv4 header carries identification `id`
+pub fn udp_with_ip_id(src: ([u8; 4], u16), dst: ([u8; 4], u16), id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut header = Ipv4Header::new(0, 64, ip_number::UDP, src.0, dst.0);
+    header.identification = id;
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ip(IpHeader::Version4(header, Default::default()))
+        .udp(src.1, dst.1);
+    let mut frame = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut frame, payload).unwrap();
+    frame
+}
+
+/// A DHCP message body with op `op`, client hardware address `mac` and the
+/// raw option bytes `options` after the magic cookie
+pub fn dhcp(op: u8, mac: [u8; 6], options: &[u8]) -> Vec<u8> {
+    // op, htype (Ethernet), hlen, hops; xid through giaddr are left zero
+    let mut message = vec![op, 1, 6, 0];
+    message.extend_from_slice(&[0; 24]);
+    message.extend_from_slice(&mac);
+    // The rest of chaddr, sname and file
+    message.extend(std::iter::repeat(0).take(10 + 64 + 128));
+    message.extend_from_slice(&[99, 130, 83, 99]);
+    message.extend_from_slice(options);
+    message
+}
+
+pub fn udp6(src: ([u8; 16], u16), dst: ([u8; 16], u16), payload: &[u8]) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ipv6(src.0, dst.0, 64)
+        .udp(src.1, dst.1);
+    let mut frame = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut frame, payload).unwrap();
+    frame
+}
+
+/// A UDP datagram as an IPv4 packet with no link-layer header
+fn ipv4_udp(src: ([u8; 4], u16), dst: ([u8; 4], u16), payload: &[u8]) -> Vec<u8> {
+    let builder = PacketBuilder::ipv4(src.0, dst.0, 64).udp(src.1, dst.1);
+    let mut packet = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut packet, payload).unwrap();
+    packet
+}
+
+/// A UDP datagram behind a Linux cooked v1 (SLL) header
+pub fn sll_udp(src: ([u8; 4], u16), dst: ([u8; 4], u16), payload: &[u8]) -> Vec<u8> {
+    // packet type (outgoing), ARPHRD_ETHER, address length, address, protocol
+    let mut frame = vec![0x00, 0x04, 0x00, 0x01, 0x00, 0x06];
+    frame.extend_from_slice(&CLIENT_MAC);
+    frame.extend_from_slice(&[0, 0, 0x08, 0x00]);
+    frame.extend(ipv4_udp(src, dst, payload));
+    frame
+}
+
+/// A UDP datagram behind a Linux cooked v2 (SLL2) header
+pub fn sll2_udp(src: ([u8; 4], u16), dst: ([u8; 4], u16), payload: &[u8]) -> Vec<u8> {
+    // protocol, reserved, interface index, ARPHRD_ETHER, packet type,
+    // address length, address
+    let mut frame = vec![0x08, 0x00, 0, 0, 0, 0, 0, 2, 0x00, 0x01, 0x04, 0x06];
+    frame.extend_from_slice(&CLIENT_MAC);
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend(ipv4_udp(src, dst, payload));
+    frame
+}
+
+/// An SCTP packet whose chunks are given as (type, value length) pairs
+pub fn sctp(src: ([u8; 4], u16), dst: ([u8; 4], u16), chunks: &[(u8, usize)]) -> Vec<u8> {
+    // Common header: ports, verification tag, checksum (left unchecked)
+    let mut sctp = Vec::new();
+    sctp.extend_from_slice(&src.1.to_be_bytes());
+    sctp.extend_from_slice(&dst.1.to_be_bytes());
+    sctp.extend_from_slice(&[0; 8]);
+    for &(chunk_type, value_len) in chunks {
+        sctp.extend_from_slice(&[chunk_type, 0]);
+        sctp.extend_from_slice(&(4 + value_len as u16).to_be_bytes());
+        sctp.extend(std::iter::repeat(0).take((value_len + 3) & !3));
+    }
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC).ipv4(src.0, dst.0, 64);
+    let mut frame = Vec::with_capacity(builder.size(sctp.len()));
+    builder.write(&mut frame, 132, &sctp).unwrap();
+    frame
+}
+
+pub fn icmp_echo(src: [u8; 4], dst: [u8; 4]) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ipv4(src, dst, 64)
+        .icmpv4_echo_request(1, 1);
+    let mut frame = Vec::with_capacity(builder.size(0));
+    builder.write(&mut frame, &[]).unwrap();
+    frame
+}
+
+/// An ARP who-has request: a non-IP frame
+pub fn arp() -> Vec<u8> {
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&[0xff; 6]);
+    frame.extend_from_slice(&CLIENT_MAC);
+    frame.extend_from_slice(&[0x08, 0x06]);
+    // htype, ptype, hlen, plen, oper
+    frame.extend_from_slice(&[0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01]);
+    frame.extend_from_slice(&CLIENT_MAC);
+    frame.extend_from_slice(&CLIENT);
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&SERVER);
+    frame
+}